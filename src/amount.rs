@@ -0,0 +1,428 @@
+use serde::{Deserialize, Serialize};
+
+/// 消費税率と端数処理
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TaxRates {
+    /// 標準税率（%）
+    pub standard: u32,
+    /// 軽減税率（%）
+    pub reduced: u32,
+    pub rounding: Rounding,
+}
+
+impl Default for TaxRates {
+    fn default() -> Self {
+        Self { standard: 10, reduced: 8, rounding: Rounding::Floor }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rounding {
+    /// 切り捨て
+    Floor,
+    /// 四捨五入
+    Round,
+    /// 切り上げ
+    Ceil,
+}
+
+/// 全角の数字・記号を半角にする。文字数は変えない
+pub fn normalize(c: char) -> char {
+    match c {
+        '０'..='９' => char::from_u32(c as u32 - '０' as u32 + '0' as u32).unwrap(),
+        '＋' => '+',
+        '－' | '−' | '‐' => '-',
+        '×' | '＊' | '✕' => '*',
+        '÷' | '／' => '/',
+        '（' => '(',
+        '）' => ')',
+        '．' => '.',
+        '，' => ',',
+        '％' => '%',
+        '　' => ' ',
+        _ => c,
+    }
+}
+
+/// 分数で計算して、税や割り算の端数を途中で失わないようにする
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Ratio {
+    num: i128,
+    den: i128,
+}
+
+impl Ratio {
+    fn new(num: i128, den: i128) -> Result<Self, anyhow::Error> {
+        if den == 0 {
+            return Err(anyhow::anyhow!("0 で割ることはできません"));
+        }
+        let g = gcd(num.abs(), den.abs()).max(1);
+        let sign = if den < 0 { -1 } else { 1 };
+        Ok(Self { num: sign * num / g, den: sign * den / g })
+    }
+
+    fn integer(n: i128) -> Self {
+        Self { num: n, den: 1 }
+    }
+
+    fn add(self, other: Self) -> Result<Self, anyhow::Error> {
+        Self::new(
+            checked(self.num.checked_mul(other.den).and_then(|a| a.checked_add(other.num.checked_mul(self.den)?)))?,
+            checked(self.den.checked_mul(other.den))?,
+        )
+    }
+
+    fn mul(self, other: Self) -> Result<Self, anyhow::Error> {
+        Self::new(
+            checked(self.num.checked_mul(other.num))?,
+            checked(self.den.checked_mul(other.den))?,
+        )
+    }
+
+    fn neg(self) -> Self {
+        Self { num: -self.num, den: self.den }
+    }
+
+    fn recip(self) -> Result<Self, anyhow::Error> {
+        Self::new(self.den, self.num)
+    }
+
+    fn round(self, rounding: Rounding) -> i128 {
+        let floor = self.num.div_euclid(self.den);
+        let rem = self.num.rem_euclid(self.den);
+        match rounding {
+            _ if rem == 0 => floor,
+            Rounding::Floor => floor,
+            Rounding::Ceil => floor + 1,
+            Rounding::Round => floor + if rem * 2 >= self.den { 1 } else { 0 },
+        }
+    }
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+fn checked(value: Option<i128>) -> Result<i128, anyhow::Error> {
+    value.ok_or_else(|| anyhow::anyhow!("金額が大きすぎます"))
+}
+
+/// 税抜き表記。長いものから順に照合する
+const TAX_SUFFIXES: [&str; 5] = ["税抜き", "税抜", "税別", "外税", "+税"];
+
+/// 式として読む文字数の上限。Discord のメッセージの長さに合わせる
+pub const MAX_INPUT_CHARS: usize = 2000;
+/// 括弧と符号の入れ子の上限。深い入れ子でスタックを使い切らないようにする
+const MAX_DEPTH: usize = 32;
+
+/// 金額の式を読む再帰下降パーサー。`chars` は [`normalize`] 済みであること
+struct Evaluator<'a> {
+    chars: &'a [char],
+    pos: usize,
+    tax: &'a TaxRates,
+    depth: usize,
+    /// 入れ子が深すぎた。途中で読み戻しても式全体をエラーにする
+    too_deep: bool,
+}
+
+impl Evaluator<'_> {
+    /// 入れ子を1段深くして `f` を読む
+    fn nested(&mut self, f: impl FnOnce(&mut Self) -> Result<Ratio, anyhow::Error>) -> Result<Ratio, anyhow::Error> {
+        if self.depth >= MAX_DEPTH {
+            self.too_deep = true;
+            return Err(anyhow::anyhow!("括弧や符号の入れ子が深すぎます"));
+        }
+        self.depth += 1;
+        let value = f(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn starts_with(&self, word: &str) -> bool {
+        word.chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+    }
+
+    /// 演算子のあとの `送料` のような注記を読み飛ばす
+    fn skip_label(&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_ascii_digit() || matches!(c, '(' | ')' | '+' | '-' | '*' | '/') {
+                break;
+            }
+            self.pos += 1;
+        }
+    }
+
+    /// expr := term (('+' | '-') label term)*
+    fn expr(&mut self) -> Result<Ratio, anyhow::Error> {
+        let mut value = self.term()?;
+        loop {
+            let save = self.pos;
+            self.skip_spaces();
+            let op = match self.peek() {
+                Some(op @ ('+' | '-')) => op,
+                _ => {
+                    self.pos = save;
+                    return Ok(value);
+                }
+            };
+            self.pos += 1;
+            self.skip_label();
+            // 演算子のあとに数がなければ、そこまでを式とみなす
+            let rhs = match self.term() {
+                Ok(rhs) => rhs,
+                Err(_) => {
+                    self.pos = save;
+                    return Ok(value);
+                }
+            };
+            value = value.add(if op == '-' { rhs.neg() } else { rhs })?;
+        }
+    }
+
+    /// term := unary (('*' | '/') label unary)*
+    fn term(&mut self) -> Result<Ratio, anyhow::Error> {
+        let mut value = self.unary()?;
+        loop {
+            let save = self.pos;
+            self.skip_spaces();
+            let op = match self.peek() {
+                Some(op @ ('*' | '/')) => op,
+                _ => {
+                    self.pos = save;
+                    return Ok(value);
+                }
+            };
+            self.pos += 1;
+            self.skip_label();
+            let rhs = match self.unary() {
+                Ok(rhs) => rhs,
+                Err(_) => {
+                    self.pos = save;
+                    return Ok(value);
+                }
+            };
+            value = value.mul(if op == '/' { rhs.recip()? } else { rhs })?;
+        }
+    }
+
+    /// unary := '-' unary | primary tax*
+    fn unary(&mut self) -> Result<Ratio, anyhow::Error> {
+        self.skip_spaces();
+        if self.peek() == Some('-') {
+            self.pos += 1;
+            return Ok(self.nested(Self::unary)?.neg());
+        }
+
+        let mut value = self.primary()?;
+        while let Some(rate) = self.tax_suffix() {
+            let taxed = value.mul(Ratio::new(100 + rate as i128, 100)?)?;
+            value = Ratio::integer(taxed.round(self.tax.rounding));
+        }
+        Ok(value)
+    }
+
+    /// primary := number | '(' expr ')'
+    fn primary(&mut self) -> Result<Ratio, anyhow::Error> {
+        self.skip_spaces();
+        if self.peek() == Some('(') {
+            self.pos += 1;
+            return self.nested(|this| {
+                let value = this.expr()?;
+                this.skip_spaces();
+                if this.peek() != Some(')') {
+                    return Err(anyhow::anyhow!("括弧が閉じられていません"));
+                }
+                this.pos += 1;
+                Ok(value)
+            });
+        }
+        self.number()
+    }
+
+    /// 桁区切りのカンマと小数点を含む数
+    fn number(&mut self) -> Result<Ratio, anyhow::Error> {
+        let start = self.pos;
+        let (mut num, mut den) = (0i128, 1i128);
+        let mut fraction = false;
+        while let Some(c) = self.peek() {
+            match c {
+                '0'..='9' => {
+                    num = checked(num.checked_mul(10).and_then(|n| n.checked_add(c as i128 - '0' as i128)))?;
+                    if fraction {
+                        den = checked(den.checked_mul(10))?;
+                    }
+                }
+                ',' if self.chars.get(self.pos + 1).is_some_and(char::is_ascii_digit) => {}
+                '.' if !fraction && self.chars.get(self.pos + 1).is_some_and(char::is_ascii_digit) => fraction = true,
+                _ => break,
+            }
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return Err(anyhow::anyhow!("金額がありません"));
+        }
+        Ratio::new(num, den)
+    }
+
+    /// `税抜`、`税抜8%`、`税抜(軽減)` などを読み、税率を返す
+    fn tax_suffix(&mut self) -> Option<u32> {
+        let save = self.pos;
+        self.skip_spaces();
+        let suffix = match TAX_SUFFIXES.iter().find(|s| self.starts_with(s)) {
+            Some(suffix) => suffix,
+            None => {
+                self.pos = save;
+                return None;
+            }
+        };
+        self.pos += suffix.chars().count();
+
+        let save = self.pos;
+        let paren = self.peek() == Some('(');
+        if paren {
+            self.pos += 1;
+        }
+        let rate = if self.starts_with("軽減") {
+            self.pos += 2;
+            Some(self.tax.reduced)
+        } else {
+            let start = self.pos;
+            while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                self.pos += 1;
+            }
+            let digits: String = self.chars[start..self.pos].iter().collect();
+            match digits.parse().ok().filter(|_| self.peek() == Some('%')) {
+                Some(rate) => {
+                    self.pos += 1;
+                    Some(rate)
+                }
+                None => None,
+            }
+        };
+        match rate {
+            Some(rate) if !paren || self.peek() == Some(')') => {
+                if paren {
+                    self.pos += 1;
+                }
+                Some(rate)
+            }
+            _ => {
+                self.pos = save;
+                Some(self.tax.standard)
+            }
+        }
+    }
+}
+
+/// `chars` の先頭から金額の式を読み、整数の金額と読んだ文字数を返す。式のあとに続く文章は残す
+pub fn evaluate(chars: &[char], tax: &TaxRates) -> Result<(i64, usize), anyhow::Error> {
+    if chars.len() > MAX_INPUT_CHARS {
+        return Err(anyhow::anyhow!("式が長すぎます"));
+    }
+    let mut evaluator = Evaluator { chars, pos: 0, tax, depth: 0, too_deep: false };
+    let value = evaluator.expr();
+    if evaluator.too_deep {
+        return Err(anyhow::anyhow!("括弧や符号の入れ子が深すぎます"));
+    }
+    let value = value?;
+    let amount = i64::try_from(value.round(tax.rounding)).map_err(|_| anyhow::anyhow!("金額が大きすぎます"))?;
+    Ok((amount, evaluator.pos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(input: &str) -> Result<(i64, usize), anyhow::Error> {
+        eval_with(input, &TaxRates::default())
+    }
+
+    fn eval_with(input: &str, tax: &TaxRates) -> Result<(i64, usize), anyhow::Error> {
+        let chars: Vec<char> = input.chars().map(normalize).collect();
+        evaluate(&chars, tax)
+    }
+
+    #[test]
+    fn multiplies_before_adding() {
+        assert_eq!(eval("1+2*3").unwrap().0, 7);
+        assert_eq!(eval("10-4/2").unwrap().0, 8);
+        assert_eq!(eval("2*3-1").unwrap().0, 5);
+    }
+
+    #[test]
+    fn parentheses_change_order() {
+        assert_eq!(eval("(1+2)*3").unwrap().0, 9);
+        assert_eq!(eval("-(100-30)").unwrap().0, -70);
+        assert!(eval("(1+2").is_err());
+    }
+
+    #[test]
+    fn reads_full_width_digits_and_operators() {
+        assert_eq!(eval("１２０×３").unwrap().0, 360);
+        assert_eq!(eval("（１，０００＋５００）÷３").unwrap().0, 500);
+        assert_eq!(eval("１０００－２００").unwrap().0, 800);
+    }
+
+    #[test]
+    fn leaves_trailing_text() {
+        let (amount, length) = eval("980 ランチ").unwrap();
+        assert_eq!((amount, length), (980, 3));
+    }
+
+    #[test]
+    fn adds_tax_for_suffixes() {
+        assert_eq!(eval("1000税抜").unwrap().0, 1100);
+        assert_eq!(eval("1000税抜8%").unwrap().0, 1080);
+        assert_eq!(eval("1000税別(軽減)").unwrap().0, 1080);
+        assert_eq!(eval("500+500外税").unwrap().0, 1050);
+    }
+
+    #[test]
+    fn rounds_tax_as_configured() {
+        assert_eq!(eval("98税抜").unwrap().0, 107);
+        let round = TaxRates { rounding: Rounding::Round, ..TaxRates::default() };
+        assert_eq!(eval_with("98税抜", &round).unwrap().0, 108);
+        assert_eq!(eval_with("94税抜", &round).unwrap().0, 103);
+        let ceil = TaxRates { rounding: Rounding::Ceil, ..TaxRates::default() };
+        assert_eq!(eval_with("91税抜", &ceil).unwrap().0, 101);
+    }
+
+    #[test]
+    fn rejects_division_by_zero() {
+        assert!(eval("100/0").is_err());
+        assert!(eval("100/(1-1)").is_err());
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        // 長さの上限には届かない深さで、深さの上限だけを確かめる
+        let depth = MAX_DEPTH + 1;
+        let too_deep = |input: String| eval(&input).unwrap_err().to_string().contains("深すぎます");
+        assert!(too_deep(format!("{}1{}", "(".repeat(depth), ")".repeat(depth))));
+        assert!(too_deep(format!("{}1", "-".repeat(depth))));
+        // 式の途中で深くなっても、そこまでを式とはみなさない
+        assert!(too_deep(format!("1+{}1", "(".repeat(depth))));
+        assert_eq!(eval(&format!("{}1{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH))).unwrap().0, 1);
+        assert_eq!(eval(&format!("{}1", "-".repeat(MAX_DEPTH))).unwrap().0, 1);
+    }
+
+    #[test]
+    fn rejects_long_input() {
+        assert!(eval(&"1".repeat(MAX_INPUT_CHARS + 1)).is_err());
+        assert!(eval(&format!("1{}", "+1".repeat(MAX_INPUT_CHARS / 2 - 1))).is_ok());
+    }
+}
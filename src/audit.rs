@@ -0,0 +1,175 @@
+use chrono::{DateTime, Utc};
+use poise::serenity_prelude::Message;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing::error;
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// 1ファイルの上限。超えたら `audit.1.jsonl` に回す
+const MAX_BYTES: u64 = 1024 * 1024;
+/// 回したファイルを何世代残すか
+const KEEP_FILES: usize = 5;
+
+/// 操作した人と、きっかけになった Discord のメッセージ（またはコマンド）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Actor {
+    pub user_id: Option<u64>,
+    pub user_name: String,
+    pub guild_id: Option<u64>,
+    pub message_id: Option<u64>,
+}
+
+impl Actor {
+    pub fn from_message(msg: &Message) -> Self {
+        Self {
+            user_id: Some(msg.author.id.get()),
+            user_name: msg.author.name.clone(),
+            guild_id: msg.guild_id.map(|id| id.get()),
+            message_id: Some(msg.id.get()),
+        }
+    }
+
+    /// 人ではなくボット自身の処理（起動時の取り込みなど）
+    pub fn system(name: &str) -> Self {
+        Self { user_name: name.to_string(), ..Default::default() }
+    }
+}
+
+/// 監査ログの1件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub time: DateTime<Utc>,
+    #[serde(flatten)]
+    pub actor: Actor,
+    /// `append`、`member.add` など
+    pub action: String,
+    /// スプレッドシートの ID や設定ファイルの名前
+    pub target: String,
+    #[serde(default)]
+    pub range: Option<String>,
+    #[serde(default)]
+    pub old: Option<Value>,
+    #[serde(default)]
+    pub new: Option<Value>,
+    /// 失敗したときのエラー。成功なら `None`
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl AuditRecord {
+    pub fn new(actor: &Actor, action: &str, target: &str) -> Self {
+        Self {
+            time: Utc::now(),
+            actor: actor.clone(),
+            action: action.to_string(),
+            target: target.to_string(),
+            range: None,
+            old: None,
+            new: None,
+            error: None,
+        }
+    }
+
+    pub fn range(mut self, range: impl Into<String>) -> Self {
+        self.range = Some(range.into());
+        self
+    }
+
+    pub fn old(mut self, old: impl Serialize) -> Self {
+        self.old = serde_json::to_value(old).ok();
+        self
+    }
+
+    pub fn new_value(mut self, new: impl Serialize) -> Self {
+        self.new = serde_json::to_value(new).ok();
+        self
+    }
+
+    pub fn result<T, E: std::fmt::Display>(mut self, result: &Result<T, E>) -> Self {
+        self.error = result.as_ref().err().map(|e| e.to_string());
+        self
+    }
+}
+
+/// 追記専用の JSONL ファイル。大きくなったら番号付きのファイルに回す
+#[derive(Debug)]
+pub struct AuditLog {
+    dir: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl AuditLog {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir, lock: Mutex::new(()) }
+    }
+
+    fn path(&self, generation: usize) -> PathBuf {
+        match generation {
+            0 => self.dir.join("audit.jsonl"),
+            n => self.dir.join(format!("audit.{}.jsonl", n)),
+        }
+    }
+
+    /// 1件書き込む。書き込めなくても元の操作は止めず、ログに残すだけにする
+    pub fn record(&self, record: AuditRecord) {
+        if let Err(e) = self.append(&record) {
+            error!("failed to write audit log: {:?} {:?}", e, record);
+        }
+    }
+
+    fn append(&self, record: &AuditRecord) -> Result<(), Error> {
+        let _lock = self.lock.lock().unwrap();
+        std::fs::create_dir_all(&self.dir)?;
+        if std::fs::metadata(self.path(0)).is_ok_and(|m| m.len() >= MAX_BYTES) {
+            self.rotate()?;
+        }
+
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut file = OpenOptions::new().create(true).append(true).open(self.path(0))?;
+        file.write_all(&line)?;
+        Ok(())
+    }
+
+    fn rotate(&self) -> Result<(), Error> {
+        // 一番古い世代は上書きして消す
+        for generation in (0..KEEP_FILES).rev() {
+            let from = self.path(generation);
+            if from.exists() {
+                std::fs::rename(&from, self.path(generation + 1))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// サーバーの記録を古い順に返す。`user` と `since` で絞り込める
+    pub fn search(&self, guild_id: u64, user_id: Option<u64>, since: Option<DateTime<Utc>>) -> Result<Vec<AuditRecord>, Error> {
+        let _lock = self.lock.lock().unwrap();
+        let mut records = Vec::new();
+        for generation in (0..=KEEP_FILES).rev() {
+            let file = match std::fs::File::open(self.path(generation)) {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for line in BufReader::new(file).lines() {
+                let record: AuditRecord = match serde_json::from_str(&line?) {
+                    Ok(record) => record,
+                    Err(_) => continue,
+                };
+                if record.actor.guild_id == Some(guild_id)
+                    && user_id.is_none_or(|id| record.actor.user_id == Some(id))
+                    && since.is_none_or(|since| record.time >= since)
+                {
+                    records.push(record);
+                }
+            }
+        }
+        Ok(records)
+    }
+}
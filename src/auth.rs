@@ -0,0 +1,241 @@
+use crate::audit::Actor;
+use crate::http_server::ApiError;
+use axum::body::{to_bytes, Body};
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, Method};
+use axum::middleware::Next;
+use axum::response::Response;
+use ring::{digest, hmac};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// リクエストの本文の上限。Discord に添付できるファイルの大きさに合わせる
+pub const MAX_BODY_BYTES: usize = 10 * 1024 * 1024;
+/// 署名の時刻とサーバーの時刻のずれをどこまで許すか（秒）
+const TIMESTAMP_TOLERANCE: i64 = 300;
+
+/// キーで使えるエンドポイントとチャンネル
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Scopes {
+    /// `/send-message` のようなパス。その下のパスも含む。`GET /expenses` のようにメソッドを限ることもできる。`*` なら全て
+    #[serde(default)]
+    pub endpoints: Vec<String>,
+    /// 投稿できるチャンネル。省略するとサーバーで許可しているチャンネル全て
+    #[serde(default)]
+    pub channels: Option<Vec<u64>>,
+}
+
+impl Scopes {
+    fn allows_endpoint(&self, method: &Method, path: &str) -> bool {
+        self.endpoints.iter().any(|endpoint| {
+            let (allowed_method, allowed_path) = match endpoint.split_once(' ') {
+                Some((method, path)) => (Some(method), path.trim()),
+                None => (None, endpoint.as_str()),
+            };
+            allowed_method.is_none_or(|m| m.eq_ignore_ascii_case(method.as_str()))
+                && (allowed_path == "*"
+                    || path == allowed_path
+                    || path.strip_prefix(allowed_path).is_some_and(|rest| rest.starts_with('/')))
+        })
+    }
+
+    pub fn allows_channel(&self, channel_id: u64) -> bool {
+        self.channels.as_ref().is_none_or(|channels| channels.contains(&channel_id))
+    }
+}
+
+/// `Authorization: Bearer <token>` で使う API キー。トークンそのものは持たず、SHA-256 だけを設定に書く
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKey {
+    pub name: String,
+    /// トークンの SHA-256（16進数）
+    pub sha256: String,
+    #[serde(default)]
+    pub scopes: Scopes,
+    /// このキーで操作する Discord のユーザー。指定するとその人の権限と記録者名を使う
+    #[serde(default)]
+    pub user_id: Option<u64>,
+}
+
+/// Webhook 用の署名の鍵。`X-Timestamp` と `X-Signature: sha256=<hex>` を付けて送ってもらう。
+/// 署名するのは `{X-Timestamp}.{メソッド}.{パス（クエリを含む）}.{本文}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookSecret {
+    pub name: String,
+    pub secret: String,
+    #[serde(default)]
+    pub scopes: Scopes,
+    #[serde(default)]
+    pub user_id: Option<u64>,
+}
+
+/// 認証できた呼び出し元。ハンドラーでチャンネルの権限を確かめるのに使う
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub name: String,
+    pub scopes: Scopes,
+    pub user_id: Option<u64>,
+}
+
+impl Principal {
+    /// 監査ログに残す操作した人。キーの名前に `api:` を付ける
+    pub fn actor(&self, guild_id: Option<u64>) -> Actor {
+        Actor { user_id: self.user_id, user_name: format!("api:{}", self.name), guild_id, message_id: None }
+    }
+}
+
+pub struct Auth {
+    keys: Vec<ApiKey>,
+    webhooks: Vec<WebhookSecret>,
+    /// 受け付けた署名と時刻。同じリクエストの再送を断る
+    seen: Mutex<HashMap<String, i64>>,
+}
+
+impl Auth {
+    pub fn new(keys: Vec<ApiKey>, webhooks: Vec<WebhookSecret>) -> Self {
+        Self { keys, webhooks, seen: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty() && self.webhooks.is_empty()
+    }
+
+    /// ヘッダーと本文から呼び出し元を確かめる。`path` はクエリ文字列を含む
+    pub fn authenticate(&self, method: &Method, path: &str, headers: &HeaderMap, body: &[u8], now: i64) -> Result<Principal, ApiError> {
+        if let Some(authorization) = header(headers, "authorization") {
+            let token = authorization
+                .strip_prefix("Bearer ")
+                .ok_or_else(|| ApiError::unauthorized("Authorization ヘッダーは Bearer 形式で指定してください"))?;
+            let hash = hex(digest::digest(&digest::SHA256, token.trim().as_bytes()).as_ref());
+            return self
+                .keys
+                .iter()
+                .find(|key| constant_time_eq(key.sha256.to_lowercase().as_bytes(), hash.as_bytes()))
+                .map(|key| Principal { name: key.name.clone(), scopes: key.scopes.clone(), user_id: key.user_id })
+                .ok_or_else(|| ApiError::unauthorized("API キーが正しくありません"));
+        }
+
+        if let Some(signature) = header(headers, "x-signature") {
+            let timestamp: i64 = header(headers, "x-timestamp")
+                .and_then(|t| t.parse().ok())
+                .ok_or_else(|| ApiError::unauthorized("X-Timestamp ヘッダーがありません"))?;
+            if (now - timestamp).abs() > TIMESTAMP_TOLERANCE {
+                return Err(ApiError::unauthorized("X-Timestamp が古すぎるか、新しすぎます"));
+            }
+            let tag = signature
+                .strip_prefix("sha256=")
+                .and_then(unhex)
+                .ok_or_else(|| ApiError::unauthorized("X-Signature は sha256=<16進数> の形式で指定してください"))?;
+
+            // 署名の対象は `{timestamp}.{メソッド}.{パス}.{本文}`。別のエンドポイントに送り直されても通らないようにする
+            let mut message = format!("{}.{}.{}.", timestamp, method, path).into_bytes();
+            message.extend_from_slice(body);
+            let webhook = self
+                .webhooks
+                .iter()
+                .find(|w| hmac::verify(&hmac::Key::new(hmac::HMAC_SHA256, w.secret.as_bytes()), &message, &tag).is_ok())
+                .ok_or_else(|| ApiError::unauthorized("署名が正しくありません"))?;
+
+            let mut seen = self.seen.lock().unwrap();
+            seen.retain(|_, t| (now - *t).abs() <= TIMESTAMP_TOLERANCE);
+            // 16進数の大文字と小文字を変えただけの再送も断れるよう、復号した署名で覚える
+            if seen.insert(hex(&tag), timestamp).is_some() {
+                return Err(ApiError::unauthorized("同じリクエストがすでに送られています"));
+            }
+            return Ok(Principal { name: webhook.name.clone(), scopes: webhook.scopes.clone(), user_id: webhook.user_id });
+        }
+
+        Err(ApiError::unauthorized("Authorization ヘッダーか X-Signature ヘッダーが必要です"))
+    }
+}
+
+/// 認証とエンドポイントの権限を確かめ、呼び出し元をリクエストに付ける
+pub async fn require(State(auth): State<Arc<Auth>>, request: Request, next: Next) -> Result<Response, ApiError> {
+    let (mut parts, body) = request.into_parts();
+    // 署名の確認に本文が要るので、いったん読み切る
+    let body = to_bytes(body, MAX_BODY_BYTES)
+        .await
+        .map_err(|_| ApiError::bad_request("リクエストの本文が大きすぎます"))?;
+
+    let signed_path = parts.uri.path_and_query().map_or("/", |p| p.as_str());
+    let principal = auth.authenticate(&parts.method, signed_path, &parts.headers, &body, chrono::Utc::now().timestamp())?;
+    let path = parts.uri.path().to_string();
+    if !principal.scopes.allows_endpoint(&parts.method, &path) {
+        return Err(ApiError::forbidden(format!("このキーでは {} {} を使えません", parts.method, path)));
+    }
+
+    parts.extensions.insert(principal);
+    Ok(next.run(Request::from_parts(parts, Body::from(body))).await)
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_760_000_000;
+
+    fn auth() -> Auth {
+        let webhook = WebhookSecret { name: "form".to_string(), secret: "secret".to_string(), scopes: Scopes::default(), user_id: None };
+        Auth::new(Vec::new(), vec![webhook])
+    }
+
+    fn signed(method: &str, path: &str, body: &[u8]) -> String {
+        let mut message = format!("{}.{}.{}.", NOW, method, path).into_bytes();
+        message.extend_from_slice(body);
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"secret");
+        hex(hmac::sign(&key, &message).as_ref())
+    }
+
+    fn headers(signature: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-timestamp", NOW.to_string().parse().unwrap());
+        headers.insert("x-signature", format!("sha256={}", signature).parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn accepts_signature_over_method_and_path() {
+        let signature = signed("POST", "/expenses?dry_run=1", b"{}");
+        let principal = auth()
+            .authenticate(&Method::POST, "/expenses?dry_run=1", &headers(&signature), b"{}", NOW)
+            .unwrap();
+        assert_eq!(principal.name, "form");
+    }
+
+    #[test]
+    fn rejects_signature_for_another_endpoint() {
+        let signature = signed("POST", "/expenses", b"{}");
+        let auth = auth();
+        assert!(auth.authenticate(&Method::POST, "/send-message", &headers(&signature), b"{}", NOW).is_err());
+        assert!(auth.authenticate(&Method::DELETE, "/expenses", &headers(&signature), b"{}", NOW).is_err());
+    }
+
+    #[test]
+    fn rejects_replay_with_uppercase_signature() {
+        let signature = signed("POST", "/expenses", b"{}");
+        let auth = auth();
+        assert!(auth.authenticate(&Method::POST, "/expenses", &headers(&signature), b"{}", NOW).is_ok());
+        let upper = signature.to_uppercase();
+        assert!(auth.authenticate(&Method::POST, "/expenses", &headers(&upper), b"{}", NOW).is_err());
+    }
+}
@@ -0,0 +1,232 @@
+use crate::ledger::{format_yen, Entry, Month};
+use crate::summary;
+use chrono::Datelike;
+use plotters::prelude::*;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::OnceLock;
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// リポジトリに同梱している日本語フォント
+pub const DEFAULT_FONT_PATH: &str = "assets/fonts/NotoSansJP-Regular.ttf";
+
+const WIDTH: u32 = 960;
+const HEIGHT: u32 = 640;
+const FONT: &str = "sans-serif";
+/// 円グラフに個別に出す分類の数。残りは「その他」にまとめる
+const PIE_SLICES: usize = 8;
+
+const PALETTE: [RGBColor; 9] = [
+    RGBColor(0x4e, 0x79, 0xa7),
+    RGBColor(0xf2, 0x8e, 0x2b),
+    RGBColor(0xe1, 0x57, 0x59),
+    RGBColor(0x76, 0xb7, 0xb2),
+    RGBColor(0x59, 0xa1, 0x4f),
+    RGBColor(0xed, 0xc9, 0x48),
+    RGBColor(0xb0, 0x7a, 0xa1),
+    RGBColor(0xff, 0x9d, 0xa7),
+    RGBColor(0xba, 0xb0, 0xac),
+];
+
+/// フォントを登録できたか。失敗は覚えず、次の呼び出しで読み込み直す
+static FONT_LOADED: OnceLock<()> = OnceLock::new();
+
+/// フォントを読み込んで plotters に登録する。登録できたあとは何もしない
+pub fn load_font(path: &Path) -> Result<(), Error> {
+    if FONT_LOADED.get().is_some() {
+        return Ok(());
+    }
+    let bytes = std::fs::read(path).map_err(|e| format!("フォント {} を読み込めません: {}", path.display(), e))?;
+    // plotters は 'static なバイト列を要求するので、プロセス終了まで保持する
+    let bytes: &'static [u8] = Box::leak(bytes.into_boxed_slice());
+    plotters::style::register_font(FONT, FontStyle::Normal, bytes)
+        .map_err(|_| format!("フォント {} を解釈できません", path.display()))?;
+    let _ = FONT_LOADED.set(());
+    Ok(())
+}
+
+/// 分類別の円グラフ。返金は同じ分類から差し引く
+pub fn category_pie(month: Month, entries: &[Entry]) -> Result<Vec<u8>, Error> {
+    let mut totals = summary::category_totals(entries);
+    if totals.len() > PIE_SLICES {
+        let others: i64 = totals.drain(PIE_SLICES..).map(|(_, amount)| amount).sum();
+        totals.push(("その他".to_string(), others));
+    }
+    let total: i64 = totals.iter().map(|(_, amount)| amount).sum();
+
+    render(|root| {
+        let title = format!("{} 分類別（合計 {}）", month, format_yen(total));
+        let area = root.titled(&title, (FONT, 32))?;
+        let (width, height) = area.dim_in_pixel();
+        let center = (width as i32 / 2, height as i32 / 2);
+        if totals.is_empty() {
+            area.draw(&Text::new("記録がありません", (center.0 - 100, center.1), (FONT, 28)))?;
+            return Ok(());
+        }
+
+        let sizes: Vec<f64> = totals.iter().map(|(_, amount)| *amount as f64).collect();
+        let labels: Vec<String> = totals
+            .iter()
+            .map(|(category, amount)| format!("{} {}", category, format_yen(*amount)))
+            .collect();
+        let colors: Vec<RGBColor> = (0..totals.len()).map(|i| PALETTE[i % PALETTE.len()]).collect();
+        let radius = 200.0;
+
+        let mut pie = Pie::new(&center, &radius, &sizes, &colors, &labels);
+        pie.start_angle(-90.0);
+        pie.label_style((FONT, 20).into_font().color(&BLACK));
+        pie.label_offset(30.0);
+        pie.percentages((FONT, 16).into_font().color(&WHITE));
+        area.draw(&pie)?;
+        Ok(())
+    })
+}
+
+/// 日ごとの累計支出と予算
+pub fn cumulative(month: Month, entries: &[Entry], budget: Option<i64>) -> Result<Vec<u8>, Error> {
+    let days = month.days();
+    let mut daily = vec![0i64; days as usize + 1];
+    for entry in entries.iter().filter(|e| e.is_expense()) {
+        if let Some(date) = entry.date.filter(|d| Month::of(*d) == month) {
+            daily[date.day() as usize] += entry.amount;
+        }
+    }
+
+    // 今月なら今日まで、過去の月なら月末まで線を引く
+    let today = chrono::Local::now().date_naive();
+    let last_day = if Month::of(today) == month { today.day() } else { days };
+    let mut points = vec![(0u32, 0i64)];
+    let mut sum = 0;
+    for day in 1..=last_day {
+        sum += daily[day as usize];
+        points.push((day, sum));
+    }
+
+    let y_max = points.iter().map(|(_, sum)| *sum).max().unwrap_or(0).max(budget.unwrap_or(0)).max(1);
+    let y_max = y_max + y_max / 10;
+
+    render(|root| {
+        let mut chart = ChartBuilder::on(root)
+            .caption(format!("{} 累計支出（{}）", month, format_yen(sum)), (FONT, 32))
+            .margin(20)
+            .x_label_area_size(40)
+            .y_label_area_size(100)
+            .build_cartesian_2d(0u32..days, 0i64..y_max)?;
+
+        chart
+            .configure_mesh()
+            .x_desc("日")
+            .x_labels(days as usize / 2)
+            .y_label_formatter(&|v| format_yen(*v))
+            .label_style((FONT, 16))
+            .axis_desc_style((FONT, 18))
+            .draw()?;
+
+        chart
+            .draw_series(LineSeries::new(points.iter().copied(), PALETTE[0].stroke_width(3)))?
+            .label("累計")
+            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], PALETTE[0].stroke_width(3)));
+
+        if let Some(budget) = budget {
+            chart
+                .draw_series(LineSeries::new(vec![(0, budget), (days, budget)], PALETTE[2].stroke_width(2)))?
+                .label(format!("予算 {}", format_yen(budget)))
+                .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], PALETTE[2].stroke_width(2)));
+            chart
+                .draw_series(LineSeries::new(vec![(0, 0), (days, budget)], PALETTE[8].stroke_width(1)))?
+                .label("予算ペース")
+                .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], PALETTE[8].stroke_width(1)));
+        }
+
+        chart
+            .configure_series_labels()
+            .position(SeriesLabelPosition::UpperLeft)
+            .label_font((FONT, 18))
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()?;
+        Ok(())
+    })
+}
+
+/// 月ごとの支出合計の棒グラフ
+pub fn trend(totals: &[(Month, i64)]) -> Result<Vec<u8>, Error> {
+    let y_max = totals.iter().map(|(_, amount)| *amount).max().unwrap_or(0).max(1);
+    let y_max = y_max + y_max / 10;
+    let (first, last) = match (totals.first(), totals.last()) {
+        (Some(first), Some(last)) => (first.0, last.0),
+        _ => return Err("表示する月がありません".into()),
+    };
+
+    render(|root| {
+        let mut chart = ChartBuilder::on(root)
+            .caption(format!("{} 〜 {} の推移", first, last), (FONT, 32))
+            .margin(20)
+            .x_label_area_size(40)
+            .y_label_area_size(100)
+            .build_cartesian_2d((0..totals.len() as i32).into_segmented(), 0i64..y_max)?;
+
+        chart
+            .configure_mesh()
+            .disable_x_mesh()
+            .x_labels(totals.len())
+            .x_label_formatter(&|v| match v {
+                SegmentValue::CenterOf(i) => totals
+                    .get(*i as usize)
+                    .map(|(month, _)| format!("{}月", month.month))
+                    .unwrap_or_default(),
+                _ => String::new(),
+            })
+            .y_label_formatter(&|v| format_yen(*v))
+            .label_style((FONT, 16))
+            .draw()?;
+
+        chart.draw_series(
+            Histogram::vertical(&chart)
+                .style(PALETTE[0].filled())
+                .margin(8)
+                .data(totals.iter().enumerate().map(|(i, (_, amount))| (i as i32, (*amount).max(0)))),
+        )?;
+        Ok(())
+    })
+}
+
+/// 白背景の描画領域を用意して `draw` を呼び、PNG にエンコードする
+fn render<F>(draw: F) -> Result<Vec<u8>, Error>
+where
+    F: FnOnce(&DrawingArea<BitMapBackend, plotters::coord::Shift>) -> Result<(), Error>,
+{
+    let mut buffer = vec![0u8; (WIDTH * HEIGHT * 3) as usize];
+    {
+        let root = BitMapBackend::with_buffer(&mut buffer, (WIDTH, HEIGHT)).into_drawing_area();
+        root.fill(&WHITE)?;
+        draw(&root)?;
+        root.present()?;
+    }
+
+    let image = image::RgbImage::from_raw(WIDTH, HEIGHT, buffer).ok_or("画像バッファの大きさが不正です")?;
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_font_after_failure() {
+        let dir = std::env::temp_dir().join(format!("hiratakebot-chart-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("font.ttf");
+        let _ = std::fs::remove_file(&path);
+
+        let missing = load_font(&path).unwrap_err().to_string();
+        assert!(missing.contains("読み込めません"), "{}", missing);
+        // 置いたあとは読み込み直す（前回の失敗を返さない）
+        std::fs::write(&path, b"not a font").unwrap();
+        let invalid = load_font(&path).unwrap_err().to_string();
+        assert!(invalid.contains("解釈できません"), "{}", invalid);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::statement::parse_japanese_date;
+use crate::{Context, Error};
+use chrono::{Local, TimeZone};
+use poise::serenity_prelude as serenity;
+use poise::CreateReply;
+
+/// 一度に表示する件数。表示しきれない分があれば全件をファイルで添付する
+const SHOWN: usize = 20;
+
+/// Discord のメッセージに載せられる文字数
+const MESSAGE_LIMIT: usize = 2000;
+
+/// 家計簿や設定の変更履歴を表示します
+#[poise::command(slash_command, guild_only, check = "super::admin")]
+pub async fn audit(
+    ctx: Context<'_>,
+    #[description = "操作した人で絞り込む"] user: Option<serenity::User>,
+    #[description = "この日以降（例: 2026-09-01）"] since: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("サーバーの中で実行してください")?;
+    let since = match since {
+        Some(since) => {
+            let date = parse_japanese_date(&since).ok_or_else(|| format!("日付が読めません: {}", since))?;
+            let start = Local
+                .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+                .earliest()
+                .ok_or("日付が読めません")?;
+            Some(start.to_utc())
+        }
+        None => None,
+    };
+
+    ctx.defer_ephemeral().await?;
+    let records = ctx.data().audit.search(guild_id.get(), user.map(|u| u.id.get()), since)?;
+    if records.is_empty() {
+        ctx.say("該当する記録はありません").await?;
+        return Ok(());
+    }
+
+    let lines: Vec<String> = records
+        .iter()
+        .rev()
+        .take(SHOWN)
+        .map(|r| {
+            let status = match &r.error {
+                Some(e) => format!("失敗: {}", e),
+                None => "成功".to_string(),
+            };
+            format!(
+                "- {} {} `{}` {} {}（{}）",
+                r.time.with_timezone(&Local).format("%Y/%m/%d %H:%M"),
+                r.actor.user_name,
+                r.action,
+                r.target,
+                r.range.as_deref().unwrap_or(""),
+                status
+            )
+        })
+        .collect();
+
+    let (content, shown) = fit_lines(&lines, records.len());
+    let mut reply = CreateReply::default().content(content).ephemeral(true);
+    if shown < records.len() {
+        let mut jsonl = Vec::new();
+        for record in &records {
+            jsonl.extend(serde_json::to_vec(record)?);
+            jsonl.push(b'\n');
+        }
+        reply = reply.attachment(serenity::CreateAttachment::bytes(jsonl, "audit.jsonl"));
+    }
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// メッセージの文字数に収まるまで古い行を落とし、本文と表示した件数を返す
+fn fit_lines(lines: &[String], total: usize) -> (String, usize) {
+    let mut shown = lines.len();
+    loop {
+        let content = format!("新しい順に {} 件 / 全 {} 件\n{}", shown, total, lines[..shown].join("\n"));
+        if shown == 0 || content.chars().count() <= MESSAGE_LIMIT {
+            return (content, shown);
+        }
+        shown -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_short_logs_whole() {
+        let lines = vec!["- a".to_string(), "- b".to_string()];
+        assert_eq!(fit_lines(&lines, 2), ("新しい順に 2 件 / 全 2 件\n- a\n- b".to_string(), 2));
+    }
+
+    #[test]
+    fn drops_old_lines_to_fit_the_message_limit() {
+        let lines: Vec<String> = (0..SHOWN).map(|i| format!("- {} {}", i, "あ".repeat(200))).collect();
+        let (content, shown) = fit_lines(&lines, 30);
+        assert!(content.chars().count() <= MESSAGE_LIMIT);
+        assert!(shown < SHOWN);
+        assert!(content.starts_with(&format!("新しい順に {} 件 / 全 30 件\n- 0 ", shown)));
+        assert!(!content.contains(&format!("- {} ", shown)));
+    }
+}
@@ -0,0 +1,58 @@
+use crate::ledger::{format_yen, Entry, Month};
+use crate::{Context, Error};
+
+/// カードごとの今の請求期間の利用額を表示します
+#[poise::command(slash_command, guild_only, check = "super::viewer")]
+pub async fn cards(ctx: Context<'_>) -> Result<(), Error> {
+    let cards: Vec<_> = ctx
+        .data()
+        .config
+        .payment_methods
+        .iter()
+        .filter(|m| m.closing_day.is_some())
+        .collect();
+    if cards.is_empty() {
+        ctx.say("締め日が設定されたカードがありません").await?;
+        return Ok(());
+    }
+
+    ctx.defer().await?;
+    let book = super::book(ctx).await?;
+    let today = book.today();
+    // 請求期間が次の月にかかると、まだシートがないことがある。同じ月は一度だけ読む
+    let cycles: Vec<_> = cards.iter().map(|card| card.billing_cycle(today).unwrap()).collect();
+    let sheets = book.sheet_names().await?;
+    let mut entries: Vec<Entry> = Vec::new();
+    let first = cycles.iter().map(|(start, _)| Month::of(*start)).min().unwrap();
+    let last = cycles.iter().map(|(_, end)| Month::of(*end)).max().unwrap();
+    let mut month = first;
+    while month <= last {
+        entries.extend(book.read_month_if_exists(month, &sheets).await?);
+        month = month.next();
+    }
+
+    let mut lines = Vec::new();
+    for (card, (start, end)) in cards.into_iter().zip(cycles) {
+        let total: i64 = entries
+            .iter()
+            .filter(|e| e.is_expense() && e.payment == card.name)
+            .filter(|e| e.date.is_some_and(|d| start <= d && d <= end))
+            .map(|e| e.amount)
+            .sum();
+
+        let mut line = format!(
+            "**{}**: {}（{} 〜 {} 締め）",
+            card.name,
+            format_yen(total),
+            start.format("%m/%d"),
+            end.format("%m/%d")
+        );
+        if let Some(payment) = card.payment_date(end) {
+            line.push_str(&format!("、{} 引き落とし", payment.format("%m/%d")));
+        }
+        lines.push(line);
+    }
+
+    ctx.say(lines.join("\n")).await?;
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use crate::chart;
+use crate::ledger::{self, Month};
+use crate::{Context, Error};
+use poise::serenity_prelude::CreateAttachment;
+use poise::CreateReply;
+
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+pub enum ChartKind {
+    #[name = "分類別"]
+    Category,
+    #[name = "累計と予算"]
+    Cumulative,
+    #[name = "12か月の推移"]
+    Trend,
+}
+
+/// 支出のグラフを表示します
+#[poise::command(slash_command, guild_only, check = "super::viewer")]
+pub async fn chart(
+    ctx: Context<'_>,
+    #[description = "対象の月（例: 2026-09）。省略すると今月"] month: Option<String>,
+    #[description = "グラフの種類"] kind: Option<ChartKind>,
+) -> Result<(), Error> {
+    let book = super::book(ctx).await?;
+    let month = match month {
+        Some(month) => month.parse::<Month>()?,
+        None => Month::of(book.today()),
+    };
+    let kind = kind.unwrap_or(ChartKind::Category);
+
+    ctx.defer().await?;
+    chart::load_font(&ctx.data().config.chart_font_path)?;
+
+    let png = match kind {
+        ChartKind::Category => chart::category_pie(month, &book.read_month(month).await?)?,
+        ChartKind::Cumulative => {
+            chart::cumulative(month, &book.read_month(month).await?, ctx.data().config.monthly_budget)?
+        }
+        ChartKind::Trend => {
+            // シートがまだない月は 0 円として扱う。読み込めなかった月はエラーにする
+            let sheets = book.sheet_names().await?;
+            let mut totals = Vec::new();
+            let mut m = month;
+            for _ in 0..12 {
+                let entries = book.read_month_if_exists(m, &sheets).await?;
+                totals.push((m, ledger::total_spending(&entries)));
+                m = m.prev();
+            }
+            totals.reverse();
+            chart::trend(&totals)?
+        }
+    };
+
+    let attachment = CreateAttachment::bytes(png, format!("chart-{}.png", month));
+    ctx.send(CreateReply::default().attachment(attachment)).await?;
+    Ok(())
+}
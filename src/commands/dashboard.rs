@@ -0,0 +1,41 @@
+use crate::dashboard::LOGIN_MINUTES;
+use crate::{Context, Error};
+use poise::serenity_prelude::CreateMessage;
+use poise::CreateReply;
+use tracing::warn;
+
+/// このチャンネルの家計簿のダッシュボードを開くリンクを DM で送ります
+#[poise::command(slash_command, guild_only, check = "super::viewer")]
+pub async fn dashboard(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("サーバーの中で実行してください")?;
+    let base_url = ctx
+        .data()
+        .config
+        .public_url
+        .clone()
+        .ok_or("PUBLIC_URL が設定されていないため、ダッシュボードは使えません")?;
+    // 家計簿のないチャンネルではリンクを送らない
+    super::book(ctx).await?;
+
+    let author = ctx.author();
+    let token = ctx.data().dashboard.issue(
+        author.id.get(),
+        &author.name,
+        guild_id.get(),
+        ctx.channel_id().get(),
+        &super::actor(ctx),
+    )?;
+    let content = format!(
+        "家計簿のダッシュボードを開くリンクです。{}分以内に1回だけ使えます。ほかの人には教えないでください\n{}/dashboard/login?token={}",
+        LOGIN_MINUTES, base_url, token
+    );
+    let reply = match author.direct_message(ctx, CreateMessage::new().content(content)).await {
+        Ok(_) => "DM にログイン用のリンクを送りました".to_string(),
+        Err(e) => {
+            warn!("failed to send a dashboard link to {}: {:?}", author.id, e);
+            "DM を送れませんでした。このサーバーのメンバーからの DM を許可してから、もう一度実行してください".to_string()
+        }
+    };
+    ctx.send(CreateReply::default().content(reply).ephemeral(true)).await?;
+    Ok(())
+}
@@ -0,0 +1,31 @@
+use crate::export::{self, Format};
+use crate::ledger::Month;
+use crate::{Context, Error};
+use poise::serenity_prelude::CreateAttachment;
+use poise::CreateReply;
+
+/// 月の明細をファイルで書き出します
+#[poise::command(slash_command, guild_only, check = "super::viewer")]
+pub async fn export(
+    ctx: Context<'_>,
+    #[description = "対象の月（例: 2026-09）。省略すると今月"] month: Option<String>,
+    #[description = "ファイル形式"] format: Option<Format>,
+) -> Result<(), Error> {
+    let book = super::book(ctx).await?;
+    let month = match month {
+        Some(month) => month.parse::<Month>()?,
+        None => Month::of(book.today()),
+    };
+    let format = format.unwrap_or(Format::Csv);
+
+    ctx.defer().await?;
+    let entries = book.read_month(month).await?;
+    let bytes = export::export(&book.layout, &entries, format)?;
+
+    let filename = format!("ledger-{}.{}", month, format.extension());
+    let reply = CreateReply::default()
+        .content(format!("{} の明細 {} 件", month, entries.len()))
+        .attachment(CreateAttachment::bytes(bytes, filename));
+    ctx.send(reply).await?;
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use crate::ledger::{format_yen, Entry, Kind, Month};
+use crate::statement::{self, Reconciliation};
+use crate::{Context, Error};
+use chrono::Duration;
+use poise::serenity_prelude as serenity;
+use poise::CreateReply;
+
+/// 一覧に表示する行数の上限（Discord のメッセージ長の制限のため）
+const LIST_LIMIT: usize = 10;
+
+async fn autocomplete_profile(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    ctx.data()
+        .config
+        .statement_profiles
+        .iter()
+        .filter(|profile| profile.name.contains(partial))
+        .map(|profile| profile.name.clone())
+        .collect()
+}
+
+/// カードや銀行の明細 CSV を家計簿と照合します
+#[poise::command(slash_command, guild_only, check = "super::recorder")]
+pub async fn import(
+    ctx: Context<'_>,
+    #[description = "明細の CSV ファイル"] file: serenity::Attachment,
+    #[description = "明細の種類"]
+    #[autocomplete = "autocomplete_profile"]
+    profile: String,
+) -> Result<(), Error> {
+    let profile = ctx
+        .data()
+        .config
+        .statement_profiles
+        .iter()
+        .find(|p| p.name == profile)
+        .ok_or_else(|| format!("明細の種類 {} は設定されていません", profile))?
+        .clone();
+
+    ctx.defer().await?;
+    let bytes = file.download().await?;
+    let lines = profile.parse(&bytes)?;
+    let (first, last) = match (lines.iter().map(|l| l.date).min(), lines.iter().map(|l| l.date).max()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Err("明細の行を読み取れませんでした。設定の列番号を確認してください".into()),
+    };
+
+    // 明細の期間（前後のずれを含む）にかかる月の記録を集める
+    let tolerance = Duration::days(profile.date_tolerance_days);
+    let (from, to) = (first - tolerance, last + tolerance);
+    let book = super::book(ctx).await?;
+    // まだシートのない月は記録がないものとして扱う
+    let sheets = book.sheet_names().await?;
+    let mut entries = Vec::new();
+    let mut month = Month::of(from);
+    while month <= Month::of(to) {
+        entries.extend(book.read_month_if_exists(month, &sheets).await?);
+        month = month.next();
+    }
+    // 収入や振替はカードの明細には出てこない
+    entries.retain(|e| e.is_expense() && e.date.is_some_and(|d| from <= d && d <= to));
+    // 支払い方法を書いていない行は、どの明細のものか分からないので照合の対象に残す
+    if let Some(method) = &profile.payment_method {
+        entries.retain(|e| e.payment.is_empty() || e.payment == *method);
+    }
+
+    let result = statement::reconcile(lines, entries, profile.date_tolerance_days);
+    let summary = summarize(&profile.name, &result);
+
+    let add_id = format!("{}-add", ctx.id());
+    let close_id = format!("{}-close", ctx.id());
+    let mut buttons = Vec::new();
+    if !result.missing_in_ledger.is_empty() {
+        buttons.push(
+            serenity::CreateButton::new(&add_id)
+                .style(serenity::ButtonStyle::Primary)
+                .label(format!("家計簿にない {} 件を追加", result.missing_in_ledger.len())),
+        );
+    }
+    buttons.push(
+        serenity::CreateButton::new(&close_id)
+            .style(serenity::ButtonStyle::Secondary)
+            .label("閉じる"),
+    );
+
+    let handle = ctx
+        .send(
+            CreateReply::default()
+                .content(&summary)
+                .components(vec![serenity::CreateActionRow::Buttons(buttons)]),
+        )
+        .await?;
+    let link = handle.message().await?.link();
+
+    let interaction = serenity::ComponentInteractionCollector::new(ctx)
+        .author_id(ctx.author().id)
+        .channel_id(ctx.channel_id())
+        .timeout(std::time::Duration::from_secs(600))
+        .filter(move |mci| mci.data.custom_id == add_id || mci.data.custom_id == close_id)
+        .await;
+
+    let footer = match interaction {
+        Some(mci) if mci.data.custom_id.ends_with("-add") => {
+            mci.create_response(ctx, serenity::CreateInteractionResponse::Acknowledge).await?;
+            let user = super::author_name(ctx, &book);
+            let new_entries: Vec<Entry> = result
+                .missing_in_ledger
+                .iter()
+                .map(|line| Entry {
+                    row: 0,
+                    date: Some(line.date),
+                    item: line.description.clone(),
+                    amount: line.amount,
+                    category: String::new(),
+                    user: user.clone(),
+                    link: link.clone(),
+                    kind: Kind::Expense,
+                    payment: profile.payment_method.clone().unwrap_or_default(),
+                    payer: String::new(),
+                    receipt: String::new(),
+                    patient: String::new(),
+                    provider: String::new(),
+                })
+                .collect();
+            match book.append_entries(&new_entries, &super::actor(ctx)).await {
+                Ok(written) => format!("{} 件を家計簿に追加しました", written.len()),
+                Err(e) => format!("追加に失敗しました: {}", e),
+            }
+        }
+        Some(mci) => {
+            mci.create_response(ctx, serenity::CreateInteractionResponse::Acknowledge).await?;
+            "閉じました".to_string()
+        }
+        None => "時間切れのため操作を終了しました".to_string(),
+    };
+
+    handle
+        .edit(ctx, CreateReply::default().content(format!("{}\n\n{}", summary, footer)).components(vec![]))
+        .await?;
+    Ok(())
+}
+
+fn summarize(profile: &str, result: &Reconciliation) -> String {
+    let mut text = format!(
+        "**{}** の明細を照合しました\n一致: {} 件 / 家計簿にない: {} 件 / 明細にない: {} 件",
+        profile,
+        result.matched.len(),
+        result.missing_in_ledger.len(),
+        result.missing_in_statement.len()
+    );
+
+    if !result.missing_in_ledger.is_empty() {
+        text.push_str("\n\n**家計簿にない明細**");
+        let lines = result.missing_in_ledger.iter().map(|line| {
+            format!("{} {} {}", line.date.format("%m/%d"), line.description, format_yen(line.amount))
+        });
+        push_list(&mut text, lines, result.missing_in_ledger.len());
+    }
+
+    if !result.missing_in_statement.is_empty() {
+        text.push_str("\n\n**明細にない記録**");
+        let entries = result.missing_in_statement.iter().map(|entry| {
+            let date = entry.date.map(|d| d.format("%m/%d").to_string()).unwrap_or_default();
+            format!("{} {} {}（{}）", date, entry.item, format_yen(entry.amount), entry.user)
+        });
+        push_list(&mut text, entries, result.missing_in_statement.len());
+    }
+
+    text
+}
+
+fn push_list(text: &mut String, items: impl Iterator<Item = String>, total: usize) {
+    for item in items.take(LIST_LIMIT) {
+        text.push_str("\n- ");
+        text.push_str(&item);
+    }
+    if total > LIST_LIMIT {
+        text.push_str(&format!("\n…ほか {} 件", total - LIST_LIMIT));
+    }
+}
@@ -0,0 +1,75 @@
+use crate::ledger::Layout;
+use crate::routing::Route;
+use crate::{Context, Error};
+use poise::serenity_prelude as serenity;
+use std::collections::HashMap;
+
+/// チャンネルと家計簿の割り当てを管理します
+#[poise::command(slash_command, guild_only, subcommands("bind", "unbind", "list"))]
+pub async fn ledger(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// チャンネルまたはスレッドに家計簿を割り当てます
+#[poise::command(slash_command, guild_only, check = "super::admin")]
+pub async fn bind(
+    ctx: Context<'_>,
+    #[description = "スプレッドシートの ID"] spreadsheet_id: String,
+    #[description = "割り当てるチャンネルまたはスレッド。省略するとこのチャンネル"] channel: Option<serenity::GuildChannel>,
+    #[description = "タイムゾーン（例: +09:00）。省略するとサーバーのタイムゾーン"] timezone: Option<String>,
+    #[description = "シートのレイアウト（JSON）。省略すると既定のレイアウト"] layout: Option<String>,
+    #[description = "この家計簿だけで使うユーザー ID と記録者名の対応（JSON）。省略すると /member の登録を使う"] users: Option<String>,
+) -> Result<(), Error> {
+    let channel_id = channel.map(|c| c.id).unwrap_or_else(|| ctx.channel_id());
+    let layout: Layout = match layout {
+        Some(layout) => serde_json::from_str(&layout).map_err(|e| format!("レイアウトの JSON が読めません: {}", e))?,
+        None => Layout::default(),
+    };
+    let users: Option<HashMap<u64, String>> = users
+        .map(|users| serde_json::from_str(&users))
+        .transpose()
+        .map_err(|e| format!("ユーザーの JSON が読めません: {}", e))?;
+
+    let guild_id = ctx.guild_id().map(|id| id.get());
+    let route = Route { spreadsheet_id: spreadsheet_id.trim().to_string(), layout, timezone, users, guild_id };
+    ctx.data().ledgers.bind(channel_id, route, &super::actor(ctx))?;
+    ctx.say(format!("<#{}> の記録をこの家計簿に書き込みます", channel_id)).await?;
+    Ok(())
+}
+
+/// `/ledger bind` で割り当てた家計簿を外します
+#[poise::command(slash_command, guild_only, check = "super::admin")]
+pub async fn unbind(
+    ctx: Context<'_>,
+    #[description = "割り当てを外すチャンネルまたはスレッド。省略するとこのチャンネル"] channel: Option<serenity::GuildChannel>,
+) -> Result<(), Error> {
+    let channel_id = channel.map(|c| c.id).unwrap_or_else(|| ctx.channel_id());
+    let guild_id = ctx.guild_id().ok_or("サーバーの中で実行してください")?;
+    if ctx.data().ledgers.unbind(guild_id, channel_id, &super::actor(ctx))? {
+        ctx.say(format!("<#{}> の割り当てを外しました", channel_id)).await?;
+    } else {
+        ctx.say(format!("<#{}> は /ledger bind で割り当てられていません", channel_id)).await?;
+    }
+    Ok(())
+}
+
+/// チャンネルと家計簿の割り当ての一覧を表示します
+#[poise::command(slash_command, guild_only, check = "super::admin")]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("サーバーの中で実行してください")?;
+    let routes = ctx.data().ledgers.routes(guild_id);
+    if routes.is_empty() {
+        ctx.say("割り当てはありません。すべて既定の家計簿に記録します").await?;
+        return Ok(());
+    }
+
+    let lines: Vec<String> = routes
+        .iter()
+        .map(|(channel, route)| {
+            let timezone = route.timezone.as_deref().unwrap_or("サーバーの時刻");
+            format!("- <#{}>: `{}`（{}）", channel, route.spreadsheet_id, timezone)
+        })
+        .collect();
+    ctx.say(lines.join("\n")).await?;
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use crate::permission::Role;
+use crate::{Context, Error};
+use poise::serenity_prelude as serenity;
+
+/// 家計簿に書く記録者名を管理します
+#[poise::command(slash_command, guild_only, subcommands("add", "remove", "rename", "list"))]
+pub async fn member(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// 記録者名を登録します
+#[poise::command(slash_command, guild_only, check = "super::recorder")]
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "家計簿に書く名前"] name: String,
+    #[description = "登録する人。省略すると自分"] user: Option<serenity::User>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("サーバーの中で実行してください")?;
+    let user = user.as_ref().unwrap_or_else(|| ctx.author());
+    // ほかの人の名前を変えられるのは管理者だけ
+    if user.id != ctx.author().id && !super::has_role(ctx, Role::Admin).await? {
+        return Ok(());
+    }
+    let message = match ctx.data().members.add(guild_id, user.id, &name, &super::actor(ctx))? {
+        Some(previous) => format!("<@{}> の名前を「{}」から「{}」に変えました", user.id, previous, name.trim()),
+        None => format!("<@{}> を「{}」として登録しました", user.id, name.trim()),
+    };
+    ctx.say(message).await?;
+    Ok(())
+}
+
+/// 記録者名の登録を取り消します
+#[poise::command(slash_command, guild_only, check = "super::admin")]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "取り消す人"] user: serenity::User,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("サーバーの中で実行してください")?;
+    let message = match ctx.data().members.remove(guild_id, user.id, &super::actor(ctx))? {
+        Some(name) => format!("<@{}>（{}）の登録を取り消しました", user.id, name),
+        None => format!("<@{}> は登録されていません", user.id),
+    };
+    ctx.say(message).await?;
+    Ok(())
+}
+
+/// 登録済みの記録者名を変えます
+#[poise::command(slash_command, guild_only, check = "super::recorder")]
+pub async fn rename(
+    ctx: Context<'_>,
+    #[description = "新しい名前"] name: String,
+    #[description = "名前を変える人。省略すると自分"] user: Option<serenity::User>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("サーバーの中で実行してください")?;
+    let user = user.as_ref().unwrap_or_else(|| ctx.author());
+    if user.id != ctx.author().id && !super::has_role(ctx, Role::Admin).await? {
+        return Ok(());
+    }
+    let members = &ctx.data().members;
+    if !members.list(guild_id).contains_key(&user.id.get()) {
+        ctx.say(format!("<@{}> は登録されていません。/member add で登録してください", user.id)).await?;
+        return Ok(());
+    }
+    let previous = members.add(guild_id, user.id, &name, &super::actor(ctx))?.unwrap_or_default();
+    ctx.say(format!("<@{}> の名前を「{}」から「{}」に変えました", user.id, previous, name.trim())).await?;
+    Ok(())
+}
+
+/// 登録されている記録者名の一覧を表示します
+#[poise::command(slash_command, guild_only, check = "super::viewer")]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("サーバーの中で実行してください")?;
+    let members = ctx.data().members.list(guild_id);
+    if members.is_empty() {
+        ctx.say("まだ誰も登録されていません。/member add で登録してください").await?;
+        return Ok(());
+    }
+
+    let mut lines: Vec<(&String, u64)> = members.iter().map(|(id, name)| (name, *id)).collect();
+    lines.sort();
+    let lines: Vec<String> = lines.iter().map(|(name, id)| format!("- {}: <@{}>", name, id)).collect();
+    ctx.send(
+        poise::CreateReply::default()
+            .content(lines.join("\n"))
+            .allowed_mentions(serenity::CreateAllowedMentions::new()),
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use crate::interop::{self, App};
+use crate::ledger::Month;
+use crate::{Context, Error};
+use poise::serenity_prelude as serenity;
+use poise::{ChoiceParameter, CreateReply};
+
+/// 他の家計簿アプリとの間で履歴を移行します
+#[poise::command(slash_command, guild_only, subcommands("migrate_import", "migrate_export"))]
+pub async fn migrate(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// アプリから書き出した CSV を家計簿に取り込みます
+#[poise::command(slash_command, guild_only, rename = "import", check = "super::recorder")]
+pub async fn migrate_import(
+    ctx: Context<'_>,
+    #[description = "取り込み元のアプリ"] app: App,
+    #[description = "アプリから書き出した CSV ファイル"] file: serenity::Attachment,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    let bytes = file.download().await?;
+    let mut entries = interop::import(app, &bytes, &ctx.data().config.category_map)?;
+    if entries.is_empty() {
+        ctx.say("取り込める明細がありませんでした").await?;
+        return Ok(());
+    }
+
+    let book = super::book(ctx).await?;
+    let user = super::author_name(ctx, &book);
+    for entry in &mut entries {
+        entry.user = user.clone();
+    }
+    let written = book.append_entries(&entries, &super::actor(ctx)).await?;
+    ctx.say(format!("{} から {} 件を取り込みました", app.name(), written.len())).await?;
+    Ok(())
+}
+
+/// 家計簿をアプリの CSV 形式で書き出します
+#[poise::command(slash_command, guild_only, rename = "export", check = "super::viewer")]
+pub async fn migrate_export(
+    ctx: Context<'_>,
+    #[description = "書き出し先のアプリ"] app: App,
+    #[description = "最初の月（例: 2026-01）"] from: String,
+    #[description = "最後の月（例: 2026-09）。省略すると最初の月だけ"] to: Option<String>,
+) -> Result<(), Error> {
+    let from = from.parse::<Month>()?;
+    let to = match to {
+        Some(to) => to.parse::<Month>()?,
+        None => from,
+    };
+    if to < from {
+        return Err("最後の月が最初の月より前になっています".into());
+    }
+
+    ctx.defer().await?;
+    let book = super::book(ctx).await?;
+    // まだシートのない月は明細がないものとして扱う
+    let sheets = book.sheet_names().await?;
+    let mut entries = Vec::new();
+    let mut month = from;
+    while month <= to {
+        entries.extend(book.read_month_if_exists(month, &sheets).await?);
+        month = month.next();
+    }
+
+    let bytes = interop::export(app, &entries, &ctx.data().config.category_map)?;
+    let filename = format!("{:?}-{}-{}.csv", app, from, to).to_lowercase();
+    let reply = CreateReply::default()
+        .content(format!("{} 〜 {} の明細 {} 件", from, to, entries.len()))
+        .attachment(serenity::CreateAttachment::bytes(bytes, filename));
+    ctx.send(reply).await?;
+    Ok(())
+}
@@ -0,0 +1,95 @@
+pub mod chart;
+pub mod export;
+pub mod import;
+pub mod migrate;
+pub mod cards;
+pub mod receipt;
+pub mod tax;
+pub mod ledger;
+pub mod setup;
+pub mod member;
+pub mod role;
+pub mod audit;
+pub mod dashboard;
+
+use crate::audit::Actor;
+use crate::permission::Role;
+use crate::spreadsheet::Book;
+use crate::{Context, Error};
+use poise::{ChoiceParameter, CreateReply};
+use std::sync::Arc;
+use tracing::warn;
+
+/// コマンドを実行したチャンネルの家計簿。ほかのサーバーの家計簿は返さない
+pub async fn book(ctx: Context<'_>) -> Result<Arc<Book>, Error> {
+    ctx.data()
+        .ledgers
+        .lookup(&ctx.serenity_context().http, ctx.guild_id(), ctx.channel_id())
+        .await
+        .ok_or_else(|| "このサーバーではまだ家計簿が設定されていません。管理者が /setup で設定してください".into())
+}
+
+/// 監査ログに残す、コマンドを実行した人
+pub fn actor(ctx: Context<'_>) -> Actor {
+    Actor {
+        user_id: Some(ctx.author().id.get()),
+        user_name: ctx.author().name.clone(),
+        guild_id: ctx.guild_id().map(|id| id.get()),
+        message_id: Some(ctx.id()),
+    }
+}
+
+/// コマンドを実行した人の記録者名。未登録なら Discord の表示名
+pub fn author_name(ctx: Context<'_>, book: &Book) -> String {
+    ctx.data()
+        .members
+        .directory(ctx.guild_id(), book)
+        .remove(&ctx.author().id.get())
+        .unwrap_or_else(|| ctx.author().display_name().to_string())
+}
+
+/// 実行した人が `required` 以上の権限を持つか。足りなければその旨を返信し、ログに残す
+pub async fn has_role(ctx: Context<'_>, required: Role) -> Result<bool, Error> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("サーバーの中で実行してください").await?;
+        return Ok(false);
+    };
+    let member = ctx.author_member().await;
+    let roles = member.as_ref().map(|m| m.roles.clone()).unwrap_or_default();
+    let manages_guild = member
+        .as_ref()
+        .and_then(|m| m.permissions)
+        .is_some_and(|p| p.manage_guild());
+    let role = ctx.data().permissions.role_of(guild_id, ctx.author().id, &roles, manages_guild);
+    if role >= Some(required) {
+        return Ok(true);
+    }
+
+    warn!(
+        "permission denied: user {} ({}) ran /{} in guild {} with role {:?}, requires {:?}",
+        ctx.author().name,
+        ctx.author().id,
+        ctx.command().qualified_name,
+        guild_id,
+        role,
+        required
+    );
+    let reply = CreateReply::default()
+        .content(format!("この操作には「{}」の権限が必要です", required.name()))
+        .ephemeral(true);
+    ctx.send(reply).await?;
+    Ok(false)
+}
+
+/// `check = "viewer"` などで各コマンドに必要な権限を宣言する
+pub async fn viewer(ctx: Context<'_>) -> Result<bool, Error> {
+    has_role(ctx, Role::Viewer).await
+}
+
+pub async fn recorder(ctx: Context<'_>) -> Result<bool, Error> {
+    has_role(ctx, Role::Recorder).await
+}
+
+pub async fn admin(ctx: Context<'_>) -> Result<bool, Error> {
+    has_role(ctx, Role::Admin).await
+}
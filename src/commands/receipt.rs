@@ -0,0 +1,43 @@
+use crate::ledger::Month;
+use crate::receipt;
+use crate::{Context, Error};
+use poise::CreateReply;
+
+/// 記録した行のレシート画像を表示します
+#[poise::command(slash_command, guild_only, check = "super::viewer")]
+pub async fn receipt(
+    ctx: Context<'_>,
+    #[description = "シートの行番号"] row: i64,
+    #[description = "対象の月（例: 2026-09）。省略すると今月"] month: Option<String>,
+) -> Result<(), Error> {
+    let book = super::book(ctx).await?;
+    let month = match month {
+        Some(month) => month.parse::<Month>()?,
+        None => Month::of(book.today()),
+    };
+
+    ctx.defer().await?;
+    let entries = book.read_month(month).await?;
+    let entry = entries
+        .iter()
+        .find(|e| e.row == row)
+        .ok_or_else(|| format!("{} の {} 行目は記録されていません", month, row))?;
+    if entry.receipt.is_empty() {
+        ctx.say(format!("{} 行目（{}）にはレシートがありません", row, entry.item)).await?;
+        return Ok(());
+    }
+
+    let guild_id = ctx.guild_id().ok_or("サーバーの中で実行してください")?;
+    let archive_channel = ctx
+        .data()
+        .ledgers
+        .receipt_channel(guild_id, ctx.data().config.receipt_channel_id)
+        .ok_or("このサーバーにはレシートの保管用チャンネルが設定されていません")?;
+    let files = receipt::fetch(&ctx.serenity_context().http, &entry.receipt, guild_id, archive_channel).await?;
+    let mut reply = CreateReply::default().content(format!("{} 行目: {}\n{}", row, entry.item, entry.receipt));
+    for file in files {
+        reply = reply.attachment(file);
+    }
+    ctx.send(reply).await?;
+    Ok(())
+}
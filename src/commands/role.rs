@@ -0,0 +1,103 @@
+use crate::permission::Role;
+use crate::{Context, Error};
+use poise::serenity_prelude as serenity;
+use poise::{ChoiceParameter, CreateReply};
+
+/// ボットの操作権限を Discord のロールや人に割り当てます
+#[poise::command(slash_command, guild_only, subcommands("grant", "revoke", "everyone", "list"))]
+pub async fn role(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Discord のロールまたは人に権限を割り当てます
+#[poise::command(slash_command, guild_only, check = "super::admin")]
+pub async fn grant(
+    ctx: Context<'_>,
+    #[description = "割り当てる権限"] role: Role,
+    #[description = "対象の Discord のロール"] discord_role: Option<serenity::Role>,
+    #[description = "対象の人"] user: Option<serenity::User>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("サーバーの中で実行してください")?;
+    let target = match (&discord_role, &user) {
+        (Some(r), None) => format!("<@&{}>", r.id),
+        (None, Some(u)) => format!("<@{}>", u.id),
+        _ => return Err("Discord のロールか人のどちらか一方を指定してください".into()),
+    };
+    ctx.data().permissions.update(guild_id, &super::actor(ctx), |bindings| {
+        if let Some(r) = &discord_role {
+            bindings.roles.insert(r.id.get(), role);
+        }
+        if let Some(u) = &user {
+            bindings.users.insert(u.id.get(), role);
+        }
+    })?;
+    say_quietly(ctx, format!("{} に「{}」の権限を割り当てました", target, role.name())).await
+}
+
+/// Discord のロールまたは人への割り当てを取り消します
+#[poise::command(slash_command, guild_only, check = "super::admin")]
+pub async fn revoke(
+    ctx: Context<'_>,
+    #[description = "対象の Discord のロール"] discord_role: Option<serenity::Role>,
+    #[description = "対象の人"] user: Option<serenity::User>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("サーバーの中で実行してください")?;
+    let target = match (&discord_role, &user) {
+        (Some(r), None) => format!("<@&{}>", r.id),
+        (None, Some(u)) => format!("<@{}>", u.id),
+        _ => return Err("Discord のロールか人のどちらか一方を指定してください".into()),
+    };
+    ctx.data().permissions.update(guild_id, &super::actor(ctx), |bindings| {
+        if let Some(r) = &discord_role {
+            bindings.roles.remove(&r.id.get());
+        }
+        if let Some(u) = &user {
+            bindings.users.remove(&u.id.get());
+        }
+    })?;
+    say_quietly(ctx, format!("{} への割り当てを取り消しました", target)).await
+}
+
+/// どの割り当てにも当てはまらない人の権限を決めます
+#[poise::command(slash_command, guild_only, check = "super::admin")]
+pub async fn everyone(
+    ctx: Context<'_>,
+    #[description = "全員に与える権限。省略すると何もできない"] role: Option<Role>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("サーバーの中で実行してください")?;
+    ctx.data().permissions.update(guild_id, &super::actor(ctx), |bindings| bindings.everyone = role)?;
+    let message = match role {
+        Some(role) => format!("ほかの人は「{}」の権限になります", role.name()),
+        None => "割り当てのない人は何もできなくなります".to_string(),
+    };
+    say_quietly(ctx, message).await
+}
+
+/// 権限の割り当ての一覧を表示します
+#[poise::command(slash_command, guild_only, check = "super::admin")]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("サーバーの中で実行してください")?;
+    let bindings = ctx.data().permissions.bindings(guild_id);
+
+    let mut lines = vec!["サーバーの管理権限を持つ人は常に「管理」です".to_string()];
+    if bindings.is_unconfigured() {
+        lines.push("まだ設定していないため、全員が「記録」の権限を持っています".to_string());
+    } else if bindings.roles.is_empty() && bindings.users.is_empty() && bindings.everyone.is_none() {
+        lines.push("割り当てがないため、管理者のほかは何もできません".to_string());
+    }
+    lines.extend(bindings.roles.iter().map(|(id, role)| format!("- <@&{}>: {}", id, role.name())));
+    lines.extend(bindings.users.iter().map(|(id, role)| format!("- <@{}>: {}", id, role.name())));
+    if let Some(role) = bindings.everyone {
+        lines.push(format!("- ほかの人: {}", role.name()));
+    }
+    say_quietly(ctx, lines.join("\n")).await
+}
+
+/// ロールや人をメンションしても通知しないように返信する
+async fn say_quietly(ctx: Context<'_>, content: String) -> Result<(), Error> {
+    let reply = CreateReply::default()
+        .content(content)
+        .allowed_mentions(serenity::CreateAllowedMentions::new());
+    ctx.send(reply).await?;
+    Ok(())
+}
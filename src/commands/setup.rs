@@ -0,0 +1,60 @@
+use crate::routing::GuildSettings;
+use crate::{Context, Error};
+use poise::serenity_prelude as serenity;
+use poise::CreateReply;
+
+/// このサーバーで使う家計簿を設定します
+#[poise::command(slash_command, guild_only, check = "super::admin")]
+pub async fn setup(
+    ctx: Context<'_>,
+    #[description = "支出を記録するチャンネル"] channel: serenity::GuildChannel,
+    #[description = "スプレッドシートの ID（URL の /d/ と /edit の間）"] spreadsheet_id: String,
+    #[description = "タイムゾーン（例: +09:00）。省略すると前回の設定のまま"] timezone: Option<String>,
+    #[description = "レシート画像を保管するチャンネル"] receipt_channel: Option<serenity::GuildChannel>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("サーバーの中で実行してください")?;
+    ctx.defer_ephemeral().await?;
+
+    let ledgers = &ctx.data().ledgers;
+    // 2 回目以降は、省略した項目やレイアウトを前回の設定から引き継ぐ
+    let previous = ledgers.guild_settings(guild_id);
+    let settings = GuildSettings {
+        expenses_channel_id: channel.id.get(),
+        spreadsheet_id: spreadsheet_id.trim().to_string(),
+        layout: previous.as_ref().map(|s| s.layout.clone()).unwrap_or_default(),
+        timezone: timezone.or(previous.as_ref().and_then(|s| s.timezone.clone())),
+        receipt_channel_id: receipt_channel
+            .map(|c| c.id.get())
+            .or(previous.as_ref().and_then(|s| s.receipt_channel_id)),
+    };
+
+    // ほかのサーバーの家計簿は、開けるかどうかも確かめない
+    ledgers.check_owner(guild_id, &settings.spreadsheet_id)?;
+    let book = ledgers.open_guild(guild_id, &settings)?;
+    let title = match book.title().await {
+        Ok(title) => title,
+        Err(e) => {
+            let message = format!(
+                "スプレッドシートを開けませんでした: {}\n`{}` に編集者として共有してから、もう一度 /setup を実行してください",
+                e,
+                book.service_account()
+            );
+            ctx.send(CreateReply::default().content(message).ephemeral(true)).await?;
+            return Ok(());
+        }
+    };
+
+    let lines = [
+        "設定を保存しました".to_string(),
+        format!("- 記録用チャンネル: <#{}>", channel.id),
+        format!("- 家計簿: {}", title),
+        format!("- タイムゾーン: {}", settings.timezone.as_deref().unwrap_or("サーバーの時刻")),
+        match settings.receipt_channel_id {
+            Some(id) => format!("- レシートの保管先: <#{}>", id),
+            None => "- レシートの保管先: なし".to_string(),
+        },
+    ];
+    ledgers.save_guild(guild_id, settings, book, &super::actor(ctx))?;
+    ctx.send(CreateReply::default().content(lines.join("\n")).ephemeral(true)).await?;
+    Ok(())
+}
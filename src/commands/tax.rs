@@ -0,0 +1,77 @@
+use crate::ledger::{format_yen, Month};
+use crate::medical;
+use crate::{Context, Error};
+use chrono::Datelike;
+use poise::serenity_prelude::CreateAttachment;
+use poise::CreateReply;
+use std::collections::BTreeMap;
+
+/// 確定申告のための集計をします
+#[poise::command(slash_command, guild_only, subcommands("medical"))]
+pub async fn tax(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// 1年間の医療費を医療を受けた人・支払先ごとに集計します
+#[poise::command(slash_command, guild_only, check = "super::viewer")]
+pub async fn medical(
+    ctx: Context<'_>,
+    #[description = "対象の年（例: 2026）。省略すると去年"] year: Option<i32>,
+) -> Result<(), Error> {
+    let book = super::book(ctx).await?;
+    let year = year.unwrap_or(book.today().year() - 1);
+
+    ctx.defer().await?;
+    let sheets = book.sheet_names().await?;
+    let mut entries = Vec::new();
+    let mut missing = Vec::new();
+    for month in 1..=12 {
+        let month = Month::new(year, month).unwrap();
+        // シートがない月は記録がないものとして扱う。読み込めなかった月があれば集計しない
+        if !sheets.contains(&book.layout.sheet_name(month)) {
+            missing.push(month);
+            continue;
+        }
+        entries.extend(book.read_month(month).await.map_err(|e| format!("{} を読み込めませんでした: {}", month, e))?);
+    }
+
+    let records = medical::summarize(&entries, &ctx.data().config.medical_categories);
+    if records.is_empty() {
+        ctx.say(format!("{}年の医療費は記録されていません", year)).await?;
+        return Ok(());
+    }
+
+    let mut per_patient: BTreeMap<&str, (i64, i64)> = BTreeMap::new();
+    for record in &records {
+        let (paid, reimbursed) = per_patient.entry(record.patient.as_str()).or_default();
+        *paid += record.paid;
+        *reimbursed += record.reimbursed;
+    }
+    let paid: i64 = records.iter().map(|r| r.paid).sum();
+    let reimbursed: i64 = records.iter().map(|r| r.reimbursed).sum();
+
+    let mut lines = vec![format!("**{}年の医療費**", year)];
+    for (patient, (paid, reimbursed)) in &per_patient {
+        lines.push(format!("- {}: {}（補填 {}）", patient, format_yen(*paid), format_yen(*reimbursed)));
+    }
+    lines.push(format!(
+        "合計 {} − 補填 {} = {}",
+        format_yen(paid),
+        format_yen(reimbursed),
+        format_yen(paid - reimbursed)
+    ));
+    // 控除額は所得によって変わるので、目安として 10 万円を超えた分だけを示す
+    lines.push(format!("10万円を超える分: {}", format_yen((paid - reimbursed - 100_000).max(0))));
+
+    if !missing.is_empty() {
+        let months: Vec<String> = missing.iter().map(|m| m.to_string()).collect();
+        lines.push(format!("シートがないため含めていない月: {}", months.join("、")));
+    }
+
+    let csv = medical::to_csv(&records)?;
+    let reply = CreateReply::default()
+        .content(lines.join("\n"))
+        .attachment(CreateAttachment::bytes(csv, format!("medical-{}.csv", year)));
+    ctx.send(reply).await?;
+    Ok(())
+}
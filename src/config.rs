@@ -0,0 +1,193 @@
+use anyhow::Context as _;
+use poise::serenity_prelude::ChannelId;
+use shuttle_runtime::SecretStore;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use crate::statement::StatementProfile;
+use crate::interop::CategoryMap;
+use crate::payment::PaymentMethod;
+use crate::amount::TaxRates;
+use crate::medical::{self, MedicalKind};
+use crate::routing::Route;
+use crate::ledger::Layout;
+use crate::auth::{ApiKey, WebhookSecret};
+use crate::webhook::WebhookSource;
+use std::collections::BTreeMap;
+
+/// 必須ではない設定値。Shuttle のシークレットから読み込む
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// 月の予算（円）
+    pub monthly_budget: Option<i64>,
+    /// グラフに使う日本語フォント
+    pub chart_font_path: PathBuf,
+    /// `/import` で読める明細 CSV の種類
+    pub statement_profiles: Vec<StatementProfile>,
+    /// Zaim・マネーフォワード ME の分類との対応表
+    pub category_map: CategoryMap,
+    /// `@` で指定できる支払い方法
+    pub payment_methods: Vec<PaymentMethod>,
+    /// `税抜` と書いたときの税率と端数処理
+    pub tax_rates: TaxRates,
+    /// レシート画像を保管するチャンネル
+    pub receipt_channel_id: Option<ChannelId>,
+    /// 医療費控除の対象とする分類と、その医療費の区分
+    pub medical_categories: BTreeMap<String, MedicalKind>,
+    /// シークレットの家計簿（`EXPENSES_SPREADSHEET_ID`）のレイアウト。書いていない項目は既定のまま
+    pub ledger_layout: Layout,
+    /// チャンネル ID ごとに割り当てる家計簿
+    pub ledger_routes: BTreeMap<u64, Route>,
+    /// `/ledger bind` などで変更した設定を保存するディレクトリ
+    pub data_dir: PathBuf,
+    /// HTTP サーバーが待ち受けるアドレス。`None` なら Shuttle が渡すアドレス
+    pub http_addr: Option<SocketAddr>,
+    /// 外から HTTP サーバーを開くときの URL（`https://example.com`）。ダッシュボードのリンクに使う
+    pub public_url: Option<String>,
+    /// `/send-message` で既定のチャンネルのほかに送信を許すチャンネル
+    pub http_allowed_channels: Vec<ChannelId>,
+    /// HTTP API の `Authorization: Bearer` で使うキー
+    pub api_keys: Vec<ApiKey>,
+    /// HTTP API に署名付きで送るときの鍵
+    pub webhook_secrets: Vec<WebhookSecret>,
+    /// `/webhooks/{source}` で受け取る決済の通知の読み取り方
+    pub webhook_sources: BTreeMap<String, WebhookSource>,
+}
+
+impl Config {
+    pub fn from_secrets(secrets: &SecretStore) -> Result<Self, anyhow::Error> {
+        let monthly_budget = secrets
+            .get("MONTHLY_BUDGET")
+            .map(|v| v.parse::<i64>())
+            .transpose()
+            .context("'MONTHLY_BUDGET' is not a valid i64")?;
+
+        let chart_font_path = secrets
+            .get("CHART_FONT_PATH")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(crate::chart::DEFAULT_FONT_PATH));
+
+        let statement_profiles = secrets
+            .get("STATEMENT_PROFILES")
+            .map(|v| serde_json::from_str(&v))
+            .transpose()
+            .context("'STATEMENT_PROFILES' is not valid JSON")?
+            .unwrap_or_default();
+
+        let category_map = secrets
+            .get("CATEGORY_MAP")
+            .map(|v| serde_json::from_str(&v))
+            .transpose()
+            .context("'CATEGORY_MAP' is not valid JSON")?
+            .unwrap_or_default();
+
+        let payment_methods = secrets
+            .get("PAYMENT_METHODS")
+            .map(|v| serde_json::from_str(&v))
+            .transpose()
+            .context("'PAYMENT_METHODS' is not valid JSON")?
+            .unwrap_or_default();
+
+        let tax_rates = secrets
+            .get("TAX_RATES")
+            .map(|v| serde_json::from_str(&v))
+            .transpose()
+            .context("'TAX_RATES' is not valid JSON")?
+            .unwrap_or_default();
+
+        let receipt_channel_id = secrets
+            .get("RECEIPT_CHANNEL_ID")
+            .map(|v| v.parse::<u64>())
+            .transpose()
+            .context("'RECEIPT_CHANNEL_ID' is not a valid u64")?
+            .map(ChannelId::new);
+
+        let medical_categories = secrets
+            .get("MEDICAL_CATEGORIES")
+            .map(|v| serde_json::from_str(&v))
+            .transpose()
+            .context("'MEDICAL_CATEGORIES' is not valid JSON")?
+            .unwrap_or_else(medical::default_categories);
+
+        let ledger_layout = secrets
+            .get("LEDGER_LAYOUT")
+            .map(|v| serde_json::from_str(&v))
+            .transpose()
+            .context("'LEDGER_LAYOUT' is not valid JSON")?
+            .unwrap_or_default();
+
+        let ledger_routes = secrets
+            .get("LEDGER_ROUTES")
+            .map(|v| serde_json::from_str(&v))
+            .transpose()
+            .context("'LEDGER_ROUTES' is not valid JSON")?
+            .unwrap_or_default();
+
+        let data_dir = secrets
+            .get("DATA_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("data"));
+
+        // シークレットになければ環境変数を見る。ポートだけなら全てのアドレスで待ち受ける
+        let setting = |key: &str| secrets.get(key).or_else(|| std::env::var(key).ok());
+        let http_addr = match (setting("HTTP_ADDR"), setting("HTTP_PORT")) {
+            (Some(addr), _) => Some(addr.parse().context("'HTTP_ADDR' is not a valid socket address")?),
+            (None, Some(port)) => {
+                let port: u16 = port.parse().context("'HTTP_PORT' is not a valid port")?;
+                Some(SocketAddr::from(([0, 0, 0, 0], port)))
+            }
+            (None, None) => None,
+        };
+        let public_url = setting("PUBLIC_URL").map(|url| url.trim_end_matches('/').to_string());
+
+        let http_allowed_channels = secrets
+            .get("HTTP_ALLOWED_CHANNELS")
+            .map(|v| serde_json::from_str::<Vec<u64>>(&v))
+            .transpose()
+            .context("'HTTP_ALLOWED_CHANNELS' is not valid JSON")?
+            .unwrap_or_default()
+            .into_iter()
+            .map(ChannelId::new)
+            .collect();
+
+        let api_keys = secrets
+            .get("API_KEYS")
+            .map(|v| serde_json::from_str(&v))
+            .transpose()
+            .context("'API_KEYS' is not valid JSON")?
+            .unwrap_or_default();
+
+        let webhook_secrets = secrets
+            .get("WEBHOOK_SECRETS")
+            .map(|v| serde_json::from_str(&v))
+            .transpose()
+            .context("'WEBHOOK_SECRETS' is not valid JSON")?
+            .unwrap_or_default();
+
+        let webhook_sources = secrets
+            .get("WEBHOOK_SOURCES")
+            .map(|v| serde_json::from_str(&v))
+            .transpose()
+            .context("'WEBHOOK_SOURCES' is not valid JSON")?
+            .unwrap_or_default();
+
+        Ok(Self {
+            monthly_budget,
+            chart_font_path,
+            statement_profiles,
+            category_map,
+            payment_methods,
+            tax_rates,
+            receipt_channel_id,
+            medical_categories,
+            ledger_layout,
+            ledger_routes,
+            data_dir,
+            http_addr,
+            public_url,
+            http_allowed_channels,
+            api_keys,
+            webhook_secrets,
+            webhook_sources,
+        })
+    }
+}
@@ -0,0 +1,399 @@
+use crate::audit::{Actor, AuditLog, AuditRecord};
+use crate::auth::{self, Principal, Scopes};
+use crate::expenses_api::Target;
+use crate::http_server::{ApiError, AppState};
+use crate::ledger::{format_yen, Entry, Month};
+use crate::permission::Role;
+use crate::storage::{load_json, save_json};
+use crate::summary::MonthSummary;
+use axum::extract::{Form, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::routing::{get, post};
+use axum::Router;
+use chrono::{DateTime, Duration, Utc};
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// ログイン用のリンクの有効期限（分）
+pub const LOGIN_MINUTES: i64 = 15;
+/// ログインしたままにする日数
+const SESSION_DAYS: i64 = 30;
+const COOKIE: &str = "dashboard_session";
+/// 最近の明細として表示する件数
+const RECENT_ENTRIES: usize = 10;
+
+/// ダッシュボードを見られる人と家計簿
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub user_id: u64,
+    pub user_name: String,
+    pub guild_id: u64,
+    /// `/dashboard` を実行したチャンネル。このチャンネルの家計簿を表示する
+    pub channel_id: u64,
+    /// ログイン用のリンクなら `true`。1回使うと消す
+    #[serde(default)]
+    login: bool,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Session {
+    fn actor(&self) -> Actor {
+        Actor { user_id: Some(self.user_id), user_name: self.user_name.clone(), guild_id: Some(self.guild_id), message_id: None }
+    }
+}
+
+/// ダッシュボードのログイン。トークンそのものは持たず、SHA-256 だけをファイルに保存する
+pub struct Sessions {
+    path: PathBuf,
+    items: RwLock<BTreeMap<String, Session>>,
+    audit: Arc<AuditLog>,
+}
+
+impl Sessions {
+    pub fn load(path: PathBuf, audit: Arc<AuditLog>) -> Result<Self, Error> {
+        let items = load_json(&path)?;
+        Ok(Self { path, items: RwLock::new(items), audit })
+    }
+
+    /// ログイン用のリンクに入れるトークンを発行する
+    pub fn issue(&self, user_id: u64, user_name: &str, guild_id: u64, channel_id: u64, actor: &Actor) -> Result<String, Error> {
+        let session = Session {
+            user_id,
+            user_name: user_name.to_string(),
+            guild_id,
+            channel_id,
+            login: true,
+            expires_at: Utc::now() + Duration::minutes(LOGIN_MINUTES),
+        };
+        let mut items = self.items.write().unwrap();
+        let token = insert(&mut items, session.clone())?;
+        let result = save_json(&self.path, &*items);
+        self.audit.record(
+            AuditRecord::new(actor, "dashboard.issue", "dashboard.json")
+                .range(channel_id.to_string())
+                .new_value(&session)
+                .result(&result),
+        );
+        result.map(|_| token)
+    }
+
+    /// ログイン用のトークンをセッションのトークンに替える。期限切れや使用済みなら `None`
+    fn login(&self, token: &str) -> Result<Option<(String, Session)>, Error> {
+        let mut items = self.items.write().unwrap();
+        let Some(login) = items.remove(&hash(token)).filter(|s| s.login && s.expires_at > Utc::now()) else {
+            return Ok(None);
+        };
+        let session = Session { login: false, expires_at: Utc::now() + Duration::days(SESSION_DAYS), ..login };
+        let token = insert(&mut items, session.clone())?;
+        let result = save_json(&self.path, &*items);
+        self.audit.record(
+            AuditRecord::new(&session.actor(), "dashboard.login", "dashboard.json")
+                .range(session.channel_id.to_string())
+                .result(&result),
+        );
+        result.map(|_| Some((token, session)))
+    }
+
+    fn session(&self, token: &str) -> Option<Session> {
+        self.items
+            .read()
+            .unwrap()
+            .get(&hash(token))
+            .filter(|s| !s.login && s.expires_at > Utc::now())
+            .cloned()
+    }
+
+    fn logout(&self, token: &str) -> Result<(), Error> {
+        let mut items = self.items.write().unwrap();
+        if items.remove(&hash(token)).is_none() {
+            return Ok(());
+        }
+        save_json(&self.path, &*items)
+    }
+}
+
+/// 期限の切れたものを捨ててから加え、トークンを返す
+fn insert(items: &mut BTreeMap<String, Session>, session: Session) -> Result<String, Error> {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new().fill(&mut bytes).map_err(|_| "トークンを作れませんでした")?;
+    let token = auth::hex(&bytes);
+    let now = Utc::now();
+    items.retain(|_, s| s.expires_at > now);
+    items.insert(hash(&token), session);
+    Ok(token)
+}
+
+fn hash(token: &str) -> String {
+    auth::hex(digest::digest(&digest::SHA256, token.as_bytes()).as_ref())
+}
+
+fn cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|pair| pair.trim().strip_prefix(COOKIE)?.strip_prefix('='))
+}
+
+/// HTTPS で公開しているときだけ `Secure` を付ける
+fn set_cookie(state: &AppState, value: &str, max_age: i64) -> String {
+    let secure = state.config.public_url.as_deref().is_some_and(|url| url.starts_with("https://"));
+    format!(
+        "{}={}; Path=/dashboard; Max-Age={}; HttpOnly; SameSite=Lax{}",
+        COOKIE,
+        value,
+        max_age,
+        if secure { "; Secure" } else { "" }
+    )
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn page(title: &str, body: &str) -> Html<String> {
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="ja">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta name="robots" content="noindex">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; margin: 0 auto; max-width: 40rem; padding: 1rem; color: #222; }}
+h1 {{ font-size: 1.4rem; }}
+h2 {{ font-size: 1.1rem; margin-top: 1.5rem; border-bottom: 1px solid #ddd; }}
+table {{ width: 100%; border-collapse: collapse; }}
+td {{ padding: 0.3rem 0.2rem; border-bottom: 1px solid #eee; }}
+td.amount {{ text-align: right; white-space: nowrap; }}
+.totals {{ display: flex; gap: 1rem; }}
+.totals div {{ flex: 1; background: #f4f6f8; padding: 0.6rem; border-radius: 0.4rem; }}
+.totals strong {{ display: block; font-size: 1.3rem; }}
+.bar {{ background: #eee; height: 0.6rem; border-radius: 0.3rem; overflow: hidden; }}
+.bar span {{ display: block; height: 100%; background: #4e79a7; }}
+.bar.over span {{ background: #e15759; }}
+nav {{ display: flex; justify-content: space-between; }}
+button {{ font-size: 1rem; padding: 0.5rem 1rem; }}
+.muted {{ color: #777; }}
+</style>
+</head>
+<body>
+{body}
+</body>
+</html>
+"#,
+        title = escape(title),
+        body = body,
+    ))
+}
+
+fn message_page(status: StatusCode, message: &str) -> Response {
+    (status, page("家計簿", &format!("<p>{}</p>", escape(message)))).into_response()
+}
+
+/// ログインしていないときの案内
+fn login_required() -> Response {
+    message_page(
+        StatusCode::UNAUTHORIZED,
+        "ログインしてください。Discord の家計簿のチャンネルで /dashboard を実行すると、ログイン用のリンクを DM で送ります",
+    )
+}
+
+fn bar(value: i64, max: i64, over: bool) -> String {
+    let width = if max > 0 { (value * 100 / max).clamp(0, 100) } else { 0 };
+    format!(r#"<div class="bar{}"><span style="width: {}%"></span></div>"#, if over { " over" } else { "" }, width)
+}
+
+fn render(title: &str, summary: &MonthSummary, recent: &[Entry]) -> String {
+    let month = summary.month;
+    let mut body = format!(
+        r#"<h1>{title}</h1>
+<nav><a href="?month={prev}">← {prev}</a><strong>{month}</strong><a href="?month={next}">{next} →</a></nav>
+<div class="totals"><div>支出<strong>{spending}</strong></div><div>収入<strong>{income}</strong></div></div>
+"#,
+        title = escape(title),
+        prev = month.prev(),
+        next = month.next(),
+        month = month,
+        spending = format_yen(summary.spending),
+        income = format_yen(summary.income),
+    );
+
+    if let (Some(budget), Some(used)) = (summary.budget, summary.budget_used()) {
+        body.push_str(&format!(
+            "<h2>予算</h2>\n<p>{} のうち {}（{}%）、残り {}</p>\n{}\n",
+            format_yen(budget),
+            format_yen(summary.spending),
+            used,
+            format_yen(budget - summary.spending),
+            bar(summary.spending, budget, summary.spending > budget),
+        ));
+    }
+
+    body.push_str("<h2>分類別</h2>\n");
+    if summary.categories.is_empty() {
+        body.push_str(r#"<p class="muted">記録がありません</p>"#);
+    } else {
+        let top = summary.categories[0].1;
+        body.push_str("<table>\n");
+        for (category, amount) in &summary.categories {
+            body.push_str(&format!(
+                "<tr><td>{}{}</td><td class=\"amount\">{}</td></tr>\n",
+                escape(category),
+                bar(*amount, top, false),
+                format_yen(*amount)
+            ));
+        }
+        body.push_str("</table>\n");
+    }
+
+    body.push_str("<h2>最近の明細</h2>\n");
+    if recent.is_empty() {
+        body.push_str(r#"<p class="muted">記録がありません</p>"#);
+    } else {
+        body.push_str("<table>\n");
+        for entry in recent {
+            let date = entry.date.map(|d| d.format("%m/%d").to_string()).unwrap_or_default();
+            let kind = if entry.is_expense() { String::new() } else { format!("（{}）", entry.kind.label()) };
+            body.push_str(&format!(
+                "<tr><td class=\"muted\">{}</td><td>{}{}</td><td>{}</td><td class=\"amount\">{}</td></tr>\n",
+                date,
+                escape(&entry.item),
+                kind,
+                escape(&entry.user),
+                format_yen(entry.amount)
+            ));
+        }
+        body.push_str("</table>\n");
+    }
+
+    body.push_str("<h2>精算</h2>\n");
+    if summary.balances.is_empty() {
+        body.push_str(r#"<p class="muted">立て替えはありません</p>"#);
+    } else {
+        body.push_str("<table>\n");
+        for (user, amount) in &summary.balances {
+            let direction = if *amount > 0 { "受け取る" } else { "支払う" };
+            body.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td class=\"amount\">{}</td></tr>\n",
+                escape(user),
+                direction,
+                format_yen(amount.abs())
+            ));
+        }
+        body.push_str("</table>\n");
+    }
+
+    body.push_str(r#"<form method="post" action="/dashboard/logout"><p><button type="submit">ログアウト</button></p></form>"#);
+    body
+}
+
+#[derive(Deserialize)]
+struct DashboardQuery {
+    /// 省略すると家計簿のタイムゾーンでの今月
+    #[serde(default)]
+    month: Option<String>,
+}
+
+async fn dashboard(State(state): State<AppState>, Query(query): Query<DashboardQuery>, headers: HeaderMap) -> Response {
+    let Some(session) = cookie(&headers).and_then(|token| state.dashboard.session(token)) else {
+        return login_required();
+    };
+    match show(&state, &session, query.month.as_deref()).await {
+        Ok(html) => html.into_response(),
+        Err(e) => message_page(e.status(), e.message()),
+    }
+}
+
+async fn show(state: &AppState, session: &Session, month: Option<&str>) -> Result<Html<String>, ApiError> {
+    // API キーと同じく、チャンネルの家計簿と見る人の権限を毎回確かめる
+    let principal = Principal {
+        name: session.user_name.clone(),
+        scopes: Scopes { endpoints: Vec::new(), channels: Some(vec![session.channel_id]) },
+        user_id: Some(session.user_id),
+    };
+    let target = Target::resolve(state, &principal, Some(session.channel_id), Role::Viewer).await?;
+    let month = match month {
+        Some(month) => month.parse::<Month>().map_err(|e| ApiError::bad_request(e.to_string()))?,
+        None => Month::of(target.book.today()),
+    };
+
+    // 次の月へのリンクもあるので、まだシートのない月は空の月として表示する
+    let sheets = target.book.sheet_names().await.map_err(|e| ApiError::upstream(e.to_string()))?;
+    let entries = target
+        .book
+        .read_month_if_exists(month, &sheets)
+        .await
+        .map_err(|e| ApiError::upstream(e.to_string()))?;
+    let summary = MonthSummary::new(month, &entries, state.config.monthly_budget);
+    let mut recent = entries;
+    recent.sort_by(|a, b| b.date.cmp(&a.date).then(b.row.cmp(&a.row)));
+    recent.truncate(RECENT_ENTRIES);
+
+    let title = target.book.title().await.unwrap_or_else(|_| "家計簿".to_string());
+    Ok(page(&title, &render(&title, &summary, &recent)))
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+    token: String,
+}
+
+/// リンクを開いただけではログインしない。Discord がリンクのプレビューのために開いても使われないようにする
+async fn login_page(Query(query): Query<LoginForm>) -> Html<String> {
+    page(
+        "家計簿にログイン",
+        &format!(
+            r#"<h1>家計簿にログイン</h1>
+<form method="post" action="/dashboard/login">
+<input type="hidden" name="token" value="{}">
+<p><button type="submit">ログインする</button></p>
+</form>"#,
+            escape(&query.token)
+        ),
+    )
+}
+
+async fn login(State(state): State<AppState>, Form(form): Form<LoginForm>) -> Response {
+    match state.dashboard.login(&form.token) {
+        Ok(Some((token, _))) => {
+            let cookie = set_cookie(&state, &token, SESSION_DAYS * 24 * 60 * 60);
+            ([(header::SET_COOKIE, cookie)], Redirect::to("/dashboard")).into_response()
+        }
+        Ok(None) => message_page(
+            StatusCode::UNAUTHORIZED,
+            "リンクの期限が切れているか、すでに使われています。Discord で /dashboard を実行し直してください",
+        ),
+        Err(e) => message_page(StatusCode::INTERNAL_SERVER_ERROR, &format!("ログインに失敗しました: {}", e)),
+    }
+}
+
+async fn logout(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Some(token) = cookie(&headers) {
+        if let Err(e) = state.dashboard.logout(token) {
+            return message_page(StatusCode::INTERNAL_SERVER_ERROR, &format!("ログアウトに失敗しました: {}", e));
+        }
+    }
+    ([(header::SET_COOKIE, set_cookie(&state, "", 0))], Redirect::to("/dashboard")).into_response()
+}
+
+/// `/dashboard` 以下のルート。API キーではなく Cookie のセッションで確かめる
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/dashboard", get(dashboard))
+        .route("/dashboard/login", get(login_page).post(login))
+        .route("/dashboard/logout", post(logout))
+}
@@ -0,0 +1,349 @@
+use crate::audit::Actor;
+use crate::auth::Principal;
+use crate::expenses_api::{Expense, Target};
+use crate::http_server::{ApiError, AppState};
+use crate::permission::Role;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Extension, Query, State};
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::Response;
+use axum::routing::get;
+use axum::Router;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, Stream};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+/// 再接続したときに送り直せるよう残しておく件数
+const HISTORY: usize = 256;
+
+/// 取りこぼしがあったときに送る知らせ。受け取ったら一覧を読み込み直す
+const RESYNC: &str = r#"{"type":"resync"}"#;
+
+/// イベントの ID。起動した時刻と通し番号で表す（`1760000000000.42`）。再起動をまたいだ ID は送り直せない
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventId {
+    epoch: i64,
+    seq: u64,
+}
+
+impl fmt::Display for EventId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.epoch, self.seq)
+    }
+}
+
+impl FromStr for EventId {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (epoch, seq) = s.split_once('.').ok_or(())?;
+        Ok(Self { epoch: epoch.parse().map_err(|_| ())?, seq: seq.parse().map_err(|_| ())? })
+    }
+}
+
+impl Serialize for EventId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+    ExpenseCreated { expenses: Vec<Expense> },
+    ExpenseUpdated { old: Box<Expense>, expense: Box<Expense> },
+    ExpenseDeleted { expense: Box<Expense> },
+    /// 書き込みで月の支出が `MONTHLY_BUDGET` を超えた
+    BudgetExceeded { month: String, total: i64, budget: i64 },
+}
+
+impl EventKind {
+    /// SSE の `event:` に使う名前
+    fn name(&self) -> &'static str {
+        match self {
+            Self::ExpenseCreated { .. } => "expense_created",
+            Self::ExpenseUpdated { .. } => "expense_updated",
+            Self::ExpenseDeleted { .. } => "expense_deleted",
+            Self::BudgetExceeded { .. } => "budget_exceeded",
+        }
+    }
+}
+
+/// 家計簿への書き込み1回分
+#[derive(Debug, Clone, Serialize)]
+pub struct LedgerEvent {
+    pub id: EventId,
+    pub time: DateTime<Utc>,
+    pub spreadsheet_id: String,
+    /// 操作した人（API なら `api:キーの名前`）
+    pub actor: String,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+/// 家計簿の変更を `/events` の購読者に流す。家計簿（`Book`）が書き込むたびに送る
+#[derive(Debug)]
+pub struct EventBus {
+    epoch: i64,
+    /// 月の予算。超えたら `BudgetExceeded` を送る
+    pub monthly_budget: Option<i64>,
+    sender: broadcast::Sender<LedgerEvent>,
+    history: Mutex<VecDeque<LedgerEvent>>,
+}
+
+impl EventBus {
+    pub fn new(monthly_budget: Option<i64>) -> Self {
+        let (sender, _) = broadcast::channel(HISTORY);
+        Self { epoch: Utc::now().timestamp_millis(), monthly_budget, sender, history: Mutex::new(VecDeque::new()) }
+    }
+
+    pub fn publish(&self, spreadsheet_id: &str, actor: &Actor, kind: EventKind) {
+        // 購読し始めた人が履歴と配信の間で取りこぼさないよう、ロックしたまま送る
+        let mut history = self.history.lock().unwrap();
+        let seq = history.back().map_or(1, |e| e.id.seq + 1);
+        let event = LedgerEvent {
+            id: EventId { epoch: self.epoch, seq },
+            time: Utc::now(),
+            spreadsheet_id: spreadsheet_id.to_string(),
+            actor: actor.user_name.clone(),
+            kind,
+        };
+        history.push_back(event.clone());
+        if history.len() > HISTORY {
+            history.pop_front();
+        }
+        // 購読者がいなければ送れないが、履歴には残っている
+        let _ = self.sender.send(event);
+    }
+
+    /// `last_event_id` の続きから購読する。`spreadsheet_id` を指定するとその家計簿だけにする
+    pub fn subscribe(&self, last_event_id: Option<&str>, spreadsheet_id: Option<String>) -> Subscription {
+        let history = self.history.lock().unwrap();
+        let receiver = self.sender.subscribe();
+        let (missed, lost) = match last_event_id {
+            None => (VecDeque::new(), false),
+            Some(id) => match id.parse::<EventId>() {
+                Ok(id) if id.epoch == self.epoch && history.front().is_none_or(|e| e.id.seq <= id.seq + 1) => {
+                    (history.iter().filter(|e| e.id.seq > id.seq).cloned().collect(), false)
+                }
+                _ => (VecDeque::new(), true),
+            },
+        };
+        Subscription { spreadsheet_id, lost, missed, receiver }
+    }
+}
+
+/// 購読者に送るもの
+pub enum Delivery {
+    Event(Box<LedgerEvent>),
+    /// 取りこぼしがあり、続きから送れない
+    Resync,
+}
+
+impl Delivery {
+    fn json(&self) -> String {
+        match self {
+            Self::Event(event) => serde_json::to_string(event).unwrap_or_default(),
+            Self::Resync => RESYNC.to_string(),
+        }
+    }
+
+    fn sse(&self) -> Event {
+        match self {
+            Self::Event(event) => Event::default().id(event.id.to_string()).event(event.kind.name()).data(self.json()),
+            Self::Resync => Event::default().event("resync").data(RESYNC),
+        }
+    }
+}
+
+pub struct Subscription {
+    spreadsheet_id: Option<String>,
+    lost: bool,
+    missed: VecDeque<LedgerEvent>,
+    receiver: broadcast::Receiver<LedgerEvent>,
+}
+
+impl Subscription {
+    /// 次に送るもの。サーバーが止まるときは `None`
+    pub async fn next(&mut self) -> Option<Delivery> {
+        if std::mem::take(&mut self.lost) {
+            return Some(Delivery::Resync);
+        }
+        loop {
+            let event = match self.missed.pop_front() {
+                Some(event) => event,
+                None => match self.receiver.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(_)) => return Some(Delivery::Resync),
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                },
+            };
+            if self.spreadsheet_id.as_ref().is_none_or(|id| *id == event.spreadsheet_id) {
+                return Some(Delivery::Event(Box::new(event)));
+            }
+        }
+    }
+
+    fn into_stream(self) -> impl Stream<Item = Result<Event, std::convert::Infallible>> {
+        stream::unfold(self, |mut subscription| async move {
+            let delivery = subscription.next().await?;
+            Some((Ok(delivery.sse()), subscription))
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct EventsQuery {
+    /// このチャンネルの家計簿を流す。必須
+    #[serde(default)]
+    channel_id: Option<u64>,
+    /// 続きから受け取る。SSE では `Last-Event-ID` ヘッダーでもよい
+    #[serde(default)]
+    last_event_id: Option<String>,
+}
+
+/// チャンネルの家計簿の購読を始める。チャンネルとキーに結び付いた人の権限は `Target::resolve` で確かめる。
+/// 全ての家計簿をまとめて流すと、ほかのサーバーの家計簿まで見えてしまうので受け付けない
+async fn subscribe(state: &AppState, principal: &Principal, channel_id: Option<u64>, last_event_id: Option<&str>) -> Result<Subscription, ApiError> {
+    let channel_id = channel_id.ok_or_else(|| ApiError::bad_request("channel_id を指定してください"))?;
+    let target = Target::resolve(state, principal, Some(channel_id), Role::Viewer).await?;
+    Ok(state.events.subscribe(last_event_id, Some(target.book.id().to_string())))
+}
+
+async fn server_sent_events(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<EventsQuery>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, std::convert::Infallible>>>, ApiError> {
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .or(query.last_event_id.as_deref());
+    let subscription = subscribe(&state, &principal, query.channel_id, last_event_id).await?;
+    Ok(Sse::new(subscription.into_stream()).keep_alive(KeepAlive::default()))
+}
+
+async fn websocket(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<EventsQuery>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    let subscription = subscribe(&state, &principal, query.channel_id, query.last_event_id.as_deref()).await?;
+    Ok(upgrade.on_upgrade(|socket| forward(socket, subscription)))
+}
+
+async fn forward(mut socket: WebSocket, mut subscription: Subscription) {
+    loop {
+        tokio::select! {
+            delivery = subscription.next() => {
+                let Some(delivery) = delivery else { break };
+                if socket.send(Message::Text(delivery.json().into())).await.is_err() {
+                    break;
+                }
+            }
+            // クライアントからの送信は読み捨て、閉じられたら終える
+            message = socket.recv() => {
+                if !matches!(message, Some(Ok(_))) {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// `/events` 以下のルート。認証は呼び出し側で付ける
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/events", get(server_sent_events))
+        .route("/events/ws", get(websocket))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::Actor;
+
+    fn budget(month: &str) -> EventKind {
+        EventKind::BudgetExceeded { month: month.to_string(), total: 120_000, budget: 100_000 }
+    }
+
+    async fn next_month(subscription: &mut Subscription) -> String {
+        match subscription.next().await {
+            Some(Delivery::Event(event)) => match event.kind {
+                EventKind::BudgetExceeded { month, .. } => month,
+                other => panic!("unexpected event {:?}", other),
+            },
+            Some(Delivery::Resync) => "resync".to_string(),
+            None => "closed".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn resumes_after_last_event_id() {
+        let bus = EventBus::new(None);
+        let actor = Actor::system("test");
+        bus.publish("sheet", &actor, budget("2026-07"));
+        let last = bus.history.lock().unwrap().back().unwrap().id.to_string();
+        bus.publish("sheet", &actor, budget("2026-08"));
+        bus.publish("other", &actor, budget("2026-08-other"));
+        bus.publish("sheet", &actor, budget("2026-09"));
+
+        let mut subscription = bus.subscribe(Some(&last), Some("sheet".to_string()));
+        assert_eq!(next_month(&mut subscription).await, "2026-08");
+        assert_eq!(next_month(&mut subscription).await, "2026-09");
+        // 履歴を送り終えたら、あとから送られたものを受け取る
+        bus.publish("sheet", &actor, budget("2026-10"));
+        assert_eq!(next_month(&mut subscription).await, "2026-10");
+    }
+
+    #[tokio::test]
+    async fn resyncs_for_ids_from_another_run() {
+        let bus = EventBus::new(None);
+        let actor = Actor::system("test");
+        bus.publish("sheet", &actor, budget("2026-09"));
+        let stale = EventId { epoch: bus.epoch - 1, seq: 1 }.to_string();
+        let mut subscription = bus.subscribe(Some(&stale), None);
+        assert_eq!(next_month(&mut subscription).await, "resync");
+
+        let mut subscription = bus.subscribe(Some("not-an-id"), None);
+        assert_eq!(next_month(&mut subscription).await, "resync");
+    }
+
+    #[tokio::test]
+    async fn resyncs_when_history_overflowed() {
+        let bus = EventBus::new(None);
+        let actor = Actor::system("test");
+        bus.publish("sheet", &actor, budget("first"));
+        let first = bus.history.lock().unwrap().back().unwrap().id.to_string();
+        for _ in 0..HISTORY + 1 {
+            bus.publish("sheet", &actor, budget("later"));
+        }
+        assert_eq!(bus.history.lock().unwrap().len(), HISTORY);
+
+        let mut subscription = bus.subscribe(Some(&first), None);
+        assert_eq!(next_month(&mut subscription).await, "resync");
+        // 履歴に残っている ID からなら続きを送れる
+        let kept = bus.history.lock().unwrap().front().unwrap().id.to_string();
+        let mut subscription = bus.subscribe(Some(&kept), None);
+        assert_eq!(next_month(&mut subscription).await, "later");
+    }
+
+    #[tokio::test]
+    async fn resyncs_a_lagging_subscriber() {
+        let bus = EventBus::new(None);
+        let actor = Actor::system("test");
+        let mut subscription = bus.subscribe(None, None);
+        for _ in 0..HISTORY + 1 {
+            bus.publish("sheet", &actor, budget("later"));
+        }
+        assert_eq!(next_month(&mut subscription).await, "resync");
+    }
+}
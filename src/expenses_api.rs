@@ -0,0 +1,311 @@
+use crate::audit::Actor;
+use crate::auth::Principal;
+use crate::http_server::{ApiError, AppState};
+use crate::ledger::{format_yen, Entry, Kind, Month};
+use crate::permission::Role;
+use crate::recording::{self, Draft};
+use crate::spreadsheet::Book;
+use axum::extract::{Extension, Json, Path, Query, State};
+use axum::http::StatusCode;
+use axum::routing::{get, patch};
+use axum::Router;
+use chrono::NaiveDate;
+use poise::serenity_prelude::{Channel, ChannelId, GuildId, UserId};
+use poise::ChoiceParameter;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::{error, warn};
+
+/// 明細の ID。月と行番号で表す（`2026-09.17`）。削除しても行は詰めないので変わらない
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ExpenseId {
+    month: Month,
+    row: i64,
+}
+
+impl fmt::Display for ExpenseId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.month, self.row)
+    }
+}
+
+impl FromStr for ExpenseId {
+    type Err = ApiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ApiError::bad_request(format!("明細の ID は 2026-09.17 の形式で指定してください: {}", s));
+        let (month, row) = s.split_once('.').ok_or_else(invalid)?;
+        Ok(Self {
+            month: month.parse().map_err(|_| invalid())?,
+            row: row.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Expense {
+    id: String,
+    #[serde(flatten)]
+    entry: Entry,
+}
+
+impl Expense {
+    fn new(entry: Entry) -> Self {
+        let id = ExpenseId { month: Month::of(entry.date.unwrap_or_default()), row: entry.row };
+        Self { id: id.to_string(), entry }
+    }
+
+    pub fn in_month(month: Month, entry: Entry) -> Self {
+        Self { id: ExpenseId { month, row: entry.row }.to_string(), entry }
+    }
+}
+
+#[derive(Deserialize)]
+struct ChannelQuery {
+    /// 操作する家計簿のチャンネル。省略すると `EXPENSES_CHANNEL_ID`
+    #[serde(default)]
+    channel_id: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    #[serde(default)]
+    channel_id: Option<u64>,
+    #[serde(default)]
+    month: Option<String>,
+    #[serde(default)]
+    category: Option<String>,
+    #[serde(default)]
+    payer: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CreateExpense {
+    /// Discord に書くのと同じ記録用の文（`ランチ 980 @楽天カード` など）
+    text: String,
+    /// 省略すると家計簿のタイムゾーンでの今日
+    #[serde(default)]
+    date: Option<NaiveDate>,
+    #[serde(default)]
+    category: Option<String>,
+}
+
+/// 指定した項目だけを書き換える。空の文字列はセルを空にする
+#[derive(Deserialize)]
+struct UpdateExpense {
+    #[serde(default)]
+    date: Option<NaiveDate>,
+    #[serde(default)]
+    item: Option<String>,
+    #[serde(default)]
+    amount: Option<i64>,
+    #[serde(default)]
+    category: Option<String>,
+    #[serde(default)]
+    kind: Option<Kind>,
+    #[serde(default)]
+    payment: Option<String>,
+    #[serde(default)]
+    payer: Option<String>,
+    #[serde(default)]
+    patient: Option<String>,
+    #[serde(default)]
+    provider: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Created {
+    expenses: Vec<Expense>,
+    /// Discord に知らせた文
+    message: String,
+}
+
+#[derive(Serialize)]
+struct Listed {
+    month: String,
+    expenses: Vec<Expense>,
+}
+
+/// 操作する家計簿と、その家計簿のサーバー・チャンネル
+pub struct Target {
+    channel_id: ChannelId,
+    guild_id: Option<GuildId>,
+    pub book: Arc<Book>,
+    actor: Actor,
+}
+
+impl Target {
+    /// チャンネルの家計簿を Discord の発言と同じ規則で探す。キーに Discord のユーザーが結び付けてあれば、その人の権限も確かめる
+    pub async fn resolve(state: &AppState, principal: &Principal, channel_id: Option<u64>, required: Role) -> Result<Self, ApiError> {
+        let channel_id = channel_id.map(ChannelId::new).unwrap_or(state.expenses_channel_id);
+        if !principal.scopes.allows_channel(channel_id.get()) {
+            return Err(ApiError::forbidden(format!("このキーでは <#{}> の家計簿を使えません", channel_id)));
+        }
+        let guild_id = match channel_id.to_channel(&state.http).await {
+            Ok(Channel::Guild(channel)) => Some(channel.guild_id),
+            Ok(_) => None,
+            Err(e) => return Err(ApiError::not_found(format!("チャンネル {} が見つかりません: {}", channel_id, e))),
+        };
+        let book = if channel_id == state.expenses_channel_id {
+            Some(state.ledgers.get(channel_id).unwrap_or_else(|| state.ledgers.default()))
+        } else {
+            state.ledgers.for_message(&state.http, guild_id, channel_id).await
+        };
+        let book = book.ok_or_else(|| ApiError::not_found(format!("<#{}> には家計簿が割り当てられていません", channel_id)))?;
+
+        if let (Some(user_id), Some(guild_id)) = (principal.user_id.map(UserId::new), guild_id) {
+            let roles = guild_id.member(&state.http, user_id).await.map(|m| m.roles).unwrap_or_default();
+            let role = state.permissions.role_of_member(&state.http, guild_id, user_id, &roles, required).await;
+            if role < Some(required) {
+                warn!(
+                    "permission denied: API key {} (user {}) used channel {} with role {:?}, requires {:?}",
+                    principal.name, user_id, channel_id, role, required
+                );
+                return Err(ApiError::forbidden(format!("この操作には「{}」の権限が必要です", required.name())));
+            }
+        }
+
+        let actor = principal.actor(guild_id.map(|id| id.get()));
+        Ok(Self { channel_id, guild_id, book, actor })
+    }
+
+    async fn read(&self, id: ExpenseId) -> Result<Entry, ApiError> {
+        self.book
+            .read_entry(id.month, id.row)
+            .await
+            .map_err(|e| ApiError::upstream(e.to_string()))?
+            .ok_or_else(|| ApiError::not_found(format!("明細 {} はありません", id)))
+    }
+
+    /// 記録した人が分かるように、キーの名前を添えてチャンネルに知らせる
+    async fn announce(&self, state: &AppState, principal: &Principal, message: &str) {
+        let content = format!("{}（API: {}）", message, principal.name);
+        if let Err(e) = self.channel_id.say(&state.http, content).await {
+            error!("Error announcing API change: {:?}", e);
+        }
+    }
+}
+
+async fn create_expense(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<ChannelQuery>,
+    Json(request): Json<CreateExpense>,
+) -> Result<(StatusCode, Json<Created>), ApiError> {
+    let target = Target::resolve(&state, &principal, query.channel_id, Role::Recorder).await?;
+    let users = state.members.directory(target.guild_id, &target.book);
+    let user_name = principal
+        .user_id
+        .and_then(|id| users.get(&id).cloned())
+        .unwrap_or_else(|| principal.name.clone());
+
+    let draft = Draft::new(&request.text, &state.config, &users, &user_name).map_err(|e| ApiError::bad_request(e.to_string()))?;
+    let expression = draft.expression();
+    let date = request.date.unwrap_or_else(|| target.book.today());
+    let mut entries = draft
+        .into_entries(date, String::new(), String::new())
+        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    if let Some(category) = &request.category {
+        for entry in &mut entries {
+            entry.category = category.clone();
+        }
+    }
+
+    let written = target
+        .book
+        .append_entries(&entries, &target.actor)
+        .await
+        .map_err(|e| ApiError::upstream(e.to_string()))?;
+    let message = recording::announcement(&written, expression.as_deref());
+    target.announce(&state, &principal, &message).await;
+
+    let expenses = written.into_iter().map(Expense::new).collect();
+    Ok((StatusCode::CREATED, Json(Created { expenses, message })))
+}
+
+async fn list_expenses(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<ListQuery>,
+) -> Result<Json<Listed>, ApiError> {
+    let target = Target::resolve(&state, &principal, query.channel_id, Role::Viewer).await?;
+    let month = match &query.month {
+        Some(month) => month.parse::<Month>().map_err(|e| ApiError::bad_request(e.to_string()))?,
+        None => Month::of(target.book.today()),
+    };
+
+    let entries = target.book.read_month(month).await.map_err(|e| ApiError::upstream(e.to_string()))?;
+    let expenses = entries
+        .into_iter()
+        .filter(|e| query.category.as_ref().is_none_or(|c| e.category == *c))
+        .filter(|e| query.payer.as_ref().is_none_or(|p| e.payer() == p))
+        .map(|e| Expense::in_month(month, e))
+        .collect();
+    Ok(Json(Listed { month: month.to_string(), expenses }))
+}
+
+async fn update_expense(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+    Query(query): Query<ChannelQuery>,
+    Json(request): Json<UpdateExpense>,
+) -> Result<Json<Expense>, ApiError> {
+    let id: ExpenseId = id.parse()?;
+    let target = Target::resolve(&state, &principal, query.channel_id, Role::Recorder).await?;
+    let old = target.read(id).await?;
+
+    // 月ごとにシートが分かれているので、別の月の日付には変えられない
+    if request.date.is_some_and(|date| Month::of(date) != id.month) {
+        return Err(ApiError::bad_request("別の月に移すときは、削除してから記録し直してください"));
+    }
+    let mut entry = old.clone();
+    let UpdateExpense { date, item, amount, category, kind, payment, payer, patient, provider } = request;
+    entry.date = date.or(entry.date);
+    entry.item = item.unwrap_or(entry.item);
+    entry.amount = amount.unwrap_or(entry.amount);
+    entry.category = category.unwrap_or(entry.category);
+    entry.kind = kind.unwrap_or(entry.kind);
+    entry.payment = payment.unwrap_or(entry.payment);
+    entry.payer = payer.unwrap_or(entry.payer);
+    entry.patient = patient.unwrap_or(entry.patient);
+    entry.provider = provider.unwrap_or(entry.provider);
+
+    target
+        .book
+        .update_entry(id.month, &old, &entry, &target.actor)
+        .await
+        .map_err(|e| ApiError::upstream(e.to_string()))?;
+    let message = format!("更新しました: {} {}", entry.item, format_yen(entry.amount));
+    target.announce(&state, &principal, &message).await;
+    Ok(Json(Expense::in_month(id.month, entry)))
+}
+
+async fn delete_expense(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+    Query(query): Query<ChannelQuery>,
+) -> Result<Json<Expense>, ApiError> {
+    let id: ExpenseId = id.parse()?;
+    let target = Target::resolve(&state, &principal, query.channel_id, Role::Recorder).await?;
+    let old = target.read(id).await?;
+
+    target
+        .book
+        .delete_entry(id.month, &old, &target.actor)
+        .await
+        .map_err(|e| ApiError::upstream(e.to_string()))?;
+    let message = format!("削除しました: {} {}", old.item, format_yen(old.amount));
+    target.announce(&state, &principal, &message).await;
+    Ok(Json(Expense::in_month(id.month, old)))
+}
+
+/// `/expenses` 以下のルート。認証は呼び出し側で付ける
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/expenses", get(list_expenses).post(create_expense))
+        .route("/expenses/{id}", patch(update_expense).delete(delete_expense))
+}
@@ -0,0 +1,91 @@
+use crate::ledger::{Entry, Layout};
+use serde_json::json;
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// Excel で日本語が文字化けしないように先頭に付ける
+pub const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum Format {
+    #[name = "csv"]
+    Csv,
+    #[name = "tsv"]
+    Tsv,
+    #[name = "json"]
+    Json,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Tsv => "tsv",
+            Format::Json => "json",
+        }
+    }
+}
+
+/// 明細をシートの列順のまま書き出す
+pub fn export(layout: &Layout, entries: &[Entry], format: Format) -> Result<Vec<u8>, Error> {
+    let columns = layout.columns();
+    let records: Vec<Vec<String>> = entries
+        .iter()
+        .map(|entry| columns.iter().map(|(name, _)| field(entry, name)).collect())
+        .collect();
+
+    match format {
+        Format::Csv | Format::Tsv => {
+            let delimiter = if format == Format::Tsv { b'\t' } else { b',' };
+            let mut writer = csv::WriterBuilder::new()
+                .delimiter(delimiter)
+                .from_writer(UTF8_BOM.to_vec());
+            writer.write_record(columns.iter().map(|(name, _)| name))?;
+            for record in &records {
+                writer.write_record(record)?;
+            }
+            Ok(writer.into_inner().map_err(|e| e.to_string())?)
+        }
+        Format::Json => {
+            let rows: Vec<serde_json::Value> = entries
+                .iter()
+                .map(|entry| {
+                    json!({
+                        "row": entry.row,
+                        "date": entry.date.map(|d| d.format("%Y-%m-%d").to_string()),
+                        "item": entry.item,
+                        "amount": entry.amount,
+                        "category": entry.category,
+                        "user": entry.user,
+                        "link": entry.link,
+                        "kind": entry.kind,
+                        "payment": entry.payment,
+                        "payer": entry.payer(),
+                        "receipt": entry.receipt,
+                        "patient": entry.patient,
+                        "provider": entry.provider,
+                    })
+                })
+                .collect();
+            Ok(serde_json::to_vec_pretty(&rows)?)
+        }
+    }
+}
+
+fn field(entry: &Entry, name: &str) -> String {
+    match name {
+        "日付" => entry.date.map(|d| d.format("%Y/%m/%d").to_string()).unwrap_or_default(),
+        "品目" => entry.item.clone(),
+        "金額" => entry.amount.to_string(),
+        "分類" => entry.category.clone(),
+        "記録者" => entry.user.clone(),
+        "リンク" => entry.link.clone(),
+        "種別" => entry.kind.label().to_string(),
+        "支払い方法" => entry.payment.clone(),
+        "支払った人" => entry.payer.clone(),
+        "レシート" => entry.receipt.clone(),
+        "医療を受けた人" => entry.patient.clone(),
+        "支払先" => entry.provider.clone(),
+        _ => String::new(),
+    }
+}
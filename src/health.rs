@@ -0,0 +1,66 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::Mutex;
+
+/// Google Sheets API の最近の結果
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SheetsStatus {
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_failure: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    /// 最後に取得したアクセストークンの期限
+    pub token_expires_at: Option<DateTime<Utc>>,
+    /// 最後のトークンの取得に失敗していればそのエラー
+    pub token_error: Option<String>,
+}
+
+impl SheetsStatus {
+    /// トークンが取れていて、最後の呼び出しが成功していれば使える。まだ一度も呼んでいなければ使えるとみなす
+    pub fn is_up(&self) -> bool {
+        self.token_error.is_none()
+            && match (self.last_success, self.last_failure) {
+                (_, None) => true,
+                (None, Some(_)) => false,
+                (Some(success), Some(failure)) => success > failure,
+            }
+    }
+}
+
+/// `/readyz` で返す稼働状況。家計簿（`Book`）が呼び出しのたびに書き込む
+#[derive(Debug)]
+pub struct Health {
+    pub started_at: DateTime<Utc>,
+    sheets: Mutex<SheetsStatus>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self { started_at: Utc::now(), sheets: Mutex::new(SheetsStatus::default()) }
+    }
+}
+
+impl Health {
+    pub fn sheets(&self) -> SheetsStatus {
+        self.sheets.lock().unwrap().clone()
+    }
+
+    pub fn sheets_succeeded(&self) {
+        self.sheets.lock().unwrap().last_success = Some(Utc::now());
+    }
+
+    pub fn sheets_failed(&self, error: String) {
+        let mut sheets = self.sheets.lock().unwrap();
+        sheets.last_failure = Some(Utc::now());
+        sheets.last_error = Some(error);
+    }
+
+    pub fn token_refreshed(&self, expires_at: DateTime<Utc>) {
+        let mut sheets = self.sheets.lock().unwrap();
+        sheets.token_expires_at = Some(expires_at);
+        sheets.token_error = None;
+    }
+
+    pub fn token_failed(&self, error: String) {
+        self.sheets.lock().unwrap().token_error = Some(error);
+    }
+}
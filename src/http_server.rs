@@ -1,51 +1,289 @@
 use axum::{
   routing::{post, get},
   Router,
-  extract::Json,
-  http::StatusCode,
+  extract::{DefaultBodyLimit, Extension, FromRef, FromRequest, Json, Multipart, Request, State},
+  http::{header, StatusCode},
+  middleware,
+  response::{IntoResponse, Response},
 };
 use serde::{Deserialize, Serialize};
+use std::future::Future;
 use std::sync::Arc;
 use std::net::SocketAddr;
-use poise::serenity_prelude::{Http, ChannelId};
-use tracing::error;
+use poise::serenity_prelude::{
+  ConnectionStage, Http, ChannelId, ShardManager, CreateAllowedMentions, CreateAttachment, CreateEmbed, Embed, MessageId, RoleId, UserId,
+};
+use tokio::net::TcpListener;
+use tracing::{error, info};
+use crate::auth::{self, Auth, Principal};
+use crate::config::Config;
+use crate::health::{Health, SheetsStatus};
+use crate::telemetry;
+use metrics_exporter_prometheus::PrometheusHandle;
+use crate::events::EventBus;
+use crate::dashboard::Sessions;
+use crate::{dashboard, events, expenses_api, webhook};
+use crate::members::Members;
+use crate::pending::PendingExpenses;
+use crate::permission::Permissions;
+use crate::routing::Ledgers;
+use crate::sink::{MessageSink, OutgoingMessage};
 
-#[derive(Deserialize)]
+#[derive(Default, Deserialize)]
 struct MessageRequest {
-  content: String,
+  #[serde(default)]
+  content: Option<String>,
+  /// 送信先。省略すると既定のチャンネル
+  #[serde(default)]
+  channel_id: Option<ChannelId>,
+  /// Discord の API と同じ形式の埋め込み
+  #[serde(default)]
+  embeds: Vec<Embed>,
+  /// 省略すると本文のメンションを全て通知する
+  #[serde(default)]
+  allowed_mentions: Option<AllowedMentions>,
+  /// 返信する元のメッセージ（送信先と同じチャンネル）
+  #[serde(default)]
+  reply_to: Option<MessageId>,
+  /// 指定すると、送ったメッセージからこの名前のスレッドを作る
+  #[serde(default)]
+  thread_name: Option<String>,
+}
+
+// Discord の API の allowed_mentions と同じ形式
+#[derive(Default, Deserialize)]
+struct AllowedMentions {
+  /// `users` `roles` `everyone` のうち、全て通知するもの
+  #[serde(default)]
+  parse: Vec<String>,
+  #[serde(default)]
+  users: Vec<UserId>,
+  #[serde(default)]
+  roles: Vec<RoleId>,
+  #[serde(default)]
+  replied_user: bool,
+}
+
+impl AllowedMentions {
+  fn build(&self) -> CreateAllowedMentions {
+    let parses = |kind: &str| self.parse.iter().any(|p| p == kind);
+    CreateAllowedMentions::new()
+        .all_users(parses("users"))
+        .all_roles(parses("roles"))
+        .everyone(parses("everyone"))
+        .users(self.users.iter().copied())
+        .roles(self.roles.iter().copied())
+        .replied_user(self.replied_user)
+  }
+}
+
+// JSON の本文か、`payload_json` とファイルを並べた multipart/form-data で受け取る
+struct SendMessage {
+  request: MessageRequest,
+  files: Vec<CreateAttachment>,
+}
+
+impl<S: Send + Sync> FromRequest<S> for SendMessage {
+  type Rejection = ApiError;
+
+  async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+    let is_multipart = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("multipart/form-data"));
+    if !is_multipart {
+      let Json(request) = Json::<MessageRequest>::from_request(req, state)
+          .await
+          .map_err(|e| ApiError::bad_request(e.body_text()))?;
+      return Ok(Self { request, files: Vec::new() });
+    }
+
+    let mut multipart = Multipart::from_request(req, state)
+        .await
+        .map_err(|e| ApiError::bad_request(e.body_text()))?;
+    let mut request = MessageRequest::default();
+    let mut files = Vec::new();
+    while let Some(field) = multipart.next_field().await.map_err(|e| ApiError::bad_request(e.body_text()))? {
+      let name = field.name().unwrap_or_default().to_string();
+      let filename = field.file_name().map(str::to_string);
+      let data = field.bytes().await.map_err(|e| ApiError::bad_request(e.body_text()))?;
+      match filename {
+        Some(filename) => files.push(CreateAttachment::bytes(data.to_vec(), filename)),
+        None if name == "payload_json" => {
+          request = serde_json::from_slice(&data)
+              .map_err(|e| ApiError::bad_request(format!("payload_json が正しくありません: {}", e)))?;
+        }
+        None => return Err(ApiError::bad_request(format!("{} はファイルでも payload_json でもありません", name))),
+      }
+    }
+    Ok(Self { request, files })
+  }
 }
 
 #[derive(Serialize)]
 struct MessageResponse {
   success: bool,
   message: String,
+  channel_id: ChannelId,
+  message_id: MessageId,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  thread_id: Option<ChannelId>,
+}
+
+// ハンドラーで共有するもの。Discord の発言の処理と同じ家計簿や設定を使う
+#[derive(Clone)]
+pub struct AppState {
+  pub http: Arc<Http>,
+  pub shard_manager: Arc<ShardManager>,
+  pub health: Arc<Health>,
+  /// `/metrics` で書き出す集計
+  pub metrics: PrometheusHandle,
+  /// `/events` に流す家計簿の変更
+  pub events: Arc<EventBus>,
+  /// ダッシュボードのログイン
+  pub dashboard: Arc<Sessions>,
+  pub messaging: Messaging,
+  /// シークレットの家計簿に記録するチャンネル（`EXPENSES_CHANNEL_ID`）
+  pub expenses_channel_id: ChannelId,
+  pub ledgers: Arc<Ledgers>,
+  pub members: Arc<Members>,
+  pub permissions: Arc<Permissions>,
+  pub pending: Arc<PendingExpenses>,
+  pub config: Arc<Config>,
+}
+
+/// `/send-message` に要るもの。Discord への送信は `sink` に任せる
+#[derive(Clone)]
+pub struct Messaging {
+  pub sink: Arc<dyn MessageSink>,
+  /// 送信先を省略したときのチャンネル
+  pub default_channel: ChannelId,
+  /// 既定のチャンネルのほかに送信を許すチャンネル（`HTTP_ALLOWED_CHANNELS`）
+  pub allowed_channels: Vec<ChannelId>,
+}
+
+impl FromRef<AppState> for Messaging {
+  fn from_ref(state: &AppState) -> Self {
+    state.messaging.clone()
+  }
+}
+
+// JSON で返すエラー。`code` は機械で判定するための短い識別子
+#[derive(Debug)]
+pub struct ApiError {
+  status: StatusCode,
+  code: &'static str,
+  message: String,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+  success: bool,
+  error: &'a str,
+  message: &'a str,
+}
+
+impl ApiError {
+  pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+    Self { status, code, message: message.into() }
+  }
+
+  pub fn bad_request(message: impl Into<String>) -> Self {
+    Self::new(StatusCode::BAD_REQUEST, "bad_request", message)
+  }
+
+  pub fn unauthorized(message: impl Into<String>) -> Self {
+    Self::new(StatusCode::UNAUTHORIZED, "unauthorized", message)
+  }
+
+  pub fn forbidden(message: impl Into<String>) -> Self {
+    Self::new(StatusCode::FORBIDDEN, "forbidden", message)
+  }
+
+  pub fn not_found(message: impl Into<String>) -> Self {
+    Self::new(StatusCode::NOT_FOUND, "not_found", message)
+  }
+
+  // スプレッドシートなど、先の API が失敗したとき
+  pub fn upstream(message: impl Into<String>) -> Self {
+    Self::new(StatusCode::BAD_GATEWAY, "upstream_error", message)
+  }
+
+  pub fn status(&self) -> StatusCode {
+    self.status
+  }
+
+  pub fn message(&self) -> &str {
+    &self.message
+  }
+}
+
+impl IntoResponse for ApiError {
+  fn into_response(self) -> Response {
+    let body = Json(ErrorBody { success: false, error: self.code, message: &self.message });
+    if self.status == StatusCode::UNAUTHORIZED {
+      (self.status, [(header::WWW_AUTHENTICATE, "Bearer")], body).into_response()
+    } else {
+      (self.status, body).into_response()
+    }
+  }
 }
 
 async fn send_message(
-  Json(payload): Json<MessageRequest>,
-  http_context: Arc<Http>,
-  channel_id: ChannelId,
-) -> (StatusCode, Json<MessageResponse>) {
-  println!("メッセージ送信: {:?}", payload.content);
-  match channel_id.say(&http_context, &payload.content).await {
-      Ok(_) => (
-          StatusCode::OK,
-          Json(MessageResponse {
-              success: true,
-              message: "メッセージを送信しました".to_string(),
-          }),
-      ),
-      Err(e) => {
-          error!("メッセージ送信エラー: {:?}", e);
-          (
-              StatusCode::INTERNAL_SERVER_ERROR,
-              Json(MessageResponse {
-                  success: false,
-                  message: format!("エラー: {:?}", e),
-              }),
-          )
-      }
+  State(messaging): State<Messaging>,
+  Extension(principal): Extension<Principal>,
+  SendMessage { request, files }: SendMessage,
+) -> Result<Json<MessageResponse>, ApiError> {
+  let default_channel = messaging.default_channel;
+  let channel_id = request.channel_id.unwrap_or(default_channel);
+  if channel_id != default_channel && !messaging.allowed_channels.contains(&channel_id) {
+    return Err(ApiError::forbidden(format!("<#{}> への送信は許可されていません", channel_id)));
+  }
+  if !principal.scopes.allows_channel(channel_id.get()) {
+    return Err(ApiError::forbidden(format!("このキーでは <#{}> に送信できません", channel_id)));
   }
+  let content = request.content.unwrap_or_default();
+  if content.is_empty() && request.embeds.is_empty() && files.is_empty() {
+    return Err(ApiError::bad_request("content、embeds、ファイルのいずれかが必要です"));
+  }
+  info!("メッセージ送信 ({}): {:?}", principal.name, content);
+
+  let message = OutgoingMessage {
+      channel_id,
+      content,
+      embeds: request.embeds.into_iter().map(CreateEmbed::from).collect(),
+      allowed_mentions: request.allowed_mentions.as_ref().map(AllowedMentions::build),
+      reply_to: request.reply_to,
+      files,
+  };
+  let message_id = messaging.sink.send(message).await.map_err(|e| {
+    error!("メッセージ送信エラー: {:?}", e);
+    ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "discord_error", format!("エラー: {}", e))
+  })?;
+
+  let thread_id = match request.thread_name {
+    Some(name) => {
+      let thread_id = messaging.sink.create_thread(channel_id, message_id, name).await.map_err(|e| {
+        error!("スレッド作成エラー: {:?}", e);
+        ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "discord_error",
+            format!("メッセージ {} は送信しましたが、スレッドを作れませんでした: {}", message_id, e),
+        )
+      })?;
+      Some(thread_id)
+    }
+    None => None,
+  };
+
+  Ok(Json(MessageResponse {
+      success: true,
+      message: "メッセージを送信しました".to_string(),
+      channel_id,
+      message_id,
+      thread_id,
+  }))
 }
 
 // ルートパスのハンドラを追加
@@ -53,14 +291,311 @@ async fn root() -> &'static str {
     "Discord Bot API Server is running!"
 }
 
-pub async fn start_server(http_context: Arc<Http>, channel_id: ChannelId) {
-  let app = Router::new()
+// プロセスが動いていれば 200 を返す
+async fn healthz() -> Json<serde_json::Value> {
+  Json(serde_json::json!({ "status": "ok" }))
+}
+
+#[derive(Serialize)]
+struct ShardStatus {
+  id: u32,
+  stage: String,
+  latency_ms: Option<u128>,
+}
+
+#[derive(Serialize)]
+struct GatewayStatus {
+  connected: bool,
+  shards: Vec<ShardStatus>,
+}
+
+#[derive(Serialize)]
+struct SheetsReadiness {
+  up: bool,
+  #[serde(flatten)]
+  status: SheetsStatus,
+}
+
+#[derive(Serialize)]
+struct QueueStatus {
+  /// Webhook で受け取り、Discord での確認を待っている支出
+  pending_expenses: usize,
+}
+
+#[derive(Serialize)]
+struct Readiness {
+  ready: bool,
+  started_at: chrono::DateTime<chrono::Utc>,
+  gateway: GatewayStatus,
+  sheets: SheetsReadiness,
+  queue: QueueStatus,
+}
+
+// Discord のゲートウェイと Google Sheets が使えるか。どちらかが使えなければ 503 を返す
+async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<Readiness>) {
+  let runners = state.shard_manager.runners.lock().await;
+  let connected = !runners.is_empty() && runners.values().all(|runner| runner.stage == ConnectionStage::Connected);
+  let shards = runners
+      .iter()
+      .map(|(id, runner)| ShardStatus {
+          id: id.0,
+          stage: runner.stage.to_string(),
+          latency_ms: runner.latency.map(|l| l.as_millis()),
+      })
+      .collect();
+  drop(runners);
+  let sheets = state.health.sheets();
+  let sheets_up = sheets.is_up();
+
+  let readiness = Readiness {
+      ready: connected && sheets_up,
+      started_at: state.health.started_at,
+      gateway: GatewayStatus { connected, shards },
+      sheets: SheetsReadiness { up: sheets_up, status: sheets },
+      queue: QueueStatus { pending_expenses: state.pending.count() },
+  };
+  let status = if readiness.ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+  (status, Json(readiness))
+}
+
+// Prometheus 形式の集計
+async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+  ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], state.metrics.render())
+}
+
+// OpenAPI の定義
+async fn openapi() -> impl IntoResponse {
+  ([(header::CONTENT_TYPE, "application/json")], include_str!("openapi.json"))
+}
+
+/// `/send-message` のルート。`Messaging` だけで動くので、Discord につながなくても試せる
+pub fn message_routes<S>() -> Router<S>
+where
+  S: Clone + Send + Sync + 'static,
+  Messaging: FromRef<S>,
+{
+  Router::new().route("/send-message", post(send_message))
+}
+
+/// API キーか署名を求め、本文の大きさを制限する
+pub fn protect<S>(routes: Router<S>, auth: Arc<Auth>) -> Router<S>
+where
+  S: Clone + Send + Sync + 'static,
+{
+  routes
+      .route_layer(middleware::from_fn_with_state(auth, auth::require))
+      .layer(DefaultBodyLimit::max(auth::MAX_BODY_BYTES))
+}
+
+pub fn router(state: AppState, auth: Arc<Auth>) -> Router {
+  // API キーか署名が必要な経路。ヘルスチェックと OpenAPI の定義は誰でも読め、ダッシュボードはログインで確かめる
+  let protected = Router::new()
+      .merge(message_routes())
+      .route("/metrics", get(metrics))
+      .merge(expenses_api::routes())
+      .merge(webhook::routes())
+      .merge(events::routes());
+  let protected = protect(protected, auth);
+
+  Router::new()
       .route("/", get(root))
-      .route("/send-message", post(move |payload| {
-          send_message(payload, http_context.clone(), channel_id)
-      }));
+      .route("/openapi.json", get(openapi))
+      .route("/healthz", get(healthz))
+      .route("/readyz", get(readyz))
+      .merge(dashboard::routes())
+      .merge(protected)
+      .route_layer(middleware::from_fn(telemetry::track_http))
+      .with_state(state)
+}
+
+// 待ち受けを始める。失敗は起動時のエラーとして呼び出し元に返す
+pub async fn bind(addr: SocketAddr) -> Result<TcpListener, std::io::Error> {
+  TcpListener::bind(addr).await
+}
+
+// `shutdown` が終わるまでリクエストを受け付け、処理中のものを終えてから戻る
+pub async fn serve(
+  listener: TcpListener,
+  app: Router,
+  shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<(), std::io::Error> {
+  axum::serve(listener, app).with_graceful_shutdown(shutdown).await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::auth::{ApiKey, Scopes};
+  use crate::sink::MemorySink;
+  use axum::body::{to_bytes, Body};
+  use ring::digest;
+  use serde_json::{json, Value};
+  use tower::ServiceExt;
 
-  let listener = tokio::net::TcpListener::bind("0.0.0.0").await.unwrap();
-  println!("HTTPサーバーを起動: {:?}", listener);
-  axum::serve(listener, app).await.unwrap();
+  const TOKEN: &str = "test-token";
+  const DEFAULT_CHANNEL: u64 = 100;
+  const ALLOWED_CHANNEL: u64 = 200;
+
+  fn app(sink: Arc<MemorySink>) -> Router {
+    let key = ApiKey {
+        name: "test".to_string(),
+        sha256: auth::hex(digest::digest(&digest::SHA256, TOKEN.as_bytes()).as_ref()),
+        scopes: Scopes { endpoints: vec!["/send-message".to_string()], channels: None },
+        user_id: None,
+    };
+    let messaging = Messaging {
+        sink,
+        default_channel: ChannelId::new(DEFAULT_CHANNEL),
+        allowed_channels: vec![ChannelId::new(ALLOWED_CHANNEL)],
+    };
+    protect(message_routes(), Arc::new(Auth::new(vec![key], Vec::new()))).with_state(messaging)
+  }
+
+  fn post(content_type: &str, body: impl Into<Body>) -> Request {
+    Request::builder()
+        .method("POST")
+        .uri("/send-message")
+        .header(header::AUTHORIZATION, format!("Bearer {}", TOKEN))
+        .header(header::CONTENT_TYPE, content_type)
+        .body(body.into())
+        .unwrap()
+  }
+
+  fn post_json(body: Value) -> Request {
+    post("application/json", body.to_string())
+  }
+
+  async fn call(app: Router, request: Request) -> (StatusCode, Value) {
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+  }
+
+  #[tokio::test]
+  async fn sends_to_default_channel() {
+    let sink = Arc::new(MemorySink::default());
+    let (status, body) = call(app(sink.clone()), post_json(json!({ "content": "こんにちは" }))).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["success"], true);
+    assert_eq!(body["channel_id"], DEFAULT_CHANNEL.to_string());
+    assert_eq!(body["message_id"], "1001");
+    let sent = sink.sent.lock().unwrap();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].channel_id, ChannelId::new(DEFAULT_CHANNEL));
+    assert_eq!(sent[0].content, "こんにちは");
+  }
+
+  #[tokio::test]
+  async fn sends_reply_and_creates_thread_in_allowed_channel() {
+    let sink = Arc::new(MemorySink::default());
+    let request = json!({
+        "content": "返信",
+        "channel_id": ALLOWED_CHANNEL.to_string(),
+        "reply_to": "42",
+        "thread_name": "話題",
+    });
+    let (status, body) = call(app(sink.clone()), post_json(request)).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["channel_id"], ALLOWED_CHANNEL.to_string());
+    assert_eq!(body["thread_id"], "2001");
+    assert_eq!(sink.sent.lock().unwrap()[0].reply_to, Some(MessageId::new(42)));
+    assert_eq!(
+        *sink.threads.lock().unwrap(),
+        vec![(ChannelId::new(ALLOWED_CHANNEL), MessageId::new(1001), "話題".to_string())]
+    );
+  }
+
+  #[tokio::test]
+  async fn sends_files_from_multipart() {
+    let sink = Arc::new(MemorySink::default());
+    let body = concat!(
+        "--boundary\r\n",
+        "Content-Disposition: form-data; name=\"payload_json\"\r\n\r\n",
+        "{\"content\":\"レシート\"}\r\n",
+        "--boundary\r\n",
+        "Content-Disposition: form-data; name=\"files[0]\"; filename=\"receipt.txt\"\r\n",
+        "Content-Type: text/plain\r\n\r\n",
+        "ランチ 980\r\n",
+        "--boundary--\r\n",
+    );
+    let (status, _) = call(app(sink.clone()), post("multipart/form-data; boundary=boundary", body)).await;
+
+    assert_eq!(status, StatusCode::OK);
+    let sent = sink.sent.lock().unwrap();
+    assert_eq!(sent[0].content, "レシート");
+    assert_eq!(sent[0].files.len(), 1);
+    assert_eq!(sent[0].files[0].filename, "receipt.txt");
+    assert_eq!(sent[0].files[0].data, "ランチ 980".as_bytes());
+  }
+
+  #[tokio::test]
+  async fn rejects_empty_message() {
+    let sink = Arc::new(MemorySink::default());
+    let (status, body) = call(app(sink.clone()), post_json(json!({ "content": "" }))).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "bad_request");
+    assert!(sink.sent.lock().unwrap().is_empty());
+  }
+
+  #[tokio::test]
+  async fn rejects_malformed_json() {
+    let sink = Arc::new(MemorySink::default());
+    let (status, body) = call(app(sink.clone()), post("application/json", "{\"content\":")).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["success"], false);
+    assert!(sink.sent.lock().unwrap().is_empty());
+  }
+
+  #[tokio::test]
+  async fn rejects_channel_not_allowed() {
+    let sink = Arc::new(MemorySink::default());
+    let request = json!({ "content": "こんにちは", "channel_id": "300" });
+    let (status, body) = call(app(sink.clone()), post_json(request)).await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["error"], "forbidden");
+    assert!(sink.sent.lock().unwrap().is_empty());
+  }
+
+  #[tokio::test]
+  async fn rejects_missing_api_key() {
+    let sink = Arc::new(MemorySink::default());
+    let request = Request::builder()
+        .method("POST")
+        .uri("/send-message")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "content": "こんにちは" }).to_string()))
+        .unwrap();
+    let (status, body) = call(app(sink.clone()), request).await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"], "unauthorized");
+    assert!(sink.sent.lock().unwrap().is_empty());
+  }
+
+  #[tokio::test]
+  async fn reports_discord_failure() {
+    let sink = Arc::new(MemorySink { fail_send: true, ..Default::default() });
+    let (status, body) = call(app(sink), post_json(json!({ "content": "こんにちは" }))).await;
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body["error"], "discord_error");
+  }
+
+  #[tokio::test]
+  async fn reports_thread_failure_after_sending() {
+    let sink = Arc::new(MemorySink { fail_thread: true, ..Default::default() });
+    let request = json!({ "content": "こんにちは", "thread_name": "話題" });
+    let (status, body) = call(app(sink.clone()), post_json(request)).await;
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body["error"], "discord_error");
+    assert!(body["message"].as_str().unwrap().contains("1001"));
+    assert_eq!(sink.sent.lock().unwrap().len(), 1);
+  }
 }
@@ -0,0 +1,378 @@
+use crate::ledger::{parse_amount, Entry, Kind};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+const UTF8_BOM: &str = "\u{feff}";
+
+const ZAIM_HEADER: [&str; 16] = [
+    "日付", "方法", "カテゴリ", "カテゴリの内訳", "支払元", "入金先", "品目", "メモ", "お店", "通貨",
+    "収入", "支出", "振替", "残高調整", "通貨変換前の金額", "集計の設定",
+];
+
+const MONEYFORWARD_HEADER: [&str; 10] = [
+    "計算対象", "日付", "内容", "金額（円）", "保有金融機関", "大項目", "中項目", "メモ", "振替", "ID",
+];
+
+/// 家計簿アプリ
+#[derive(Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum App {
+    #[name = "Zaim"]
+    Zaim,
+    #[name = "マネーフォワード ME"]
+    MoneyForward,
+}
+
+/// アプリの分類名（`大項目/中項目` または `大項目`）から家計簿の分類名への対応表
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CategoryMap {
+    pub zaim: BTreeMap<String, String>,
+    pub moneyforward: BTreeMap<String, String>,
+}
+
+impl CategoryMap {
+    fn table(&self, app: App) -> &BTreeMap<String, String> {
+        match app {
+            App::Zaim => &self.zaim,
+            App::MoneyForward => &self.moneyforward,
+        }
+    }
+
+    /// 中項目まで一致するものを優先し、なければ大項目だけで引く。どちらもなければそのまま使う
+    pub fn to_ledger(&self, app: App, major: &str, minor: &str) -> String {
+        let table = self.table(app);
+        let full = join_category(major, minor);
+        table
+            .get(&full)
+            .or_else(|| table.get(major))
+            .cloned()
+            .unwrap_or(full)
+    }
+
+    /// 家計簿の分類名をアプリの `(大項目, 中項目)` に戻す。対応表に複数あれば名前順で最初のもの
+    pub fn to_app(&self, app: App, category: &str) -> (String, String) {
+        let app_category = self
+            .table(app)
+            .iter()
+            .find(|(_, ledger)| ledger.as_str() == category)
+            .map(|(app_category, _)| app_category.as_str())
+            .unwrap_or(category);
+        match app_category.split_once('/') {
+            Some((major, minor)) => (major.to_string(), minor.to_string()),
+            None => (app_category.to_string(), String::new()),
+        }
+    }
+}
+
+fn join_category(major: &str, minor: &str) -> String {
+    if minor.is_empty() {
+        major.to_string()
+    } else {
+        format!("{}/{}", major, minor)
+    }
+}
+
+/// アプリの CSV を家計簿の明細にする。集計の対象外になっている行は読み飛ばす
+pub fn import(app: App, bytes: &[u8], categories: &CategoryMap) -> Result<Vec<Entry>, Error> {
+    let text = decode(bytes);
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(text.as_bytes());
+    let headers = reader.headers()?.clone();
+    let index = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h.trim() == name)
+            .ok_or_else(|| format!("{} の列が見つかりません", name))
+    };
+
+    let mut entries = Vec::new();
+    match app {
+        App::Zaim => {
+            let (date, method, major, minor, item, shop, income, expense, transfer) = (
+                index("日付")?,
+                index("方法")?,
+                index("カテゴリ")?,
+                index("カテゴリの内訳")?,
+                index("品目")?,
+                index("お店")?,
+                index("収入")?,
+                index("支出")?,
+                index("振替")?,
+            );
+            // 古い書き出しには集計の設定の列がない
+            let aggregation = index("集計の設定").ok();
+            for record in reader.records() {
+                let record = record?;
+                // Zaim は空欄を `-` で表す
+                let cell = |i: usize| match record.get(i).unwrap_or("").trim() {
+                    "-" => "",
+                    value => value,
+                };
+                if aggregation.is_some_and(|i| cell(i) == "集計に含めない") {
+                    continue;
+                }
+                let (kind, amount) = match cell(method) {
+                    "payment" => (Kind::Expense, cell(expense)),
+                    "income" => (Kind::Income, cell(income)),
+                    "transfer" => (Kind::Transfer, cell(transfer)),
+                    _ => continue,
+                };
+                let item = [cell(item), cell(shop), cell(minor), cell(major)]
+                    .into_iter()
+                    .find(|s| !s.is_empty())
+                    .unwrap_or("");
+                let category = match kind {
+                    Kind::Transfer => String::new(),
+                    _ => categories.to_ledger(app, cell(major), cell(minor)),
+                };
+                entries.push(Entry {
+                    row: 0,
+                    date: Some(parse_date(cell(date))?),
+                    item: item.to_string(),
+                    amount: parse_amount(amount).unwrap_or(0),
+                    category,
+                    user: String::new(),
+                    link: String::new(),
+                    kind,
+                    payment: String::new(),
+                    payer: String::new(),
+                    receipt: String::new(),
+                    patient: String::new(),
+                    provider: String::new(),
+                });
+            }
+        }
+        App::MoneyForward => {
+            let (target, date, content, amount, major, minor, transfer) = (
+                index("計算対象")?,
+                index("日付")?,
+                index("内容")?,
+                index("金額（円）")?,
+                index("大項目")?,
+                index("中項目")?,
+                index("振替")?,
+            );
+            for record in reader.records() {
+                let record = record?;
+                let cell = |i: usize| record.get(i).unwrap_or("").trim();
+                if cell(target) != "1" {
+                    continue;
+                }
+                // マネーフォワードは支出を負の金額で表す
+                let amount = parse_amount(cell(amount)).unwrap_or(0);
+                let (kind, amount, category) = if cell(transfer) == "1" {
+                    (Kind::Transfer, amount.abs(), String::new())
+                } else if amount > 0 {
+                    (Kind::Income, amount, categories.to_ledger(app, cell(major), cell(minor)))
+                } else {
+                    (Kind::Expense, -amount, categories.to_ledger(app, cell(major), cell(minor)))
+                };
+                entries.push(Entry {
+                    row: 0,
+                    date: Some(parse_date(cell(date))?),
+                    item: cell(content).to_string(),
+                    amount,
+                    category,
+                    user: String::new(),
+                    link: String::new(),
+                    kind,
+                    payment: String::new(),
+                    payer: String::new(),
+                    receipt: String::new(),
+                    patient: String::new(),
+                    provider: String::new(),
+                });
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// 明細をアプリの CSV 形式で書き出す。Zaim は BOM 付き UTF-8、マネーフォワードは Shift_JIS
+pub fn export(app: App, entries: &[Entry], categories: &CategoryMap) -> Result<Vec<u8>, Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    match app {
+        App::Zaim => {
+            writer.write_record(ZAIM_HEADER)?;
+            for entry in entries {
+                let (major, minor) = match entry.kind {
+                    Kind::Transfer => ("-".to_string(), "-".to_string()),
+                    _ => categories.to_app(app, &entry.category),
+                };
+                let (method, amount_column) = match entry.kind {
+                    Kind::Expense => ("payment", "支出"),
+                    Kind::Income => ("income", "収入"),
+                    Kind::Transfer => ("transfer", "振替"),
+                };
+                let amount = entry.amount.to_string();
+                let mut fields: HashMap<&str, String> = HashMap::from([
+                    ("日付", format_date(entry, "%Y-%m-%d")),
+                    ("方法", method.to_string()),
+                    ("カテゴリ", major),
+                    ("カテゴリの内訳", minor),
+                    ("品目", entry.item.clone()),
+                    ("メモ", entry.link.clone()),
+                    ("通貨", "JPY".to_string()),
+                    ("収入", "0".to_string()),
+                    ("支出", "0".to_string()),
+                    ("振替", "0".to_string()),
+                    ("残高調整", "0".to_string()),
+                    ("通貨変換前の金額", amount.clone()),
+                    ("集計の設定", "常に集計に含める".to_string()),
+                ]);
+                fields.insert(amount_column, amount);
+                writer.write_record(ZAIM_HEADER.iter().map(|h| fields.get(h).cloned().unwrap_or_default()))?;
+            }
+        }
+        App::MoneyForward => {
+            writer.write_record(MONEYFORWARD_HEADER)?;
+            for entry in entries {
+                let (major, minor) = match entry.kind {
+                    Kind::Transfer => ("未分類".to_string(), "未分類".to_string()),
+                    _ => categories.to_app(app, &entry.category),
+                };
+                let (amount, transfer) = match entry.kind {
+                    Kind::Expense => (-entry.amount, "0"),
+                    Kind::Income => (entry.amount, "0"),
+                    Kind::Transfer => (-entry.amount, "1"),
+                };
+                writer.write_record([
+                    "1".to_string(),
+                    format_date(entry, "%Y/%m/%d"),
+                    entry.item.clone(),
+                    amount.to_string(),
+                    String::new(),
+                    major,
+                    minor,
+                    entry.link.clone(),
+                    transfer.to_string(),
+                    String::new(),
+                ])?;
+            }
+        }
+    }
+
+    let text = String::from_utf8(writer.into_inner().map_err(|e| e.to_string())?)?;
+    match app {
+        App::Zaim => Ok(format!("{}{}", UTF8_BOM, text).into_bytes()),
+        App::MoneyForward => {
+            let (bytes, _, had_errors) = encoding_rs::SHIFT_JIS.encode(&text);
+            if had_errors {
+                return Err("Shift_JIS で表せない文字が含まれています".into());
+            }
+            Ok(bytes.into_owned())
+        }
+    }
+}
+
+/// UTF-8 として読めなければ Shift_JIS とみなす
+fn decode(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.trim_start_matches(UTF8_BOM).to_string(),
+        Err(_) => encoding_rs::SHIFT_JIS.decode(bytes).0.into_owned(),
+    }
+}
+
+fn parse_date(s: &str) -> Result<NaiveDate, Error> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(s, "%Y/%m/%d"))
+        .map_err(|_| format!("日付を読み取れません: {}", s).into())
+}
+
+fn format_date(entry: &Entry, format: &str) -> String {
+    entry.date.map(|d| d.format(format).to_string()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZAIM_SAMPLE: &[u8] = include_bytes!("../tests/fixtures/zaim.csv");
+    const MONEYFORWARD_SAMPLE: &[u8] = include_bytes!("../tests/fixtures/moneyforward.csv");
+
+    fn categories() -> CategoryMap {
+        CategoryMap {
+            zaim: BTreeMap::from([
+                ("食費/食料品".to_string(), "食費".to_string()),
+                ("食費/カフェ".to_string(), "外食".to_string()),
+                ("日用雑貨".to_string(), "日用品".to_string()),
+            ]),
+            moneyforward: BTreeMap::from([
+                ("食費/食料品".to_string(), "食費".to_string()),
+                ("食費/外食".to_string(), "外食".to_string()),
+                ("日用品".to_string(), "日用品".to_string()),
+            ]),
+        }
+    }
+
+    fn summary(entries: &[Entry]) -> Vec<(Option<NaiveDate>, Kind, String, i64, String)> {
+        entries
+            .iter()
+            .map(|e| (e.date, e.kind, e.item.clone(), e.amount, e.category.clone()))
+            .collect()
+    }
+
+    fn row(day: u32, kind: Kind, item: &str, amount: i64, category: &str) -> (Option<NaiveDate>, Kind, String, i64, String) {
+        (NaiveDate::from_ymd_opt(2026, 9, day), kind, item.to_string(), amount, category.to_string())
+    }
+
+    #[test]
+    fn imports_zaim_skipping_excluded_rows() {
+        let entries = import(App::Zaim, ZAIM_SAMPLE, &categories()).unwrap();
+        assert_eq!(
+            summary(&entries),
+            vec![
+                row(1, Kind::Expense, "牛乳", 238, "食費"),
+                row(2, Kind::Expense, "スターバックス", 550, "外食"),
+                row(3, Kind::Income, "給与", 280000, "給与"),
+                row(4, Kind::Transfer, "", 10000, ""),
+                row(5, Kind::Expense, "洗剤", 398, "日用品"),
+            ]
+        );
+    }
+
+    #[test]
+    fn imports_moneyforward_skipping_excluded_rows() {
+        let entries = import(App::MoneyForward, MONEYFORWARD_SAMPLE, &categories()).unwrap();
+        assert_eq!(
+            summary(&entries),
+            vec![
+                row(4, Kind::Expense, "マツモトキヨシ", 642, "日用品"),
+                row(3, Kind::Expense, "サイゼリヤ", 1500, "外食"),
+                row(2, Kind::Income, "給与 株式会社ヒラタケ", 280000, "収入/給与"),
+                row(2, Kind::Transfer, "楽天カード引き落とし", 35000, ""),
+                row(1, Kind::Expense, "イオン", 1280, "食費"),
+            ]
+        );
+    }
+
+    #[test]
+    fn zaim_round_trip() {
+        let entries = import(App::Zaim, ZAIM_SAMPLE, &categories()).unwrap();
+        let exported = export(App::Zaim, &entries, &categories()).unwrap();
+        let reimported = import(App::Zaim, &exported, &categories()).unwrap();
+        assert_eq!(summary(&reimported), summary(&entries));
+    }
+
+    #[test]
+    fn moneyforward_round_trip() {
+        let entries = import(App::MoneyForward, MONEYFORWARD_SAMPLE, &categories()).unwrap();
+        let exported = export(App::MoneyForward, &entries, &categories()).unwrap();
+        assert!(std::str::from_utf8(&exported).is_err(), "Shift_JIS で書き出す");
+        let reimported = import(App::MoneyForward, &exported, &categories()).unwrap();
+        assert_eq!(summary(&reimported), summary(&entries));
+    }
+
+    #[test]
+    fn unmapped_categories_pass_through() {
+        let categories = CategoryMap::default();
+        assert_eq!(categories.to_ledger(App::Zaim, "趣味・娯楽", "本"), "趣味・娯楽/本");
+        assert_eq!(
+            categories.to_app(App::Zaim, "趣味・娯楽/本"),
+            ("趣味・娯楽".to_string(), "本".to_string())
+        );
+    }
+}
@@ -0,0 +1,566 @@
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::str::FromStr;
+
+/// 家計簿シートのレイアウト
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Layout {
+    /// シート名。`{year}` と `{month}` が置き換えられる
+    pub sheet_name: String,
+    /// 明細の1行目
+    pub first_row: i64,
+    pub date_column: String,
+    pub item_column: String,
+    pub amount_column: String,
+    pub category_column: String,
+    pub user_column: String,
+    // ここから下の列は空なら使わない。既存のシートにある列を上書きしないよう、既定ではリンクと支払い方法だけを使う
+    /// 記録元の Discord メッセージへのリンク
+    pub link_column: String,
+    /// 支出・収入・振替の区別
+    pub kind_column: String,
+    /// 支払い方法
+    pub payment_column: String,
+    /// 実際に支払った人。割り勘の行では記録者（負担する人）と異なる
+    pub payer_column: String,
+    /// 保管用チャンネルに投稿し直したレシート画像へのリンク
+    pub receipt_column: String,
+    /// 医療を受けた人（医療費控除用）
+    pub patient_column: String,
+    /// 病院・薬局などの支払先（医療費控除用）
+    pub provider_column: String,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            sheet_name: "日々の記録（{year}.{month}）".to_string(),
+            first_row: 16,
+            date_column: "A".to_string(),
+            item_column: "B".to_string(),
+            amount_column: "C".to_string(),
+            category_column: "D".to_string(),
+            user_column: "E".to_string(),
+            link_column: "F".to_string(),
+            kind_column: String::new(),
+            payment_column: "H".to_string(),
+            payer_column: String::new(),
+            receipt_column: String::new(),
+            patient_column: String::new(),
+            provider_column: String::new(),
+        }
+    }
+}
+
+impl Layout {
+    pub fn sheet_name(&self, month: Month) -> String {
+        self.sheet_name
+            .replace("{year}", &month.year.to_string())
+            .replace("{month}", &format!("{:02}", month.month))
+    }
+
+    /// 使う明細の列を見出しと組にして、シート上の並び順で返す
+    pub fn columns(&self) -> Vec<(&'static str, &str)> {
+        let mut columns = vec![
+            ("日付", self.date_column.as_str()),
+            ("品目", self.item_column.as_str()),
+            ("金額", self.amount_column.as_str()),
+            ("分類", self.category_column.as_str()),
+            ("記録者", self.user_column.as_str()),
+            ("リンク", self.link_column.as_str()),
+            ("種別", self.kind_column.as_str()),
+            ("支払い方法", self.payment_column.as_str()),
+            ("支払った人", self.payer_column.as_str()),
+            ("レシート", self.receipt_column.as_str()),
+            ("医療を受けた人", self.patient_column.as_str()),
+            ("支払先", self.provider_column.as_str()),
+        ];
+        columns.retain(|(_, column)| !column.is_empty());
+        columns.sort_by_key(|(_, column)| column_index(column));
+        columns
+    }
+
+    /// 明細の列のうち一番右の列
+    pub fn last_column(&self) -> String {
+        let last = self
+            .columns()
+            .iter()
+            .map(|(_, column)| column_index(column))
+            .max()
+            .unwrap_or(0);
+        column_name(last)
+    }
+
+    /// 月の明細全体の範囲（例: `日々の記録（2026.09）!A16:E`）
+    pub fn entries_range(&self, month: Month) -> String {
+        format!(
+            "{}!A{}:{}",
+            self.sheet_name(month),
+            self.first_row,
+            self.last_column()
+        )
+    }
+
+    /// シートから読み込んだ1行を明細に変換する。日付も金額もない行は `None`
+    pub fn parse_row(&self, row_number: i64, row: &[Value], month: Month) -> Option<Entry> {
+        let cell = |column: &str| {
+            if column.is_empty() {
+                return String::new();
+            }
+            row.get(column_index(column))
+                .map(cell_to_string)
+                .unwrap_or_default()
+        };
+
+        let date = parse_date(&cell(&self.date_column), month);
+        let amount = parse_amount(&cell(&self.amount_column));
+        if date.is_none() && amount.is_none() {
+            return None;
+        }
+
+        Some(Entry {
+            row: row_number,
+            date,
+            item: cell(&self.item_column),
+            amount: amount.unwrap_or(0),
+            category: cell(&self.category_column),
+            user: cell(&self.user_column),
+            link: cell(&self.link_column),
+            kind: Kind::from_label(&cell(&self.kind_column)),
+            payment: cell(&self.payment_column),
+            payer: cell(&self.payer_column),
+            receipt: cell(&self.receipt_column),
+            patient: cell(&self.patient_column),
+            provider: cell(&self.provider_column),
+        })
+    }
+
+    /// 明細を書くのに要る列がなければ、その見出しを返す。書けない項目を記録したことにしないため。
+    /// 収入・振替と立て替えは、列がないと支出として読み戻してしまう。リンクは残せなくても記録する
+    pub fn missing_column(&self, entry: &Entry) -> Option<&'static str> {
+        if entry.kind != Kind::Expense && self.kind_column.is_empty() {
+            return Some("種別");
+        }
+        if !entry.payer.is_empty() && entry.payer != entry.user && self.payer_column.is_empty() {
+            return Some("支払った人");
+        }
+        [
+            ("支払い方法", &entry.payment, &self.payment_column),
+            ("レシート", &entry.receipt, &self.receipt_column),
+            ("医療を受けた人", &entry.patient, &self.patient_column),
+            ("支払先", &entry.provider, &self.provider_column),
+        ]
+        .into_iter()
+        .find(|(_, value, column)| !value.is_empty() && column.is_empty())
+        .map(|(name, _, _)| name)
+    }
+
+    /// 明細を A 列から始まる1行分の値にする。空の項目は `Null` にして既存のセルを残す
+    pub fn to_row(&self, entry: &Entry) -> Vec<Value> {
+        let mut row = vec![Value::Null; column_index(&self.last_column()) + 1];
+        let text = |s: &str| {
+            if s.is_empty() {
+                Value::Null
+            } else {
+                Value::String(s.to_string())
+            }
+        };
+
+        let mut set = |column: &str, value: Value| {
+            if !column.is_empty() {
+                row[column_index(column)] = value;
+            }
+        };
+
+        if let Some(date) = entry.date {
+            set(&self.date_column, Value::String(date.format("%Y/%m/%d").to_string()));
+        }
+        set(&self.item_column, Value::String(entry.item.clone()));
+        set(&self.amount_column, Value::Number(entry.amount.into()));
+        set(&self.category_column, text(&entry.category));
+        set(&self.user_column, Value::String(entry.user.clone()));
+        set(&self.link_column, text(&entry.link));
+        set(&self.kind_column, Value::String(entry.kind.label().to_string()));
+        set(&self.payment_column, text(&entry.payment));
+        set(&self.payer_column, text(&entry.payer));
+        set(&self.receipt_column, text(&entry.receipt));
+        set(&self.patient_column, text(&entry.patient));
+        set(&self.provider_column, text(&entry.provider));
+        row
+    }
+}
+
+/// 家計簿の1行
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// シート上の行番号
+    pub row: i64,
+    pub date: Option<NaiveDate>,
+    pub item: String,
+    pub amount: i64,
+    pub category: String,
+    pub user: String,
+    pub link: String,
+    pub kind: Kind,
+    pub payment: String,
+    /// 空なら記録者が支払ったもの
+    pub payer: String,
+    pub receipt: String,
+    pub patient: String,
+    pub provider: String,
+}
+
+impl Entry {
+    /// 支出（返金などの負の金額を含む）として集計する行か
+    pub fn is_expense(&self) -> bool {
+        self.kind == Kind::Expense
+    }
+
+    /// 実際に支払った人
+    pub fn payer(&self) -> &str {
+        if self.payer.is_empty() {
+            &self.user
+        } else {
+            &self.payer
+        }
+    }
+
+    /// 医療を受けた人。未入力なら記録者本人
+    pub fn patient(&self) -> &str {
+        if self.patient.is_empty() {
+            &self.user
+        } else {
+            &self.patient
+        }
+    }
+
+    /// 医療費の支払先。未入力なら品目名で代用する
+    pub fn provider(&self) -> &str {
+        if self.provider.is_empty() {
+            &self.item
+        } else {
+            &self.provider
+        }
+    }
+
+    /// 分類が未入力なら品目名で代用する
+    pub fn category_or_item(&self) -> &str {
+        if self.category.is_empty() {
+            &self.item
+        } else {
+            &self.category
+        }
+    }
+}
+
+/// 明細の種別
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    #[default]
+    Expense,
+    Income,
+    Transfer,
+}
+
+impl Kind {
+    pub fn label(self) -> &'static str {
+        match self {
+            Kind::Expense => "支出",
+            Kind::Income => "収入",
+            Kind::Transfer => "振替",
+        }
+    }
+
+    /// シートの表記を読む。空欄や知らない表記は支出とみなす（種別の列ができる前の行のため）
+    pub fn from_label(label: &str) -> Self {
+        match label.trim() {
+            "収入" | "income" => Kind::Income,
+            "振替" | "transfer" => Kind::Transfer,
+            _ => Kind::Expense,
+        }
+    }
+}
+
+/// 支出の合計。返金は差し引き、収入と振替は含めない
+pub fn total_spending(entries: &[Entry]) -> i64 {
+    entries.iter().filter(|e| e.is_expense()).map(|e| e.amount).sum()
+}
+
+/// 年月（シートの単位）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Month {
+    pub year: i32,
+    pub month: u32,
+}
+
+impl Month {
+    pub fn new(year: i32, month: u32) -> Option<Self> {
+        (1..=12).contains(&month).then_some(Self { year, month })
+    }
+
+    pub fn of(date: NaiveDate) -> Self {
+        Self { year: date.year(), month: date.month() }
+    }
+
+    pub fn prev(self) -> Self {
+        if self.month == 1 {
+            Self { year: self.year - 1, month: 12 }
+        } else {
+            Self { year: self.year, month: self.month - 1 }
+        }
+    }
+
+    pub fn next(self) -> Self {
+        if self.month == 12 {
+            Self { year: self.year + 1, month: 1 }
+        } else {
+            Self { year: self.year, month: self.month + 1 }
+        }
+    }
+
+    pub fn first_day(self) -> NaiveDate {
+        NaiveDate::from_ymd_opt(self.year, self.month, 1).unwrap()
+    }
+
+    pub fn days(self) -> u32 {
+        self.next().first_day().signed_duration_since(self.first_day()).num_days() as u32
+    }
+}
+
+impl fmt::Display for Month {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{:02}", self.year, self.month)
+    }
+}
+
+impl FromStr for Month {
+    type Err = anyhow::Error;
+
+    /// `2026-09`、`2026/9`、`2026.09` を受け付ける
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow::anyhow!("年月は 2026-09 の形式で指定してください: {}", s);
+        let (year, month) = s.trim().split_once(['-', '/', '.']).ok_or_else(invalid)?;
+        let year = year.parse().map_err(|_| invalid())?;
+        let month = month.parse().map_err(|_| invalid())?;
+        Month::new(year, month).ok_or_else(invalid)
+    }
+}
+
+/// `A` → 0, `E` → 4, `AA` → 26
+pub fn column_index(column: &str) -> usize {
+    column
+        .trim()
+        .to_ascii_uppercase()
+        .bytes()
+        .filter(u8::is_ascii_uppercase)
+        .fold(0, |acc, b| acc * 26 + (b - b'A' + 1) as usize)
+        .saturating_sub(1)
+}
+
+/// 0 → `A`, 26 → `AA`
+pub fn column_name(index: usize) -> String {
+    let mut n = index + 1;
+    let mut name = Vec::new();
+    while n > 0 {
+        let rem = (n - 1) % 26;
+        name.push(b'A' + rem as u8);
+        n = (n - 1) / 26;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap()
+}
+
+pub fn cell_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.trim().to_string(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// 表示形式の金額（`¥1,200`、`-300` など）を整数にする
+pub fn parse_amount(s: &str) -> Option<i64> {
+    let negative = s.trim_start().starts_with(['-', '−', '▲']);
+    let digits: String = s.chars().filter(char::is_ascii_digit).collect();
+    let amount: i64 = digits.parse().ok()?;
+    Some(if negative { -amount } else { amount })
+}
+
+/// シートの日付表記を読む。年が省略されていれば `month` の年で補う
+pub fn parse_date(s: &str, month: Month) -> Option<NaiveDate> {
+    let s = s.trim();
+    for format in ["%Y/%m/%d", "%Y-%m-%d", "%Y.%m.%d", "%Y年%m月%d日"] {
+        if let Ok(date) = NaiveDate::parse_from_str(s, format) {
+            return Some(date);
+        }
+    }
+    for format in ["%m/%d", "%m月%d日"] {
+        let with_year = format!("{}/{}", month.year, s);
+        if let Ok(date) = NaiveDate::parse_from_str(&with_year, &format!("%Y/{}", format)) {
+            return Some(date);
+        }
+    }
+    None
+}
+
+/// 12345 → `12,345円`
+pub fn format_yen(amount: i64) -> String {
+    let digits = amount.unsigned_abs().to_string();
+    let mut grouped = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    format!("{}{}円", if amount < 0 { "-" } else { "" }, grouped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn parses_dates_with_and_without_year() {
+        let month = Month::new(2026, 9).unwrap();
+        assert_eq!(parse_date("2026/09/01", month), Some(date(2026, 9, 1)));
+        assert_eq!(parse_date("2025-12-31", month), Some(date(2025, 12, 31)));
+        assert_eq!(parse_date("2026.9.5", month), Some(date(2026, 9, 5)));
+        assert_eq!(parse_date("2026年9月5日", month), Some(date(2026, 9, 5)));
+        assert_eq!(parse_date(" 9/5 ", month), Some(date(2026, 9, 5)));
+        assert_eq!(parse_date("9月5日", month), Some(date(2026, 9, 5)));
+        assert_eq!(parse_date("2/30", month), None);
+        assert_eq!(parse_date("", month), None);
+    }
+
+    #[test]
+    fn parses_displayed_amounts() {
+        assert_eq!(parse_amount("¥1,200"), Some(1200));
+        assert_eq!(parse_amount("1,200円"), Some(1200));
+        assert_eq!(parse_amount("-300"), Some(-300));
+        assert_eq!(parse_amount("▲300"), Some(-300));
+        assert_eq!(parse_amount("−1,000"), Some(-1000));
+        assert_eq!(parse_amount(""), None);
+        assert_eq!(parse_amount("なし"), None);
+    }
+
+    #[test]
+    fn converts_column_names() {
+        assert_eq!(column_index("A"), 0);
+        assert_eq!(column_index("e"), 4);
+        assert_eq!(column_index("Z"), 25);
+        assert_eq!(column_index("AA"), 26);
+        assert_eq!(column_index(" AZ "), 51);
+        for index in [0, 25, 26, 51, 701, 702] {
+            assert_eq!(column_index(&column_name(index)), index);
+        }
+        assert_eq!(column_name(701), "ZZ");
+        assert_eq!(column_name(702), "AAA");
+    }
+
+    #[test]
+    fn steps_months_across_years() {
+        let january = Month::new(2026, 1).unwrap();
+        assert_eq!(january.prev(), Month::new(2025, 12).unwrap());
+        assert_eq!(Month::new(2025, 12).unwrap().next(), january);
+        assert_eq!(Month::new(2024, 2).unwrap().days(), 29);
+        assert_eq!(Month::new(2026, 2).unwrap().days(), 28);
+        assert_eq!(Month::new(2026, 12).unwrap().days(), 31);
+        assert_eq!(Month::of(date(2026, 9, 30)), Month::new(2026, 9).unwrap());
+        assert!(Month::new(2026, 13).is_none());
+    }
+
+    #[test]
+    fn parses_and_formats_months() {
+        assert_eq!("2026-09".parse::<Month>().unwrap(), Month::new(2026, 9).unwrap());
+        assert_eq!("2026/9".parse::<Month>().unwrap(), Month::new(2026, 9).unwrap());
+        assert_eq!(" 2026.12 ".parse::<Month>().unwrap(), Month::new(2026, 12).unwrap());
+        assert!("2026-13".parse::<Month>().is_err());
+        assert!("202609".parse::<Month>().is_err());
+        assert_eq!(Month::new(2026, 9).unwrap().to_string(), "2026-09");
+    }
+
+    fn entry(kind: Kind, payer: &str) -> Entry {
+        Entry {
+            row: 0,
+            date: NaiveDate::from_ymd_opt(2026, 9, 1),
+            item: "ランチ".to_string(),
+            amount: 980,
+            category: String::new(),
+            user: "太郎".to_string(),
+            link: "https://discord.com/channels/1/2/3".to_string(),
+            kind,
+            payment: String::new(),
+            payer: payer.to_string(),
+            receipt: String::new(),
+            patient: String::new(),
+            provider: String::new(),
+        }
+    }
+
+    #[test]
+    fn default_layout_writes_link_and_payment_only() {
+        let layout = Layout::default();
+        assert_eq!(layout.last_column(), "H");
+        let names: Vec<&str> = layout.columns().iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["日付", "品目", "金額", "分類", "記録者", "リンク", "支払い方法"]);
+        let row = layout.to_row(&Entry { payment: "楽天カード".to_string(), ..entry(Kind::Expense, "") });
+        assert_eq!(row.len(), 8);
+        assert_eq!(row[column_index("F")], Value::String("https://discord.com/channels/1/2/3".to_string()));
+        // 使わない列のセルはそのまま残す
+        assert_eq!(row[column_index("G")], Value::Null);
+        assert_eq!(row[column_index("H")], Value::String("楽天カード".to_string()));
+        // 使わない列は A 列として読まない
+        let values: Vec<Value> = ["2026/09/01", "ランチ", "980", "", "太郎"].iter().map(|v| Value::String(v.to_string())).collect();
+        let parsed = layout.parse_row(16, &values, Month::new(2026, 9).unwrap()).unwrap();
+        assert_eq!((parsed.kind, parsed.receipt.as_str(), parsed.payer()), (Kind::Expense, "", "太郎"));
+    }
+
+    #[test]
+    fn optional_columns_need_to_be_configured() {
+        let layout = Layout::default();
+        assert_eq!(layout.missing_column(&entry(Kind::Expense, "")), None);
+        assert_eq!(layout.missing_column(&entry(Kind::Income, "")), Some("種別"));
+        assert_eq!(layout.missing_column(&entry(Kind::Expense, "花子")), Some("支払った人"));
+        let receipt = Entry { receipt: "https://discord.com/channels/1/4/5".to_string(), ..entry(Kind::Expense, "") };
+        assert_eq!(layout.missing_column(&receipt), Some("レシート"));
+        let medical = Entry { patient: "花子".to_string(), provider: "中央病院".to_string(), ..entry(Kind::Expense, "") };
+        assert_eq!(layout.missing_column(&medical), Some("医療を受けた人"));
+        let no_payment = Layout { payment_column: String::new(), ..Layout::default() };
+        assert_eq!(no_payment.missing_column(&Entry { payment: "現金".to_string(), ..entry(Kind::Expense, "") }), Some("支払い方法"));
+        // リンクは残せなくても記録する
+        let no_link = Layout { link_column: String::new(), ..Layout::default() };
+        assert_eq!(no_link.missing_column(&entry(Kind::Expense, "")), None);
+
+        let layout = Layout {
+            kind_column: "G".to_string(),
+            payer_column: "I".to_string(),
+            receipt_column: "J".to_string(),
+            patient_column: "K".to_string(),
+            provider_column: "L".to_string(),
+            ..Layout::default()
+        };
+        assert_eq!(layout.missing_column(&entry(Kind::Income, "花子")), None);
+        assert_eq!(layout.missing_column(&Entry { receipt: "x".to_string(), ..medical }), None);
+        let row = layout.to_row(&entry(Kind::Income, "花子"));
+        assert_eq!(layout.last_column(), "L");
+        assert_eq!(row[column_index("G")], Value::String("収入".to_string()));
+        assert_eq!(row[column_index("I")], Value::String("花子".to_string()));
+    }
+
+    #[test]
+    fn formats_yen() {
+        assert_eq!(format_yen(0), "0円");
+        assert_eq!(format_yen(999), "999円");
+        assert_eq!(format_yen(12345), "12,345円");
+        assert_eq!(format_yen(-1234567), "-1,234,567円");
+    }
+}
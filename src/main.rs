@@ -1,22 +1,72 @@
 mod spreadsheet;
 mod http_server;
+mod ledger;
+mod chart;
+mod export;
+mod statement;
+mod interop;
+mod parser;
+mod amount;
+mod payment;
+mod split;
+mod receipt;
+mod medical;
+mod routing;
+mod members;
+mod storage;
+mod permission;
+mod audit;
+mod service;
+mod sink;
+mod recording;
+mod pending;
+mod webhook;
+mod health;
+mod events;
+mod summary;
+mod dashboard;
+mod telemetry;
+mod expenses_api;
+mod auth;
+mod config;
+mod commands;
 
 use anyhow::Context as _;
 use serenity::async_trait;
 use serenity::model::channel::Message;
 use serenity::prelude::*;
 use shuttle_runtime::SecretStore;
-use tracing::error;
+use tracing::{error, warn};
 use poise::serenity_prelude as serenity;
-use chrono::Local;
-use regex;
-use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use spreadsheet::Book;
+use ledger::Entry;
+use recording::Draft;
+use pending::{Decision, PendingExpense, PendingExpenses};
+use config::Config;
+use routing::Ledgers;
+use members::Members;
+use permission::{Permissions, Role};
+use audit::{Actor, AuditLog};
+use service::BotService;
+use auth::Auth;
+use http_server::{AppState, Messaging};
+use sink::SerenitySink;
+use health::Health;
+use events::EventBus;
+use dashboard::Sessions;
 
-struct Data {} // User data, which is stored and accessible in all command invocations
+// User data, which is stored and accessible in all command invocations
+struct Data {
+    ledgers: Arc<Ledgers>,
+    members: Arc<Members>,
+    permissions: Arc<Permissions>,
+    audit: Arc<AuditLog>,
+    dashboard: Arc<Sessions>,
+    config: Arc<Config>,
+}
 type Error = Box<dyn std::error::Error + Send + Sync>;
 type Context<'a> = poise::Context<'a, Data, Error>;
 
@@ -36,48 +86,189 @@ async fn age(
 struct Bot {
     channel_id: serenity::model::id::ChannelId,
     expenses_channel_id: serenity::model::id::ChannelId,
-    book: Book,
+    ledgers: Arc<Ledgers>,
+    members: Arc<Members>,
+    permissions: Arc<Permissions>,
+    pending: Arc<PendingExpenses>,
+    config: Arc<Config>,
+    /// 一度でもつながったシャード。2回目からはつなぎ直しとして数える
+    connected_shards: std::sync::Mutex<HashSet<u32>>,
 }
 
 impl Bot {
-    async fn write_expenses(&self, msg: Message) -> Result<(), anyhow::Error> {
-        let input_text = msg.content;
-        let re = regex::Regex::new(r"([^\d]+)\s*(\d+)").unwrap();
-        let text_part: String;
-        let parsed_number: i32;
-
-        let captures = match re.captures(input_text.as_str()) {
-            Some(captures) => captures,
-            None => return Err(anyhow::anyhow!("Invalid input format")),
-        };
-        text_part = captures.get(1).map(|m| m.as_str()).unwrap_or("").to_string();
-        parsed_number = captures.get(2).map(|m| m.as_str()).unwrap_or("0").parse().unwrap_or(0);
+    /// 割り当てのあるチャンネル（スレッド）と各サーバーの記録用チャンネルの家計簿
+    async fn book_for(&self, http: &serenity::Http, guild_id: Option<serenity::GuildId>, channel_id: serenity::ChannelId) -> Option<Arc<Book>> {
+        if channel_id == self.expenses_channel_id {
+            Some(self.ledgers.get(channel_id).unwrap_or_else(|| self.ledgers.default()))
+        } else {
+            self.ledgers.for_message(http, guild_id, channel_id).await
+        }
+    }
 
-        let today = Local::now().format("%Y/%m/%d").to_string();
-        let user_name = match msg.author.id {
-            id if self.book.users.0.contains_key(&id.into()) => self.book.users.0[&id.into()].clone(),
-            _ => "".to_string(),
+    /// 記録した行と、金額を計算で求めたときはその式を返す
+    async fn write_expenses(&self, ctx: &serenity::Context, book: &Book, msg: Message) -> Result<(Vec<Entry>, Option<String>), anyhow::Error> {
+        let users = &self.members.directory(msg.guild_id, book);
+
+        // 未登録の人は Discord の表示名で記録する
+        let user_name = match users.get(&msg.author.id.get()) {
+            Some(name) => name.clone(),
+            None => msg.author.display_name().to_string(),
         };
+        let draft = Draft::new(&msg.content, &self.config, users, &user_name)?;
+        let expression = draft.expression();
+
+        // 添付ファイルは保管用チャンネルに移して、そのリンクを行に残す
+        // ほかのサーバーのチャンネルには保管しない
+        let receipt_channel_id = msg
+            .guild_id
+            .and_then(|guild_id| self.ledgers.receipt_channel(guild_id, self.config.receipt_channel_id));
+        let mut receipt = String::new();
+        // レシートの列がない家計簿では、書き込めないリンクのために保管しない
+        let has_receipt_column = !book.layout.receipt_column.is_empty();
+        if let (Some(channel), false, true) = (receipt_channel_id, msg.attachments.is_empty(), has_receipt_column) {
+            let caption = format!("{} {} {}", book.today().format("%Y/%m/%d"), draft.parsed.item, user_name);
+            match receipt::archive(&ctx.http, channel, &msg, &caption).await {
+                Ok(link) => receipt = link,
+                Err(e) => error!("Error archiving receipt: {:?}", e),
+            }
+        }
+
+        let entries = draft.into_entries(book.today(), msg.link(), receipt)?;
+        // 書き込めなかったときは記録したと返信しない
+        book.append_entries(&entries, &Actor::from_message(&msg)).await.map_err(|e| {
+            telemetry::expense_rejected("sheets");
+            error!("Error writing to spreadsheet: {:?}", e);
+            anyhow::anyhow!(e)
+        })?;
+
+        Ok((entries, expression))
+    }
+
+    /// 記録者名が未登録の人に、一度だけ表示名での登録を勧める
+    async fn prompt_registration(&self, ctx: &serenity::Context, msg: &Message, guild_id: serenity::GuildId) -> Result<(), anyhow::Error> {
+        if !self.members.mark_prompted(guild_id, msg.author.id).map_err(|e| anyhow::anyhow!(e))? {
+            return Ok(());
+        }
 
-        let values: Vec<Vec<Value>> = vec![vec![
-            Value::String(today),
-            Value::String(text_part),
-            Value::Number(parsed_number.into()),
-            Value::Null,
-            Value::String(user_name),
-        ]];
-        let year = Local::now().format("%Y").to_string();
-        let month = Local::now().format("%m").to_string();
-        let range = format!("日々の記録（{}.{}）!A16:C", year, month);
-        let row = self.book.get_last_row(&range).await.unwrap() + 16;
-
-        let range = format!("日々の記録（{}.{}）!A{}", year, month, row);
-        if let Err(e) = self.book.write_text(&range, values).await {
-            eprintln!("Error writing to spreadsheet: {:?}", e);
+        let name = msg.author.display_name().to_string();
+        let register_id = format!("{}-register", msg.id);
+        let content = format!(
+            "記録者名が登録されていないため「{}」として記録しました。この名前で登録するならボタンを押してください。別の名前にするときは /member add で登録できます",
+            name
+        );
+        let buttons = vec![serenity::CreateActionRow::Buttons(vec![
+            serenity::CreateButton::new(&register_id).label(format!("「{}」で登録", name)),
+        ])];
+        let mut prompt = msg
+            .channel_id
+            .send_message(&ctx.http, serenity::CreateMessage::new().content(content).components(buttons).reference_message(msg))
+            .await?;
+
+        let interaction = serenity::ComponentInteractionCollector::new(ctx)
+            .author_id(msg.author.id)
+            .message_id(prompt.id)
+            .timeout(std::time::Duration::from_secs(600))
+            .await;
+        match interaction {
+            Some(mci) => {
+                let reply = match self.members.add(guild_id, msg.author.id, &name, &Actor::from_message(msg)) {
+                    Ok(_) => format!("「{}」として登録しました", name),
+                    Err(e) => format!("登録できませんでした: {}", e),
+                };
+                let response = serenity::CreateInteractionResponseMessage::new().content(reply).components(vec![]);
+                mci.create_response(&ctx.http, serenity::CreateInteractionResponse::UpdateMessage(response)).await?;
+            }
+            None => {
+                prompt.edit(&ctx.http, serenity::EditMessage::new().components(vec![])).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Webhook で受け取った支出の確認ボタンを処理する。記録の権限がある人だけが押せる
+    async fn resolve_pending(
+        &self,
+        ctx: &serenity::Context,
+        mci: &serenity::ComponentInteraction,
+        id: u64,
+        decision: Decision,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(guild_id) = mci.guild_id {
+            let member = mci.member.as_ref();
+            let roles = member.map(|m| m.roles.clone()).unwrap_or_default();
+            let manages_guild = member.and_then(|m| m.permissions).is_some_and(|p| p.manage_guild());
+            let role = self.permissions.role_of(guild_id, mci.user.id, &roles, manages_guild);
+            if role < Some(Role::Recorder) {
+                warn!(
+                    "permission denied: user {} ({}) pressed a pending expense button in channel {} with role {:?}",
+                    mci.user.name, mci.user.id, mci.channel_id, role
+                );
+                let response = serenity::CreateInteractionResponseMessage::new()
+                    .content("記録する権限がありません。管理者に「記録」の権限をもらってください")
+                    .ephemeral(true);
+                mci.create_response(&ctx.http, serenity::CreateInteractionResponse::Message(response)).await?;
+                return Ok(());
+            }
+        }
+        // スプレッドシートへの書き込みは時間がかかるので、先に応答しておく
+        mci.create_response(&ctx.http, serenity::CreateInteractionResponse::Acknowledge).await?;
+
+        let actor = Actor {
+            user_id: Some(mci.user.id.get()),
+            user_name: mci.user.name.clone(),
+            guild_id: mci.guild_id.map(|id| id.get()),
+            message_id: Some(mci.message.id.get()),
+        };
+        let action = match decision {
+            Decision::Confirm => "pending.confirm",
+            Decision::Discard => "pending.discard",
+        };
+        // ほかの人が先に押していれば、ボタンを消すだけにする
+        let Some(expense) = self.pending.take(id, action, &actor).map_err(|e| anyhow::anyhow!(e))? else {
+            mci.edit_response(&ctx.http, serenity::EditInteractionResponse::new().components(vec![])).await?;
+            return Ok(());
         };
 
+        let notice = mci.message.content.lines().next().unwrap_or_default().to_string();
+        let content = match decision {
+            Decision::Discard => format!("{}\n{} が取り消しました", notice, mci.user.display_name()),
+            Decision::Confirm => match self.record_pending(ctx, mci, &expense, &actor).await {
+                Ok(reply) => reply,
+                Err(e) => {
+                    self.pending.restore(expense).map_err(|e| anyhow::anyhow!(e))?;
+                    let followup = serenity::CreateInteractionResponseFollowup::new()
+                        .content(format!("記録できませんでした: {}", e))
+                        .ephemeral(true);
+                    mci.create_followup(&ctx.http, followup).await?;
+                    return Ok(());
+                }
+            },
+        };
+        mci.edit_response(&ctx.http, serenity::EditInteractionResponse::new().content(content).components(vec![])).await?;
         Ok(())
     }
+
+    /// 確認された支出を、ボタンを押した人を記録者にして書き込む
+    async fn record_pending(
+        &self,
+        ctx: &serenity::Context,
+        mci: &serenity::ComponentInteraction,
+        expense: &PendingExpense,
+        actor: &Actor,
+    ) -> Result<String, anyhow::Error> {
+        let book = self
+            .book_for(&ctx.http, mci.guild_id, mci.channel_id)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("このチャンネルには家計簿が割り当てられていません"))?;
+        let user_name = self
+            .members
+            .directory(mci.guild_id, &book)
+            .remove(&mci.user.id.get())
+            .unwrap_or_else(|| mci.user.display_name().to_string());
+        let entry = expense.to_entry(&user_name, book.today(), mci.message.link());
+        let written = book.append_entries(&[entry], actor).await.map_err(|e| anyhow::anyhow!(e))?;
+        Ok(format!("{}（{} の通知を {} が確認）", recording::announcement(&written, None), expense.source, mci.user.display_name()))
+    }
 }
 
 #[async_trait]
@@ -94,19 +285,70 @@ impl EventHandler for Bot {
             }
         }
 
-        if msg.channel_id == self.expenses_channel_id {
-            match self.write_expenses(msg.clone()).await {
-                Ok(_) => {
-                    if let Err(e) = msg.reply(&ctx.http, format!("記録しました")).await {
+        // 割り当てのあるチャンネル（スレッド）と各サーバーの記録用チャンネルの発言を記録する
+        if let Some(book) = self.book_for(&ctx.http, msg.guild_id, msg.channel_id).await {
+            if let Some(guild_id) = msg.guild_id {
+                let roles = msg.member.as_ref().map(|m| m.roles.clone()).unwrap_or_default();
+                let role = self
+                    .permissions
+                    .role_of_member(&ctx.http, guild_id, msg.author.id, &roles, Role::Recorder)
+                    .await;
+                if role < Some(Role::Recorder) {
+                    warn!(
+                        "permission denied: user {} ({}) posted an expense in channel {} with role {:?}",
+                        msg.author.name, msg.author.id, msg.channel_id, role
+                    );
+                    telemetry::message_handled("rejected");
+                    telemetry::expense_rejected("permission");
+                    if let Err(e) = msg.reply(&ctx.http, "記録する権限がありません。管理者に「記録」の権限をもらってください").await {
                         error!("Error sending reply: {:?}", anyhow::Error::new(e));
                     }
+                    return;
+                }
+            }
+            match self.write_expenses(&ctx, &book, msg.clone()).await {
+                Ok((entries, expression)) => {
+                    telemetry::message_handled("recorded");
+                    let reply = recording::announcement(&entries, expression.as_deref());
+                    if let Err(e) = msg.reply(&ctx.http, reply).await {
+                        error!("Error sending reply: {:?}", anyhow::Error::new(e));
+                    }
+                    if let Some(guild_id) = msg.guild_id {
+                        if !self.members.directory(Some(guild_id), &book).contains_key(&msg.author.id.get()) {
+                            if let Err(e) = self.prompt_registration(&ctx, &msg, guild_id).await {
+                                error!("Error prompting registration: {:?}", e);
+                            }
+                        }
+                    }
                 },
                 Err(e) => {
+                    telemetry::message_handled("rejected");
                     if let Err(e) = msg.reply(&ctx.http, format!("エラーが発生しました: {:?}", e)).await {
                         error!("Error sending reply: {:?}", anyhow::Error::new(e));
                     }
                 }
             }
+        } else {
+            telemetry::message_handled("ignored");
+        }
+    }
+
+    async fn interaction_create(&self, ctx: serenity::Context, interaction: serenity::Interaction) {
+        // Webhook の確認ボタンだけを扱う。コマンドとその中のボタンは poise が処理する
+        let serenity::Interaction::Component(mci) = interaction else {
+            return;
+        };
+        let Some((id, decision)) = PendingExpense::parse_custom_id(&mci.data.custom_id) else {
+            return;
+        };
+        if let Err(e) = self.resolve_pending(&ctx, &mci, id, decision).await {
+            error!("Error resolving pending expense: {:?}", e);
+        }
+    }
+
+    async fn shard_stage_update(&self, _ctx: serenity::Context, event: serenity::ShardStageUpdateEvent) {
+        if event.new == serenity::ConnectionStage::Connected && !self.connected_shards.lock().unwrap().insert(event.shard_id.0) {
+            telemetry::gateway_reconnected(event.shard_id.0);
         }
     }
 
@@ -135,7 +377,7 @@ impl EventHandler for Bot {
 #[shuttle_runtime::main]
 async fn serenity(
     #[shuttle_runtime::Secrets] secrets: SecretStore,
-) -> shuttle_serenity::ShuttleSerenity {
+) -> Result<BotService, shuttle_runtime::Error> {
     let token = secrets
         .get("DISCORD_TOKEN")
         .context("'DISCORD_TOKEN' was not found")?;
@@ -162,41 +404,135 @@ async fn serenity(
         .get("GOOGLE_CREDENTIALS_JSON")
         .context("'GOOGLE_CREDENTIALS_JSON' was not found")?;
 
-    let user_id_map = secrets
+    // 以前の記録者名の対応。今は /member で管理し、最初の起動時にだけ取り込む
+    let users: HashMap<u64, String> = secrets
         .get("USER_ID_MAP")
-        .context("'USER_ID_MAP' was not found")?;
-    let users: HashMap<u64, String> = serde_json::from_str(&user_id_map).unwrap();
-    let book = Book::new(expenses_spreadsheet_id, users, credentials);
+        .map(|v| serde_json::from_str(&v))
+        .transpose()
+        .context("'USER_ID_MAP' is not valid JSON")?
+        .unwrap_or_default();
+    let config = Arc::new(Config::from_secrets(&secrets)?);
+    let metrics = telemetry::install().context("failed to install the metrics recorder")?;
+    let audit = Arc::new(AuditLog::new(config.data_dir.join("audit")));
+    let health = Arc::new(Health::default());
+    let events = Arc::new(EventBus::new(config.monthly_budget));
+    let mut book = Book::new(expenses_spreadsheet_id, HashMap::new(), credentials.clone());
+    book.layout = config.ledger_layout.clone();
+    book.audit = Some(audit.clone());
+    book.health = Some(health.clone());
+    book.events = Some(events.clone());
+    let book = Arc::new(book);
+    let ledgers = Ledgers::new(
+        book,
+        credentials,
+        config.ledger_routes.clone(),
+        config.data_dir.clone(),
+        audit.clone(),
+        health.clone(),
+        events.clone(),
+    )
+    .map_err(|e| anyhow::anyhow!("failed to load ledger routes: {}", e))?;
+    let ledgers = Arc::new(ledgers);
+    let members = Members::load(config.data_dir.join("members.json"), audit.clone())
+        .map_err(|e| anyhow::anyhow!("failed to load members: {}", e))?;
+    let members = Arc::new(members);
+    let permissions = Permissions::load(config.data_dir.join("permissions.json"), audit.clone())
+        .map_err(|e| anyhow::anyhow!("failed to load permissions: {}", e))?;
+    let permissions = Arc::new(permissions);
+    let pending = PendingExpenses::load(config.data_dir.join("pending.json"), audit.clone())
+        .map_err(|e| anyhow::anyhow!("failed to load pending expenses: {}", e))?;
+    let pending = Arc::new(pending);
+    let dashboard = Sessions::load(config.data_dir.join("dashboard.json"), audit.clone())
+        .map_err(|e| anyhow::anyhow!("failed to load dashboard sessions: {}", e))?;
+    let dashboard = Arc::new(dashboard);
 
     let intents = GatewayIntents::GUILD_MESSAGES | GatewayIntents::MESSAGE_CONTENT;
 
     let framework = poise::Framework::builder()
         .options(poise::FrameworkOptions {
-            commands: vec![age()],
+            commands: vec![
+                age(),
+                commands::chart::chart(),
+                commands::export::export(),
+                commands::import::import(),
+                commands::migrate::migrate(),
+                commands::cards::cards(),
+                commands::receipt::receipt(),
+                commands::tax::tax(),
+                commands::ledger::ledger(),
+                commands::setup::setup(),
+                commands::member::member(),
+                commands::role::role(),
+                commands::audit::audit(),
+                commands::dashboard::dashboard(),
+            ],
             ..Default::default()
         })
-        .setup(|ctx, _ready, framework| {
-            Box::pin(async move {
-                poise::builtins::register_globally(ctx, &framework.options().commands).await?;
-                Ok(Data {})
-            })
+        .setup({
+            let ledgers = ledgers.clone();
+            let members = members.clone();
+            let permissions = permissions.clone();
+            let audit = audit.clone();
+            let dashboard = dashboard.clone();
+            let config = config.clone();
+            move |ctx, _ready, framework| {
+                Box::pin(async move {
+                    poise::builtins::register_globally(ctx, &framework.options().commands).await?;
+                    // シークレットの家計簿は EXPENSES_CHANNEL_ID のあるサーバーでだけ使う
+                    match expenses_channel_id.to_channel(ctx).await {
+                        Ok(serenity::Channel::Guild(channel)) => {
+                            ledgers.set_home_guild(channel.guild_id);
+                            members.seed(channel.guild_id, &users)?;
+                        }
+                        Ok(_) => warn!("EXPENSES_CHANNEL_ID is not a guild channel"),
+                        Err(e) => warn!("failed to fetch EXPENSES_CHANNEL_ID: {:?}", e),
+                    }
+                    Ok(Data { ledgers, members, permissions, audit, dashboard, config })
+                })
+            }
         })
         .build();
 
+    let http_addr = config.http_addr;
+    let auth = Arc::new(Auth::new(config.api_keys.clone(), config.webhook_secrets.clone()));
+    if auth.is_empty() {
+        warn!("API_KEYS も WEBHOOK_SECRETS もないため、/send-message などの HTTP API は全て拒否します");
+    }
+    let bot = Bot {
+        channel_id,
+        expenses_channel_id,
+        ledgers: ledgers.clone(),
+        members: members.clone(),
+        permissions: permissions.clone(),
+        pending: pending.clone(),
+        config: config.clone(),
+        connected_shards: Default::default(),
+    };
     let client = serenity::ClientBuilder::new(token, intents)
         .framework(framework)
-        .event_handler(Bot { channel_id, expenses_channel_id, book })
+        .event_handler(bot)
         .await
-        .unwrap();
-
-    // HTTPサーバーとDiscordクライアントで共有するコンテキスト
-    let http_context = client.http.clone();
-    let shared_channel_id = channel_id;
-
-    // HTTPサーバーを別スレッドで起動
-    tokio::spawn(async move {
-        http_server::start_server(http_context, shared_channel_id).await;
-    });
+        .context("failed to build the Discord client")?;
 
-    Ok(client.into())
+    // HTTPサーバーは Shuttle がサービスを起動するときに一緒に立ち上げる
+    let state = AppState {
+        http: client.http.clone(),
+        shard_manager: client.shard_manager.clone(),
+        health,
+        metrics,
+        events,
+        dashboard,
+        messaging: Messaging {
+            sink: Arc::new(SerenitySink::new(client.http.clone())),
+            default_channel: channel_id,
+            allowed_channels: config.http_allowed_channels.clone(),
+        },
+        expenses_channel_id,
+        ledgers,
+        members,
+        permissions,
+        pending,
+        config,
+    };
+    Ok(BotService { client, state, http_addr, auth })
 }
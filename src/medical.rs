@@ -0,0 +1,103 @@
+use crate::export::UTF8_BOM;
+use crate::ledger::{Entry, Kind};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// 医療費集計フォームの「医療費の区分」
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MedicalKind {
+    /// 診療・治療
+    Treatment,
+    /// 医薬品購入
+    Medicine,
+    /// 介護保険サービス
+    Care,
+    /// その他の医療費
+    Other,
+}
+
+impl MedicalKind {
+    const ALL: [MedicalKind; 4] = [MedicalKind::Treatment, MedicalKind::Medicine, MedicalKind::Care, MedicalKind::Other];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            MedicalKind::Treatment => "診療・治療",
+            MedicalKind::Medicine => "医薬品購入",
+            MedicalKind::Care => "介護保険サービス",
+            MedicalKind::Other => "その他の医療費",
+        }
+    }
+}
+
+/// 医療費として扱う分類と、その区分。`MEDICAL_CATEGORIES` がなければこれを使う
+pub fn default_categories() -> BTreeMap<String, MedicalKind> {
+    BTreeMap::from([
+        ("医療費".to_string(), MedicalKind::Treatment),
+        ("薬".to_string(), MedicalKind::Medicine),
+    ])
+}
+
+/// 医療を受けた人・支払先・区分ごとの1年間の合計
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MedicalRecord {
+    pub patient: String,
+    pub provider: String,
+    pub kind: MedicalKind,
+    /// 支払った医療費の金額
+    pub paid: i64,
+    /// 保険金などで補填される金額
+    pub reimbursed: i64,
+}
+
+/// 医療費の分類の行を集計する。支出（返金を含む）は支払額に、収入は補填額に数える
+pub fn summarize(entries: &[Entry], categories: &BTreeMap<String, MedicalKind>) -> Vec<MedicalRecord> {
+    let mut totals: BTreeMap<(String, String, MedicalKind), (i64, i64)> = BTreeMap::new();
+    for entry in entries {
+        let kind = match categories.get(entry.category_or_item()) {
+            Some(kind) => *kind,
+            None => continue,
+        };
+        let key = (entry.patient().to_string(), entry.provider().to_string(), kind);
+        let (paid, reimbursed) = totals.entry(key).or_default();
+        match entry.kind {
+            Kind::Expense => *paid += entry.amount,
+            Kind::Income => *reimbursed += entry.amount,
+            Kind::Transfer => {}
+        }
+    }
+
+    totals
+        .into_iter()
+        .map(|((patient, provider, kind), (paid, reimbursed))| MedicalRecord {
+            patient,
+            provider,
+            kind,
+            paid,
+            reimbursed,
+        })
+        .collect()
+}
+
+/// 国税庁の「医療費集計フォーム」と同じ列の並びで CSV にする
+pub fn to_csv(records: &[MedicalRecord]) -> Result<Vec<u8>, Error> {
+    let mut writer = csv::Writer::from_writer(UTF8_BOM.to_vec());
+    let mut header = vec!["医療を受けた人", "病院・薬局などの名称"];
+    header.extend(MedicalKind::ALL.iter().map(|kind| kind.label()));
+    header.extend(["支払った医療費の金額", "左のうち、補填される金額"]);
+    writer.write_record(&header)?;
+
+    for record in records {
+        let mut row = vec![record.patient.clone(), record.provider.clone()];
+        row.extend(
+            MedicalKind::ALL
+                .iter()
+                .map(|kind| if *kind == record.kind { "該当する" } else { "" }.to_string()),
+        );
+        row.extend([record.paid.to_string(), record.reimbursed.to_string()]);
+        writer.write_record(&row)?;
+    }
+    Ok(writer.into_inner().map_err(|e| e.to_string())?)
+}
@@ -0,0 +1,161 @@
+use crate::audit::{Actor, AuditLog, AuditRecord};
+use crate::spreadsheet::Book;
+use crate::storage::{load_json, save_json};
+use poise::serenity_prelude::{GuildId, UserId};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Directory {
+    /// サーバーごとの Discord のユーザー ID と記録者名
+    #[serde(default)]
+    guilds: BTreeMap<u64, BTreeMap<u64, String>>,
+    /// 登録を案内したことのある人
+    #[serde(default)]
+    prompted: BTreeMap<u64, BTreeSet<u64>>,
+}
+
+/// サーバーごとの記録者名。`/member` で変更し、ファイルに保存する
+pub struct Members {
+    path: PathBuf,
+    directory: RwLock<Directory>,
+    audit: Arc<AuditLog>,
+}
+
+impl Members {
+    pub fn load(path: PathBuf, audit: Arc<AuditLog>) -> Result<Self, Error> {
+        let directory = load_json(&path)?;
+        Ok(Self { path, directory: RwLock::new(directory), audit })
+    }
+
+    /// 変更した写しを保存できたときだけ、手元の内容を置き換える
+    fn commit(&self, directory: &mut Directory, updated: Directory) -> Result<(), Error> {
+        save_json(&self.path, &updated)?;
+        *directory = updated;
+        Ok(())
+    }
+
+    fn record(&self, actor: &Actor, action: &str, guild_id: GuildId, user_id: UserId) -> AuditRecord {
+        AuditRecord::new(actor, action, "members.json").range(format!("{}/{}", guild_id, user_id))
+    }
+
+    pub fn list(&self, guild_id: GuildId) -> BTreeMap<u64, String> {
+        self.directory.read().unwrap().guilds.get(&guild_id.get()).cloned().unwrap_or_default()
+    }
+
+    /// 記録に使う名前の対応。家計簿ごとの対応（`LEDGER_ROUTES` の `users`）があればそちらを優先する
+    pub fn directory(&self, guild_id: Option<GuildId>, book: &Book) -> HashMap<u64, String> {
+        let mut users: HashMap<u64, String> = guild_id.map(|id| self.list(id)).unwrap_or_default().into_iter().collect();
+        users.extend(book.users.0.clone());
+        users
+    }
+
+    /// 記録者名を登録する（登録済みなら変更する）。変更前の名前を返す
+    pub fn add(&self, guild_id: GuildId, user_id: UserId, name: &str, actor: &Actor) -> Result<Option<String>, Error> {
+        let result = self.try_add(guild_id, user_id, name);
+        let previous = result.as_ref().ok().cloned().flatten();
+        self.audit.record(
+            self.record(actor, "member.add", guild_id, user_id)
+                .old(previous)
+                .new_value(name.trim())
+                .result(&result),
+        );
+        result
+    }
+
+    fn try_add(&self, guild_id: GuildId, user_id: UserId, name: &str) -> Result<Option<String>, Error> {
+        let name = name.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) || name.starts_with(['@', '＠', '<']) {
+            return Err(format!("「{}」は記録者名に使えません。空白や @ を含まない名前にしてください", name).into());
+        }
+
+        let mut directory = self.directory.write().unwrap();
+        let mut updated = directory.clone();
+        let members = updated.guilds.entry(guild_id.get()).or_default();
+        // 割り勘の @名前 で区別できるように、大文字と小文字の違いだけの名前も重複とみなす
+        if let Some((other, _)) = members
+            .iter()
+            .find(|(id, n)| **id != user_id.get() && n.to_lowercase() == name.to_lowercase())
+        {
+            return Err(format!("「{}」は <@{}> が使っています", name, other).into());
+        }
+        let previous = members.insert(user_id.get(), name.to_string());
+        self.commit(&mut directory, updated)?;
+        Ok(previous)
+    }
+
+    /// 登録を取り消す。取り消した名前を返す
+    pub fn remove(&self, guild_id: GuildId, user_id: UserId, actor: &Actor) -> Result<Option<String>, Error> {
+        let mut directory = self.directory.write().unwrap();
+        let mut updated = directory.clone();
+        let removed = updated.guilds.get_mut(&guild_id.get()).and_then(|m| m.remove(&user_id.get()));
+        if removed.is_none() {
+            return Ok(None);
+        }
+        let result = self.commit(&mut directory, updated);
+        self.audit.record(self.record(actor, "member.remove", guild_id, user_id).old(&removed).result(&result));
+        result.map(|_| removed)
+    }
+
+    /// まだ誰も登録されていないサーバーに、以前の `USER_ID_MAP` の対応を取り込む
+    pub fn seed(&self, guild_id: GuildId, users: &HashMap<u64, String>) -> Result<(), Error> {
+        let mut directory = self.directory.write().unwrap();
+        if users.is_empty() || directory.guilds.get(&guild_id.get()).is_some_and(|m| !m.is_empty()) {
+            return Ok(());
+        }
+        let members: BTreeMap<u64, String> = users.iter().map(|(id, name)| (*id, name.clone())).collect();
+        let mut updated = directory.clone();
+        updated.guilds.insert(guild_id.get(), members.clone());
+        let result = self.commit(&mut directory, updated);
+        let actor = Actor { guild_id: Some(guild_id.get()), ..Actor::system("USER_ID_MAP") };
+        self.audit.record(
+            AuditRecord::new(&actor, "member.seed", "members.json")
+                .range(guild_id.to_string())
+                .new_value(&members)
+                .result(&result),
+        );
+        result
+    }
+
+    /// 未登録の人への案内をまだしていなければ、したことにして `true` を返す
+    pub fn mark_prompted(&self, guild_id: GuildId, user_id: UserId) -> Result<bool, Error> {
+        let mut directory = self.directory.write().unwrap();
+        let mut updated = directory.clone();
+        if !updated.prompted.entry(guild_id.get()).or_default().insert(user_id.get()) {
+            return Ok(false);
+        }
+        self.commit(&mut directory, updated)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_members_unchanged_when_saving_fails() {
+        let dir = std::env::temp_dir().join(format!("hiratakebot-members-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("members.json");
+        let members = Members::load(path.clone(), Arc::new(AuditLog::new(dir.join("audit")))).unwrap();
+        let (guild, actor) = (GuildId::new(1), Actor::system("test"));
+
+        members.add(guild, UserId::new(10), "太郎", &actor).unwrap();
+        // 保存先をディレクトリにして書き込めなくする
+        std::fs::remove_file(&path).unwrap();
+        std::fs::create_dir_all(path.join("child")).unwrap();
+
+        assert!(members.add(guild, UserId::new(20), "花子", &actor).is_err());
+        assert!(members.add(guild, UserId::new(10), "次郎", &actor).is_err());
+        assert!(members.remove(guild, UserId::new(10), &actor).is_err());
+        assert!(members.mark_prompted(guild, UserId::new(30)).is_err());
+        assert_eq!(members.list(guild), BTreeMap::from([(10, "太郎".to_string())]));
+        // 失敗した案内は、次の機会にもう一度試す
+        assert!(members.mark_prompted(guild, UserId::new(30)).is_err());
+    }
+}
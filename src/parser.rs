@@ -0,0 +1,150 @@
+use crate::amount::{self, TaxRates};
+use crate::ledger::Kind;
+use regex::Regex;
+use std::sync::OnceLock;
+
+/// 記録用メッセージを読み取った結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedEntry {
+    pub kind: Kind,
+    pub item: String,
+    /// 支出なら負の値は返金・ポイント充当を表す
+    pub amount: i64,
+    /// 金額として読んだ部分（`980*2` など）
+    pub expression: String,
+    /// `@` で始まる語（`@楽天カード` なら `楽天カード`）。メンションは `<@123>:2` のまま入れる
+    pub tags: Vec<String>,
+    /// `割り勘` と書かれていれば全員で等分する
+    pub split_evenly: bool,
+}
+
+impl ParsedEntry {
+    /// 金額を計算で求めたか（`980` や `1,200` のような数だけの場合は false）
+    pub fn is_calculated(&self) -> bool {
+        let digits: String = self.expression.chars().map(amount::normalize).filter(|c| *c != ',').collect();
+        digits.parse::<i64>().ok() != Some(self.amount)
+    }
+}
+
+/// `ランチ 980`（支出）、`返金 -1200`（支出の取り消し）、`+給与 280000`（収入）、`=PayPayチャージ 5000`（振替）を読む。
+/// 金額は `980*2`、`1500+送料 350`、`2000税抜` のような式でもよい。
+/// `@楽天カード` のような `@` で始まる語は本文から取り除いて `tags` に入れる。`割り勘` も本文から取り除く
+pub fn parse(input: &str, tax: &TaxRates) -> Result<ParsedEntry, anyhow::Error> {
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    let tag = TAG.get_or_init(|| Regex::new(r"<@!?\d+>\S*|[@＠](\S+)").unwrap());
+
+    let tags = tag
+        .captures_iter(input)
+        .map(|c| match c.get(1) {
+            Some(name) => name.as_str().to_string(),
+            None => c[0].to_string(),
+        })
+        .collect();
+    let input = tag.replace_all(input, "");
+    let split_evenly = input.contains("割り勘");
+    let input = input.replace("割り勘", "");
+    let input = input.trim();
+    let mut chars = input.chars();
+    let (kind, rest) = match chars.next() {
+        Some('+' | '＋') => (Kind::Income, chars.as_str()),
+        Some('=' | '＝') => (Kind::Transfer, chars.as_str()),
+        _ => (Kind::Expense, input),
+    };
+
+    let original: Vec<char> = rest.chars().collect();
+    let normalized: Vec<char> = original.iter().map(|c| amount::normalize(*c)).collect();
+    if normalized.len() > amount::MAX_INPUT_CHARS {
+        return Err(anyhow::anyhow!("Input is too long"));
+    }
+
+    // 品目は数字で始まらない。先頭の数字は読み飛ばす
+    let item_start = normalized
+        .iter()
+        .position(|c| !c.is_ascii_digit())
+        .ok_or_else(|| anyhow::anyhow!("Invalid input format"))?;
+
+    // 品目のあとで、式として読める最初の位置を探す（`ランチ(会社) 980` の括弧は品目に含める）
+    for start in item_start + 1..normalized.len() {
+        let c = normalized[start];
+        let next = normalized[start + 1..].iter().find(|c| !c.is_whitespace());
+        let candidate = c.is_ascii_digit()
+            || (matches!(c, '(' | '-') && next.is_some_and(|n| n.is_ascii_digit() || *n == '('));
+        if !candidate {
+            continue;
+        }
+        if let Ok((amount, length)) = amount::evaluate(&normalized[start..], tax) {
+            let item: String = original[item_start..start].iter().collect();
+            let expression: String = original[start..start + length].iter().collect();
+            return Ok(ParsedEntry {
+                kind,
+                item: item.trim().to_string(),
+                amount,
+                expression: expression.trim().to_string(),
+                tags,
+                split_evenly,
+            });
+        }
+    }
+
+    Err(anyhow::anyhow!("Invalid input format"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> ParsedEntry {
+        super::parse(input, &TaxRates::default()).unwrap()
+    }
+
+    #[test]
+    fn reads_expenses() {
+        let entry = parse("ランチ 980");
+        assert_eq!((entry.kind, entry.item.as_str(), entry.amount), (Kind::Expense, "ランチ", 980));
+        assert!(!entry.is_calculated());
+        // 品目の中の括弧は式として読まない
+        let entry = parse("ランチ(会社) 980*2");
+        assert_eq!((entry.item.as_str(), entry.amount, entry.expression.as_str()), ("ランチ(会社)", 1960, "980*2"));
+        assert!(entry.is_calculated());
+    }
+
+    #[test]
+    fn reads_income_with_leading_plus() {
+        let entry = parse("+給与 280,000");
+        assert_eq!((entry.kind, entry.item.as_str(), entry.amount), (Kind::Income, "給与", 280000));
+        let entry = parse("＋お小遣い ５０００");
+        assert_eq!((entry.kind, entry.item.as_str(), entry.amount), (Kind::Income, "お小遣い", 5000));
+    }
+
+    #[test]
+    fn reads_negative_expenses_as_refunds() {
+        let entry = parse("返金 -1200");
+        assert_eq!((entry.kind, entry.item.as_str(), entry.amount), (Kind::Expense, "返金", -1200));
+        let entry = parse("ポイント充当 －５００");
+        assert_eq!((entry.kind, entry.amount), (Kind::Expense, -500));
+        // 品目のあとの `-` は金額の符号として読む
+        let entry = parse("キャンセル -(1000+500)");
+        assert_eq!((entry.item.as_str(), entry.amount), ("キャンセル", -1500));
+    }
+
+    #[test]
+    fn reads_transfers() {
+        let entry = parse("=PayPayチャージ 5000");
+        assert_eq!((entry.kind, entry.item.as_str(), entry.amount), (Kind::Transfer, "PayPayチャージ", 5000));
+    }
+
+    #[test]
+    fn takes_tags_and_split_out_of_the_text() {
+        let entry = parse("夕食 割り勘 6000 @楽天カード");
+        assert_eq!((entry.item.as_str(), entry.amount), ("夕食", 6000));
+        assert_eq!(entry.tags, vec!["楽天カード".to_string()]);
+        assert!(entry.split_evenly);
+    }
+
+    #[test]
+    fn rejects_text_without_amount() {
+        assert!(super::parse("ランチ", &TaxRates::default()).is_err());
+        assert!(super::parse("980", &TaxRates::default()).is_err());
+        assert!(super::parse(&format!("ランチ 980 {}", "あ".repeat(amount::MAX_INPUT_CHARS)), &TaxRates::default()).is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use chrono::{Datelike, Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::ledger::Month;
+
+/// 支払い方法（`@現金`、`@楽天カード` など）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaymentMethod {
+    /// シートに書く名前
+    pub name: String,
+    /// メッセージで使える別名
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// クレジットカードの締め日（31 なら月末）。カード以外は省略する
+    #[serde(default)]
+    pub closing_day: Option<u32>,
+    /// 引き落とし日。締め日の翌月のこの日に請求される
+    #[serde(default)]
+    pub payment_day: Option<u32>,
+}
+
+impl PaymentMethod {
+    pub fn matches(&self, token: &str) -> bool {
+        let token = token.to_lowercase();
+        self.name.to_lowercase() == token || self.aliases.iter().any(|a| a.to_lowercase() == token)
+    }
+
+    /// `date` を含む請求期間（前回の締め日の翌日から次の締め日まで）
+    pub fn billing_cycle(&self, date: NaiveDate) -> Option<(NaiveDate, NaiveDate)> {
+        let closing_day = self.closing_day?;
+        let this_month = closing_date(Month::of(date), closing_day);
+        let end = if date <= this_month {
+            this_month
+        } else {
+            closing_date(Month::of(date).next(), closing_day)
+        };
+        let start = closing_date(Month::of(end).prev(), closing_day) + Duration::days(1);
+        Some((start, end))
+    }
+
+    /// 締め日 `closing` の期間が引き落とされる日
+    pub fn payment_date(&self, closing: NaiveDate) -> Option<NaiveDate> {
+        Some(closing_date(Month::of(closing).next(), self.payment_day?))
+    }
+}
+
+/// `token` に当てはまる支払い方法を探す
+pub fn resolve<'a>(methods: &'a [PaymentMethod], token: &str) -> Option<&'a PaymentMethod> {
+    methods.iter().find(|m| m.matches(token))
+}
+
+/// 月の `day` 日。その月にない日なら月末にする
+fn closing_date(month: Month, day: u32) -> NaiveDate {
+    let day = day.clamp(1, month.days());
+    month.first_day().with_day(day).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn credit_card(closing_day: u32, payment_day: u32) -> PaymentMethod {
+        PaymentMethod {
+            name: "楽天カード".to_string(),
+            aliases: vec!["rakuten".to_string()],
+            closing_day: Some(closing_day),
+            payment_day: Some(payment_day),
+        }
+    }
+
+    #[test]
+    fn billing_cycle_includes_the_closing_day() {
+        let card = credit_card(15, 10);
+        assert_eq!(card.billing_cycle(date(2026, 9, 15)), Some((date(2026, 8, 16), date(2026, 9, 15))));
+        assert_eq!(card.billing_cycle(date(2026, 9, 16)), Some((date(2026, 9, 16), date(2026, 10, 15))));
+        assert_eq!(card.billing_cycle(date(2026, 9, 1)), Some((date(2026, 8, 16), date(2026, 9, 15))));
+    }
+
+    #[test]
+    fn closing_at_month_end() {
+        let card = credit_card(31, 27);
+        assert_eq!(card.billing_cycle(date(2026, 9, 30)), Some((date(2026, 9, 1), date(2026, 9, 30))));
+        assert_eq!(card.billing_cycle(date(2026, 10, 1)), Some((date(2026, 10, 1), date(2026, 10, 31))));
+        assert_eq!(card.payment_date(date(2026, 9, 30)), Some(date(2026, 10, 27)));
+    }
+
+    #[test]
+    fn closing_day_31_in_short_months() {
+        let card = credit_card(31, 31);
+        assert_eq!(card.billing_cycle(date(2026, 2, 10)), Some((date(2026, 2, 1), date(2026, 2, 28))));
+        assert_eq!(card.billing_cycle(date(2028, 2, 29)), Some((date(2028, 2, 1), date(2028, 2, 29))));
+        assert_eq!(card.billing_cycle(date(2026, 3, 1)), Some((date(2026, 3, 1), date(2026, 3, 31))));
+        assert_eq!(card.billing_cycle(date(2026, 4, 30)), Some((date(2026, 4, 1), date(2026, 4, 30))));
+        // 引き落とし日も月末に寄せる
+        assert_eq!(card.payment_date(date(2026, 1, 31)), Some(date(2026, 2, 28)));
+        assert_eq!(card.payment_date(date(2026, 3, 31)), Some(date(2026, 4, 30)));
+    }
+
+    #[test]
+    fn closing_day_30_does_not_skip_days_around_february() {
+        let card = credit_card(30, 10);
+        assert_eq!(card.billing_cycle(date(2026, 2, 28)), Some((date(2026, 1, 31), date(2026, 2, 28))));
+        assert_eq!(card.billing_cycle(date(2026, 3, 1)), Some((date(2026, 3, 1), date(2026, 3, 30))));
+    }
+
+    #[test]
+    fn crosses_the_year_boundary() {
+        let card = credit_card(15, 10);
+        assert_eq!(card.billing_cycle(date(2026, 12, 20)), Some((date(2026, 12, 16), date(2027, 1, 15))));
+        assert_eq!(card.billing_cycle(date(2027, 1, 5)), Some((date(2026, 12, 16), date(2027, 1, 15))));
+        assert_eq!(card.payment_date(date(2026, 12, 15)), Some(date(2027, 1, 10)));
+        let month_end = credit_card(31, 27);
+        assert_eq!(month_end.billing_cycle(date(2027, 1, 1)), Some((date(2027, 1, 1), date(2027, 1, 31))));
+        assert_eq!(month_end.payment_date(date(2026, 12, 31)), Some(date(2027, 1, 27)));
+    }
+
+    #[test]
+    fn methods_without_closing_day_have_no_cycle() {
+        let cash = PaymentMethod { name: "現金".to_string(), aliases: Vec::new(), closing_day: None, payment_day: None };
+        assert_eq!(cash.billing_cycle(date(2026, 9, 1)), None);
+        assert_eq!(cash.payment_date(date(2026, 9, 30)), None);
+    }
+
+    #[test]
+    fn resolves_names_and_aliases() {
+        let methods = vec![credit_card(15, 10)];
+        assert!(resolve(&methods, "楽天カード").is_some());
+        assert!(resolve(&methods, "Rakuten").is_some());
+        assert!(resolve(&methods, "現金").is_none());
+    }
+}
@@ -0,0 +1,212 @@
+use crate::audit::{Actor, AuditLog, AuditRecord};
+use crate::ledger::{Entry, Kind};
+use crate::storage::{load_json, save_json};
+use chrono::{DateTime, NaiveDate, Utc};
+use poise::serenity_prelude::{ButtonStyle, CreateActionRow, CreateButton};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// ボタンの ID の接頭辞。`pending:{id}:confirm` のようにする
+const CUSTOM_ID_PREFIX: &str = "pending:";
+
+/// Webhook で受け取り、Discord で確認されるのを待っている支出
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingExpense {
+    pub id: u64,
+    /// `WEBHOOK_SOURCES` の名前
+    pub source: String,
+    /// 確認を求めたチャンネル
+    pub channel_id: u64,
+    /// `None` なら確認した日に記録する
+    pub date: Option<NaiveDate>,
+    pub merchant: String,
+    pub amount: i64,
+    pub payment: String,
+    pub category: String,
+    pub received_at: DateTime<Utc>,
+    /// 送ってきた API キー（または署名の鍵）の名前
+    pub received_by: String,
+}
+
+/// 確認のボタンを押したときの操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Confirm,
+    Discard,
+}
+
+impl PendingExpense {
+    /// 「記録する」と「取り消す」のボタン
+    pub fn buttons(id: u64) -> Vec<CreateActionRow> {
+        vec![CreateActionRow::Buttons(vec![
+            CreateButton::new(format!("{}{}:confirm", CUSTOM_ID_PREFIX, id))
+                .label("記録する")
+                .style(ButtonStyle::Success),
+            CreateButton::new(format!("{}{}:discard", CUSTOM_ID_PREFIX, id))
+                .label("取り消す")
+                .style(ButtonStyle::Danger),
+        ])]
+    }
+
+    /// ボタンの ID を読む。ほかのボタンなら `None`
+    pub fn parse_custom_id(custom_id: &str) -> Option<(u64, Decision)> {
+        let (id, decision) = custom_id.strip_prefix(CUSTOM_ID_PREFIX)?.split_once(':')?;
+        let decision = match decision {
+            "confirm" => Decision::Confirm,
+            "discard" => Decision::Discard,
+            _ => return None,
+        };
+        Some((id.parse().ok()?, decision))
+    }
+
+    /// 記録する行。確認した人を記録者にする
+    pub fn to_entry(&self, user: &str, today: NaiveDate, link: String) -> Entry {
+        Entry {
+            row: 0,
+            date: Some(self.date.unwrap_or(today)),
+            item: self.merchant.clone(),
+            amount: self.amount,
+            category: self.category.clone(),
+            user: user.to_string(),
+            link,
+            kind: Kind::Expense,
+            payment: self.payment.clone(),
+            payer: String::new(),
+            receipt: String::new(),
+            patient: String::new(),
+            provider: String::new(),
+        }
+    }
+}
+
+/// 確認待ちの支出。再起動してもボタンが使えるようにファイルに保存する
+pub struct PendingExpenses {
+    path: PathBuf,
+    items: RwLock<BTreeMap<u64, PendingExpense>>,
+    audit: Arc<AuditLog>,
+}
+
+impl PendingExpenses {
+    pub fn load(path: PathBuf, audit: Arc<AuditLog>) -> Result<Self, Error> {
+        let items = load_json(&path)?;
+        Ok(Self { path, items: RwLock::new(items), audit })
+    }
+
+    /// 確認待ちの件数
+    pub fn count(&self) -> usize {
+        self.items.read().unwrap().len()
+    }
+
+    /// 確認待ちに加え、振った ID を返す
+    pub fn add(&self, mut expense: PendingExpense, actor: &Actor) -> Result<u64, Error> {
+        let mut items = self.items.write().unwrap();
+        // 受け取った時刻（ミリ秒）を ID にする。重なったらずらす
+        let mut id = expense.received_at.timestamp_millis().max(0) as u64;
+        while items.contains_key(&id) {
+            id += 1;
+        }
+        expense.id = id;
+        // 保存できたときだけ置き換える。失敗した分が残ると、送り直されたときにボタンが二重になる
+        let mut updated = items.clone();
+        updated.insert(id, expense.clone());
+        let result = save_json(&self.path, &updated);
+        self.audit.record(
+            AuditRecord::new(actor, "pending.add", "pending.json")
+                .range(id.to_string())
+                .new_value(&expense)
+                .result(&result),
+        );
+        result?;
+        *items = updated;
+        Ok(id)
+    }
+
+    /// 確認待ちから外して返す。ほかの人がすでに処理していれば `None`
+    pub fn take(&self, id: u64, action: &str, actor: &Actor) -> Result<Option<PendingExpense>, Error> {
+        let mut items = self.items.write().unwrap();
+        let mut updated = items.clone();
+        let Some(expense) = updated.remove(&id) else {
+            return Ok(None);
+        };
+        let result = save_json(&self.path, &updated);
+        self.audit.record(
+            AuditRecord::new(actor, action, "pending.json")
+                .range(id.to_string())
+                .old(&expense)
+                .result(&result),
+        );
+        result?;
+        *items = updated;
+        Ok(Some(expense))
+    }
+
+    /// 記録に失敗したときに確認待ちへ戻す
+    pub fn restore(&self, expense: PendingExpense) -> Result<(), Error> {
+        let mut items = self.items.write().unwrap();
+        let mut updated = items.clone();
+        updated.insert(expense.id, expense);
+        save_json(&self.path, &updated)?;
+        *items = updated;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expense() -> PendingExpense {
+        PendingExpense {
+            id: 0,
+            source: "card".to_string(),
+            channel_id: 1,
+            date: None,
+            merchant: "コンビニ".to_string(),
+            amount: 1200,
+            payment: "楽天カード".to_string(),
+            category: String::new(),
+            received_at: DateTime::from_timestamp(1_760_000_000, 0).unwrap(),
+            received_by: "card".to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_button_ids() {
+        assert_eq!(PendingExpense::parse_custom_id("pending:42:confirm"), Some((42, Decision::Confirm)));
+        assert_eq!(PendingExpense::parse_custom_id("pending:42:discard"), Some((42, Decision::Discard)));
+        assert_eq!(PendingExpense::parse_custom_id("pending:42:delete"), None);
+        assert_eq!(PendingExpense::parse_custom_id("pending:abc:confirm"), None);
+        assert_eq!(PendingExpense::parse_custom_id("pending:42"), None);
+        assert_eq!(PendingExpense::parse_custom_id("other:42:confirm"), None);
+    }
+
+    #[test]
+    fn keeps_items_unchanged_when_saving_fails() {
+        let dir = std::env::temp_dir().join(format!("hiratakebot-pending-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("pending.json");
+        let pending = PendingExpenses::load(path.clone(), Arc::new(AuditLog::new(dir.join("audit")))).unwrap();
+        let actor = Actor::system("test");
+
+        let id = pending.add(expense(), &actor).unwrap();
+        // 同じ時刻に受け取ったものは ID をずらす
+        assert_eq!(pending.add(expense(), &actor).unwrap(), id + 1);
+        // 保存先をディレクトリにして書き込めなくする
+        std::fs::remove_file(&path).unwrap();
+        std::fs::create_dir_all(path.join("child")).unwrap();
+
+        assert!(pending.add(expense(), &actor).is_err());
+        assert_eq!(pending.count(), 2);
+        assert!(pending.take(id, "pending.confirm", &actor).is_err());
+        assert_eq!(pending.count(), 2);
+
+        std::fs::remove_dir_all(&path).unwrap();
+        assert_eq!(pending.take(id, "pending.confirm", &actor).unwrap().map(|e| e.id), Some(id));
+        assert!(pending.take(id, "pending.confirm", &actor).unwrap().is_none());
+        assert_eq!(pending.count(), 1);
+    }
+}
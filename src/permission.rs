@@ -0,0 +1,246 @@
+use crate::audit::{Actor, AuditLog, AuditRecord};
+use crate::storage::{load_json, save_json};
+use poise::serenity_prelude::{GuildId, Http, Permissions as GuildPermissions, RoleId, UserId};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use tracing::warn;
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// ボットの操作権限。上の権限は下の権限を含む
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, poise::ChoiceParameter)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// グラフや明細を見られる
+    #[name = "閲覧"]
+    Viewer,
+    /// 支出を記録・取り込みできる
+    #[name = "記録"]
+    Recorder,
+    /// 家計簿やメンバー、権限の設定を変えられる
+    #[name = "管理"]
+    Admin,
+}
+
+/// サーバーごとの権限の割り当て
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RoleBindings {
+    /// Discord のロール ID ごとの権限
+    #[serde(default)]
+    pub roles: BTreeMap<u64, Role>,
+    /// ユーザー ID ごとの権限。ロールより優先する
+    #[serde(default)]
+    pub users: BTreeMap<u64, Role>,
+    /// どれにも当てはまらない人の権限。`None` なら何もできない
+    #[serde(default)]
+    pub everyone: Option<Role>,
+    /// `/role` で一度でも設定したか。割り当てを全て消しても、全員が記録できる状態には戻さない
+    #[serde(default)]
+    pub configured: bool,
+}
+
+impl RoleBindings {
+    /// まだ一度も設定していない。以前からのサーバーのため、割り当てが残っていれば設定済みとみなす
+    pub fn is_unconfigured(&self) -> bool {
+        !self.configured && self.roles.is_empty() && self.users.is_empty() && self.everyone.is_none()
+    }
+}
+
+/// 権限の割り当て。`/role` で変更し、ファイルに保存する
+pub struct Permissions {
+    path: PathBuf,
+    guilds: RwLock<BTreeMap<u64, RoleBindings>>,
+    audit: Arc<AuditLog>,
+}
+
+impl Permissions {
+    pub fn load(path: PathBuf, audit: Arc<AuditLog>) -> Result<Self, Error> {
+        let guilds = load_json(&path)?;
+        Ok(Self { path, guilds: RwLock::new(guilds), audit })
+    }
+
+    pub fn bindings(&self, guild_id: GuildId) -> RoleBindings {
+        self.guilds.read().unwrap().get(&guild_id.get()).cloned().unwrap_or_default()
+    }
+
+    /// 人の権限。サーバーの管理権限を持つ人はいつでも管理者になる。
+    /// まだ一度も設定していないサーバーでは、これまでどおり全員が記録できる
+    pub fn role_of(&self, guild_id: GuildId, user_id: UserId, roles: &[RoleId], manages_guild: bool) -> Option<Role> {
+        if manages_guild {
+            return Some(Role::Admin);
+        }
+        let bindings = self.bindings(guild_id);
+        if bindings.is_unconfigured() {
+            return Some(Role::Recorder);
+        }
+        if let Some(role) = bindings.users.get(&user_id.get()) {
+            return Some(*role);
+        }
+        roles
+            .iter()
+            .filter_map(|id| bindings.roles.get(&id.get()).copied())
+            .chain(bindings.everyone)
+            .max()
+    }
+
+    /// 権限の付いていないメッセージや API から使う人の権限。割り当てで `required` に届かないときだけ、
+    /// サーバーのロールと所有者を取得して管理権限を確かめる
+    pub async fn role_of_member(&self, http: &Http, guild_id: GuildId, user_id: UserId, roles: &[RoleId], required: Role) -> Option<Role> {
+        let role = self.role_of(guild_id, user_id, roles, false);
+        if role >= Some(required) {
+            return role;
+        }
+        match guild_id.to_partial_guild(http).await {
+            Ok(guild) => {
+                let guild_roles = guild.roles.values().map(|r| (r.id, r.permissions));
+                if manages_guild(guild_id, guild.owner_id, guild_roles, user_id, roles) {
+                    return Some(Role::Admin);
+                }
+            }
+            Err(e) => warn!("failed to fetch guild {} to check permissions: {:?}", guild_id, e),
+        }
+        role
+    }
+
+    /// 割り当てを変えて保存する
+    pub fn update(&self, guild_id: GuildId, actor: &Actor, f: impl FnOnce(&mut RoleBindings)) -> Result<(), Error> {
+        let mut guilds = self.guilds.write().unwrap();
+        // 保存できたときだけ置き換える。失敗した変更が再起動まで効いてしまわないように
+        let mut updated = guilds.clone();
+        let bindings = updated.entry(guild_id.get()).or_default();
+        let old = bindings.clone();
+        f(bindings);
+        bindings.configured = true;
+        let new = bindings.clone();
+        let result = save_json(&self.path, &updated);
+        self.audit.record(
+            AuditRecord::new(actor, "role.update", "permissions.json")
+                .range(guild_id.to_string())
+                .old(old)
+                .new_value(new)
+                .result(&result),
+        );
+        result?;
+        *guilds = updated;
+        Ok(())
+    }
+}
+
+/// サーバーの管理権限（サーバーの管理か管理者）を持つか。@everyone のロールの ID はサーバーの ID と同じ
+fn manages_guild(
+    guild_id: GuildId,
+    owner_id: UserId,
+    guild_roles: impl Iterator<Item = (RoleId, GuildPermissions)>,
+    user_id: UserId,
+    roles: &[RoleId],
+) -> bool {
+    if user_id == owner_id {
+        return true;
+    }
+    let permissions = guild_roles
+        .filter(|(id, _)| id.get() == guild_id.get() || roles.contains(id))
+        .fold(GuildPermissions::empty(), |acc, (_, permissions)| acc | permissions);
+    permissions.administrator() || permissions.manage_guild()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn permissions(name: &str) -> Permissions {
+        let dir = std::env::temp_dir().join(format!("hiratakebot-permission-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        Permissions::load(dir.join("permissions.json"), Arc::new(AuditLog::new(dir.join("audit")))).unwrap()
+    }
+
+    const GUILD: GuildId = GuildId::new(1);
+    const USER: UserId = UserId::new(10);
+    const OTHER: UserId = UserId::new(11);
+
+    #[test]
+    fn everyone_records_until_configured() {
+        let permissions = permissions("default");
+        assert_eq!(permissions.role_of(GUILD, USER, &[], false), Some(Role::Recorder));
+        assert_eq!(permissions.role_of(GUILD, USER, &[], true), Some(Role::Admin));
+    }
+
+    #[test]
+    fn clearing_everyone_on_fresh_guild_takes_effect() {
+        let permissions = permissions("everyone");
+        permissions.update(GUILD, &Actor::system("test"), |bindings| bindings.everyone = None).unwrap();
+        assert_eq!(permissions.role_of(GUILD, USER, &[], false), None);
+        assert_eq!(permissions.role_of(GUILD, USER, &[], true), Some(Role::Admin));
+    }
+
+    #[test]
+    fn revoking_last_binding_does_not_restore_recorder() {
+        let permissions = permissions("revoke");
+        let actor = Actor::system("test");
+        permissions.update(GUILD, &actor, |bindings| {
+            bindings.users.insert(USER.get(), Role::Viewer);
+        }).unwrap();
+        assert_eq!(permissions.role_of(GUILD, USER, &[], false), Some(Role::Viewer));
+        assert_eq!(permissions.role_of(GUILD, OTHER, &[], false), None);
+
+        permissions.update(GUILD, &actor, |bindings| {
+            bindings.users.remove(&USER.get());
+        }).unwrap();
+        assert_eq!(permissions.role_of(GUILD, USER, &[], false), None);
+        assert_eq!(permissions.role_of(GUILD, OTHER, &[], false), None);
+    }
+
+    #[test]
+    fn configured_state_survives_reload() {
+        let permissions = permissions("reload");
+        permissions.update(GUILD, &Actor::system("test"), |bindings| bindings.everyone = None).unwrap();
+        let reloaded = Permissions::load(permissions.path.clone(), permissions.audit.clone()).unwrap();
+        assert_eq!(reloaded.role_of(GUILD, USER, &[], false), None);
+    }
+
+    #[test]
+    fn old_files_with_bindings_count_as_configured() {
+        let bindings: RoleBindings = serde_json::from_str(r#"{"users": {"10": "viewer"}}"#).unwrap();
+        assert!(!bindings.is_unconfigured());
+        let bindings: RoleBindings = serde_json::from_str("{}").unwrap();
+        assert!(bindings.is_unconfigured());
+    }
+
+    #[test]
+    fn keeps_roles_unchanged_when_saving_fails() {
+        let permissions = permissions("save-failure");
+        let actor = Actor::system("test");
+        permissions.update(GUILD, &actor, |b| b.everyone = Some(Role::Viewer)).unwrap();
+        // 保存先をディレクトリにして書き込めなくする
+        std::fs::remove_file(&permissions.path).unwrap();
+        std::fs::create_dir_all(permissions.path.join("child")).unwrap();
+
+        assert!(permissions.update(GUILD, &actor, |b| { b.users.insert(USER.get(), Role::Admin); }).is_err());
+        assert_eq!(permissions.role_of(GUILD, USER, &[], false), Some(Role::Viewer));
+        assert!(permissions.update(GuildId::new(2), &actor, |b| b.everyone = None).is_err());
+        assert_eq!(permissions.role_of(GuildId::new(2), USER, &[], false), Some(Role::Recorder));
+    }
+
+    #[test]
+    fn managers_are_found_from_guild_roles() {
+        let (owner, manager) = (UserId::new(100), RoleId::new(5));
+        let roles = || {
+            [
+                (RoleId::new(GUILD.get()), GuildPermissions::SEND_MESSAGES),
+                (manager, GuildPermissions::MANAGE_GUILD),
+                (RoleId::new(6), GuildPermissions::ADMINISTRATOR),
+                (RoleId::new(7), GuildPermissions::MANAGE_MESSAGES),
+            ]
+            .into_iter()
+        };
+        assert!(manages_guild(GUILD, owner, roles(), owner, &[]));
+        assert!(manages_guild(GUILD, owner, roles(), USER, &[manager]));
+        assert!(manages_guild(GUILD, owner, roles(), USER, &[RoleId::new(6)]));
+        assert!(!manages_guild(GUILD, owner, roles(), USER, &[RoleId::new(7)]));
+        assert!(!manages_guild(GUILD, owner, roles(), USER, &[]));
+        // @everyone に管理権限があれば全員が管理者
+        let everyone = [(RoleId::new(GUILD.get()), GuildPermissions::MANAGE_GUILD)].into_iter();
+        assert!(manages_guild(GUILD, owner, everyone, USER, &[]));
+    }
+}
@@ -0,0 +1,66 @@
+use poise::serenity_prelude::{ChannelId, CreateAttachment, CreateMessage, GuildId, Http, Message, MessageId};
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// 記録用メッセージの添付ファイル（レシートの写真など）を保管用チャンネルに投稿し直し、そのメッセージへのリンクを返す。
+/// 元のメッセージが消されても、保管用のメッセージから画像を取り出せる
+pub async fn archive(http: &Http, archive_channel: ChannelId, msg: &Message, caption: &str) -> Result<String, Error> {
+    let mut files = Vec::new();
+    for attachment in &msg.attachments {
+        let data = attachment.download().await?;
+        files.push(CreateAttachment::bytes(data, attachment.filename.clone()));
+    }
+
+    let content = format!("{}\n{}", caption, msg.link());
+    let archived = archive_channel
+        .send_files(http, files, CreateMessage::new().content(content))
+        .await?;
+    Ok(archived.link())
+}
+
+/// 保管用メッセージの添付ファイルを取り出す。シートのセルは誰でも書き換えられるので、
+/// `guild_id` の保管用チャンネル（`archive_channel`）へのリンクでなければ取り出さない
+pub async fn fetch(http: &Http, link: &str, guild_id: GuildId, archive_channel: ChannelId) -> Result<Vec<CreateAttachment>, Error> {
+    let (link_guild, channel_id, message_id) =
+        parse_message_link(link).ok_or_else(|| format!("メッセージのリンクではありません: {}", link))?;
+    if link_guild != guild_id || channel_id != archive_channel {
+        return Err(format!("このサーバーのレシートの保管用チャンネルへのリンクではありません: {}", link).into());
+    }
+    let message = channel_id.message(http, message_id).await?;
+
+    let mut files = Vec::new();
+    for attachment in &message.attachments {
+        let data = attachment.download().await?;
+        files.push(CreateAttachment::bytes(data, attachment.filename.clone()));
+    }
+    Ok(files)
+}
+
+/// `https://discord.com/channels/{guild}/{channel}/{message}` からサーバー、チャンネル、メッセージの ID を取り出す
+pub fn parse_message_link(link: &str) -> Option<(GuildId, ChannelId, MessageId)> {
+    let mut parts = link.trim().trim_end_matches('/').rsplit('/');
+    let message_id = parts.next()?.parse::<u64>().ok().filter(|id| *id > 0)?;
+    let channel_id = parts.next()?.parse::<u64>().ok().filter(|id| *id > 0)?;
+    let guild_id = parts.next()?.parse::<u64>().ok().filter(|id| *id > 0)?;
+    (parts.next()? == "channels").then_some((GuildId::new(guild_id), ChannelId::new(channel_id), MessageId::new(message_id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_guild_channel_and_message() {
+        assert_eq!(
+            parse_message_link("https://discord.com/channels/1/2/3"),
+            Some((GuildId::new(1), ChannelId::new(2), MessageId::new(3)))
+        );
+    }
+
+    #[test]
+    fn rejects_links_without_guild() {
+        assert_eq!(parse_message_link("https://discord.com/channels/@me/2/3"), None);
+        assert_eq!(parse_message_link("https://example.com/2/3"), None);
+        assert_eq!(parse_message_link("3"), None);
+    }
+}
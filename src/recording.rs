@@ -0,0 +1,109 @@
+use crate::config::Config;
+use crate::ledger::{format_yen, Entry, Kind};
+use crate::parser::{self, ParsedEntry};
+use crate::{payment, split, telemetry};
+use chrono::NaiveDate;
+use std::collections::HashMap;
+
+/// 記録用の文を読んで確かめたもの。Discord の発言と HTTP API のどちらから記録するときも使う
+#[derive(Debug, Clone)]
+pub struct Draft {
+    pub parsed: ParsedEntry,
+    /// 記録者名
+    pub user: String,
+    pub payment: String,
+    /// 割り勘で負担する人。空なら記録者だけ
+    members: Vec<split::Member>,
+}
+
+impl Draft {
+    /// `users` は記録者名の対応、`user` は記録する人の名前
+    pub fn new(text: &str, config: &Config, users: &HashMap<u64, String>, user: &str) -> Result<Self, anyhow::Error> {
+        let parsed = parser::parse(text, &config.tax_rates).inspect_err(|_| telemetry::expense_rejected("parse"))?;
+        let mut payment = String::new();
+        let mut members = Vec::new();
+        for tag in &parsed.tags {
+            if let Some(member) = split::parse_member(tag, users).map_err(anyhow::Error::msg)? {
+                members.push(member);
+            } else if let Some(method) = payment::resolve(&config.payment_methods, tag) {
+                payment = method.name.clone();
+            } else {
+                telemetry::expense_rejected("unknown_tag");
+                return Err(anyhow::anyhow!("@{} はメンバーにも支払い方法にも登録されていません", tag));
+            }
+        }
+
+        // 割り勘なら支払った人を先頭にして全員で等分する
+        if parsed.split_evenly && members.is_empty() {
+            let mut names: Vec<&String> = users.values().filter(|u| *u != user).collect();
+            names.sort();
+            members = std::iter::once(&user.to_string())
+                .filter(|u| !u.is_empty())
+                .chain(names)
+                .map(|user| split::Member { user: user.clone(), weight: 1 })
+                .collect();
+        }
+
+        telemetry::expense_parsed();
+        Ok(Self { parsed, user: user.to_string(), payment, members })
+    }
+
+    /// 金額を計算で求めたときはその式
+    pub fn expression(&self) -> Option<String> {
+        self.parsed.is_calculated().then(|| self.parsed.expression.clone())
+    }
+
+    /// 書き込む行。割り勘なら負担する人ごとの行にする
+    pub fn into_entries(self, date: NaiveDate, link: String, receipt: String) -> Result<Vec<Entry>, anyhow::Error> {
+        let entry = Entry {
+            row: 0,
+            date: Some(date),
+            item: self.parsed.item,
+            amount: self.parsed.amount,
+            category: String::new(),
+            user: self.user.clone(),
+            link,
+            kind: self.parsed.kind,
+            payment: self.payment,
+            payer: String::new(),
+            receipt,
+            patient: String::new(),
+            provider: String::new(),
+        };
+        if self.members.is_empty() {
+            return Ok(vec![entry]);
+        }
+        Ok(split::allocate(entry.amount, &self.members)
+            .map_err(anyhow::Error::msg)?
+            .into_iter()
+            .map(|share| Entry {
+                amount: share.amount,
+                user: share.user,
+                payer: self.user.clone(),
+                ..entry.clone()
+            })
+            .collect())
+    }
+}
+
+/// 記録したことを知らせる文（`記録しました: ランチ 980円` など）
+pub fn announcement(entries: &[Entry], expression: Option<&str>) -> String {
+    let Some(first) = entries.first() else {
+        return "記録する明細がありませんでした".to_string();
+    };
+    let total: i64 = entries.iter().map(|e| e.amount).sum();
+    let mut reply = match first.kind {
+        Kind::Expense => "記録しました".to_string(),
+        kind => format!("{}として記録しました", kind.label()),
+    };
+    reply.push_str(&format!(": {} {}", first.item, format_yen(total)));
+    if let Some(expression) = expression {
+        reply.push_str(&format!("（{}）", expression));
+    }
+    if entries.len() > 1 {
+        for entry in entries {
+            reply.push_str(&format!("\n- {}: {}", entry.user, format_yen(entry.amount)));
+        }
+    }
+    reply
+}
@@ -0,0 +1,469 @@
+use crate::audit::{Actor, AuditLog, AuditRecord};
+use crate::events::EventBus;
+use crate::health::Health;
+use crate::ledger::Layout;
+use crate::spreadsheet::Book;
+use crate::storage::{load_json, save_json};
+use chrono::FixedOffset;
+use poise::serenity_prelude::{Channel, ChannelId, GuildId, Http};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock, RwLock};
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// チャンネル（またはスレッド）に割り当てる家計簿
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Route {
+    pub spreadsheet_id: String,
+    #[serde(default)]
+    pub layout: Layout,
+    /// `+09:00` の形式。省略するとサーバーのタイムゾーン
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// この家計簿だけで使う記録者名。`/member` で登録した名前より優先する
+    #[serde(default)]
+    pub users: Option<HashMap<u64, String>>,
+    /// `/ledger bind` を実行したサーバー。シークレットで設定した経路では `None`
+    #[serde(default)]
+    pub guild_id: Option<u64>,
+}
+
+impl Route {
+    fn timezone(&self) -> Result<Option<FixedOffset>, Error> {
+        self.timezone
+            .as_deref()
+            .map(|tz| tz.parse().map_err(|_| format!("タイムゾーンは +09:00 の形式で指定してください: {}", tz).into()))
+            .transpose()
+    }
+}
+
+/// `/setup` で登録するサーバーごとの設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuildSettings {
+    /// 支出を記録するチャンネル
+    pub expenses_channel_id: u64,
+    pub spreadsheet_id: String,
+    #[serde(default)]
+    pub layout: Layout,
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// レシート画像を保管するチャンネル
+    #[serde(default)]
+    pub receipt_channel_id: Option<u64>,
+}
+
+impl GuildSettings {
+    fn route(&self, guild_id: GuildId) -> Route {
+        Route {
+            spreadsheet_id: self.spreadsheet_id.clone(),
+            layout: self.layout.clone(),
+            timezone: self.timezone.clone(),
+            users: None,
+            guild_id: Some(guild_id.get()),
+        }
+    }
+}
+
+/// チャンネルやサーバーから家計簿を引く表
+///
+/// 次の順に探す。サーバーをまたいで家計簿を引くことはない
+/// 1. チャンネル（スレッドなら親チャンネル）の割り当て。シークレットの `LEDGER_ROUTES` と `/ledger bind`
+/// 2. `/setup` で登録したサーバーの家計簿
+/// 3. シークレットの `EXPENSES_SPREADSHEET_ID`。`EXPENSES_CHANNEL_ID` のあるサーバーでだけ使う
+pub struct Ledgers {
+    default: Arc<Book>,
+    home_guild: OnceLock<GuildId>,
+    credentials: String,
+    configured: BTreeMap<u64, Route>,
+    /// `/ledger bind` や `/setup` で変更した設定の保存先
+    data_dir: PathBuf,
+    bound: RwLock<BTreeMap<u64, Route>>,
+    books: RwLock<HashMap<u64, Arc<Book>>>,
+    guilds: RwLock<BTreeMap<u64, GuildSettings>>,
+    guild_books: RwLock<HashMap<u64, Arc<Book>>>,
+    /// スプレッドシートを使うサーバー。最初に割り当てたサーバーのもので、ほかのサーバーからは割り当てられない
+    owners: RwLock<BTreeMap<String, u64>>,
+    audit: Arc<AuditLog>,
+    health: Arc<Health>,
+    events: Arc<EventBus>,
+}
+
+impl Ledgers {
+    pub fn new(
+        default: Arc<Book>,
+        credentials: String,
+        configured: BTreeMap<u64, Route>,
+        data_dir: PathBuf,
+        audit: Arc<AuditLog>,
+        health: Arc<Health>,
+        events: Arc<EventBus>,
+    ) -> Result<Self, Error> {
+        let bound: BTreeMap<u64, Route> = load_json(&data_dir.join("ledgers.json"))?;
+        let guilds: BTreeMap<u64, GuildSettings> = load_json(&data_dir.join("guilds.json"))?;
+        let mut owners: BTreeMap<String, u64> = load_json(&data_dir.join("spreadsheets.json"))?;
+        // 所有者を記録する前に割り当てた家計簿は、割り当てたサーバーのものとする
+        let assigned = bound
+            .values()
+            .filter_map(|route| Some((route.spreadsheet_id.clone(), route.guild_id?)))
+            .chain(guilds.iter().map(|(guild, settings)| (settings.spreadsheet_id.clone(), *guild)));
+        for (spreadsheet_id, guild) in assigned {
+            owners.entry(spreadsheet_id).or_insert(guild);
+        }
+
+        let ledgers = Self {
+            default,
+            home_guild: OnceLock::new(),
+            credentials,
+            configured,
+            data_dir,
+            bound: RwLock::new(BTreeMap::new()),
+            books: RwLock::new(HashMap::new()),
+            guilds: RwLock::new(BTreeMap::new()),
+            guild_books: RwLock::new(HashMap::new()),
+            owners: RwLock::new(owners),
+            audit,
+            health,
+            events,
+        };
+        // 同じチャンネルなら bind で登録したほうを優先する
+        let mut books = HashMap::new();
+        for (channel, route) in ledgers.configured.iter().chain(bound.iter()) {
+            books.insert(*channel, ledgers.open(route)?);
+        }
+        let mut guild_books = HashMap::new();
+        for (guild, settings) in &guilds {
+            guild_books.insert(*guild, ledgers.open(&settings.route(GuildId::new(*guild)))?);
+        }
+        *ledgers.books.write().unwrap() = books;
+        *ledgers.bound.write().unwrap() = bound;
+        *ledgers.guild_books.write().unwrap() = guild_books;
+        *ledgers.guilds.write().unwrap() = guilds;
+        Ok(ledgers)
+    }
+
+    /// シークレットで設定した家計簿（`EXPENSES_SPREADSHEET_ID`）
+    pub fn default(&self) -> Arc<Book> {
+        self.default.clone()
+    }
+
+    /// シークレットで設定した家計簿を使うサーバー。`EXPENSES_CHANNEL_ID` のあるサーバーを起動時に登録する
+    pub fn set_home_guild(&self, guild_id: GuildId) {
+        let _ = self.home_guild.set(guild_id);
+    }
+
+    pub fn is_home_guild(&self, guild_id: GuildId) -> bool {
+        self.home_guild.get() == Some(&guild_id)
+    }
+
+    /// チャンネルに直接割り当てられた家計簿
+    pub fn get(&self, channel_id: ChannelId) -> Option<Arc<Book>> {
+        self.books.read().unwrap().get(&channel_id.get()).cloned()
+    }
+
+    /// スレッドの親チャンネルに割り当てられた家計簿
+    async fn get_parent(&self, http: &Http, channel_id: ChannelId) -> Option<Arc<Book>> {
+        if self.books.read().unwrap().is_empty() {
+            return None;
+        }
+        match channel_id.to_channel(http).await {
+            Ok(Channel::Guild(channel)) if channel.thread_metadata.is_some() => self.get(channel.parent_id?),
+            _ => None,
+        }
+    }
+
+    /// サーバーの家計簿。`/setup` がまだなら、シークレットの家計簿を使うサーバーでだけ既定の家計簿を返す
+    pub fn for_guild(&self, guild_id: GuildId) -> Option<Arc<Book>> {
+        match self.guild_books.read().unwrap().get(&guild_id.get()) {
+            Some(book) => Some(book.clone()),
+            None => self.is_home_guild(guild_id).then(|| self.default()),
+        }
+    }
+
+    /// 発言を記録する家計簿。割り当てのあるチャンネルか、`/setup` で登録した記録用チャンネルなら返す
+    pub async fn for_message(&self, http: &Http, guild_id: Option<GuildId>, channel_id: ChannelId) -> Option<Arc<Book>> {
+        if let Some(book) = self.get(channel_id) {
+            return Some(book);
+        }
+        let guild_id = guild_id?;
+        let is_expenses_channel = self
+            .guilds
+            .read()
+            .unwrap()
+            .get(&guild_id.get())
+            .is_some_and(|settings| settings.expenses_channel_id == channel_id.get());
+        if is_expenses_channel {
+            return self.for_guild(guild_id);
+        }
+        self.get_parent(http, channel_id).await
+    }
+
+    /// コマンドで使う家計簿。チャンネルの割り当て、サーバーの家計簿の順に探す
+    pub async fn lookup(&self, http: &Http, guild_id: Option<GuildId>, channel_id: ChannelId) -> Option<Arc<Book>> {
+        let guild_id = guild_id?;
+        if let Some(book) = self.get(channel_id) {
+            return Some(book);
+        }
+        if let Some(book) = self.get_parent(http, channel_id).await {
+            return Some(book);
+        }
+        self.for_guild(guild_id)
+    }
+
+    /// サーバーのチャンネルの割り当て。シークレットで設定した経路はシークレットの家計簿を使うサーバーにだけ見せる
+    pub fn routes(&self, guild_id: GuildId) -> BTreeMap<u64, Route> {
+        let mut routes = BTreeMap::new();
+        if self.is_home_guild(guild_id) {
+            routes.extend(self.configured.clone());
+        }
+        routes.extend(self.bound.read().unwrap().clone());
+        routes.retain(|_, route| route.guild_id.is_none_or(|id| id == guild_id.get()));
+        routes
+    }
+
+    /// ほかのサーバーが使っている家計簿ならエラーにする。シークレットで設定した家計簿はそれを使うサーバーのもの
+    pub fn check_owner(&self, guild_id: GuildId, spreadsheet_id: &str) -> Result<(), Error> {
+        let is_secret = self.default.id() == spreadsheet_id
+            || self.configured.values().any(|route| route.spreadsheet_id == spreadsheet_id);
+        let owned_by_other = match self.owners.read().unwrap().get(spreadsheet_id) {
+            Some(owner) => *owner != guild_id.get(),
+            None => is_secret && !self.is_home_guild(guild_id),
+        };
+        if owned_by_other {
+            return Err("このスプレッドシートはほかのサーバーで使われているため、割り当てられません".into());
+        }
+        Ok(())
+    }
+
+    /// 家計簿をこのサーバーのものとして記録する。すでにほかのサーバーのものならエラー
+    fn claim(&self, guild_id: GuildId, spreadsheet_id: &str, actor: &Actor) -> Result<(), Error> {
+        self.check_owner(guild_id, spreadsheet_id)?;
+        let mut owners = self.owners.write().unwrap();
+        match owners.get(spreadsheet_id) {
+            Some(owner) if *owner == guild_id.get() => return Ok(()),
+            // 確かめてから書き込むまでに、ほかのサーバーが先に割り当てた
+            Some(_) => return Err("このスプレッドシートはほかのサーバーで使われているため、割り当てられません".into()),
+            None => {}
+        }
+        owners.insert(spreadsheet_id.to_string(), guild_id.get());
+        let result = save_json(&self.data_dir.join("spreadsheets.json"), &*owners);
+        self.audit.record(
+            AuditRecord::new(actor, "spreadsheet.claim", "spreadsheets.json")
+                .range(spreadsheet_id)
+                .new_value(guild_id.get())
+                .result(&result),
+        );
+        if result.is_err() {
+            owners.remove(spreadsheet_id);
+        }
+        result
+    }
+
+    /// チャンネルに家計簿を割り当てて保存する
+    pub fn bind(&self, channel_id: ChannelId, route: Route, actor: &Actor) -> Result<(), Error> {
+        if let Some(guild_id) = route.guild_id {
+            self.claim(GuildId::new(guild_id), &route.spreadsheet_id, actor)?;
+        }
+        let book = self.open(&route)?;
+        let mut bound = self.bound.write().unwrap();
+        // 保存できたときだけ置き換える。失敗したのに再起動まで割り当てが効いてしまわないように
+        let mut updated = bound.clone();
+        let old = updated.insert(channel_id.get(), route.clone());
+        let result = save_json(&self.data_dir.join("ledgers.json"), &updated);
+        self.audit.record(
+            AuditRecord::new(actor, "ledger.bind", "ledgers.json")
+                .range(channel_id.to_string())
+                .old(old)
+                .new_value(route)
+                .result(&result),
+        );
+        result?;
+        *bound = updated;
+        self.books.write().unwrap().insert(channel_id.get(), book);
+        Ok(())
+    }
+
+    /// bind で登録した割り当てを外す。シークレットで設定した経路があればそれに戻る
+    pub fn unbind(&self, guild_id: GuildId, channel_id: ChannelId, actor: &Actor) -> Result<bool, Error> {
+        let mut bound = self.bound.write().unwrap();
+        match bound.get(&channel_id.get()) {
+            Some(route) if route.guild_id.is_none_or(|id| id == guild_id.get()) => {}
+            _ => return Ok(false),
+        }
+        let fallback = self.configured.get(&channel_id.get()).map(|route| self.open(route)).transpose()?;
+        let mut updated = bound.clone();
+        let old = updated.remove(&channel_id.get());
+        let result = save_json(&self.data_dir.join("ledgers.json"), &updated);
+        self.audit.record(
+            AuditRecord::new(actor, "ledger.unbind", "ledgers.json")
+                .range(channel_id.to_string())
+                .old(old)
+                .result(&result),
+        );
+        result?;
+        *bound = updated;
+
+        let mut books = self.books.write().unwrap();
+        match fallback {
+            Some(book) => {
+                books.insert(channel_id.get(), book);
+            }
+            None => {
+                books.remove(&channel_id.get());
+            }
+        }
+        Ok(true)
+    }
+
+    /// サーバーの設定。`/setup` がまだなら `None`
+    pub fn guild_settings(&self, guild_id: GuildId) -> Option<GuildSettings> {
+        self.guilds.read().unwrap().get(&guild_id.get()).cloned()
+    }
+
+    /// サーバーのレシートの保管用チャンネル。`/setup` していないサーバーでは、シークレットの家計簿のサーバーでだけ
+    /// `configured`（`RECEIPT_CHANNEL_ID`）を使う。ほかのサーバーのチャンネルは返さない
+    pub fn receipt_channel(&self, guild_id: GuildId, configured: Option<ChannelId>) -> Option<ChannelId> {
+        match self.guild_settings(guild_id) {
+            Some(settings) => settings.receipt_channel_id.map(ChannelId::new),
+            None if self.is_home_guild(guild_id) => configured,
+            None => None,
+        }
+    }
+
+    /// 設定から家計簿を開く。保存はしない
+    pub fn open_guild(&self, guild_id: GuildId, settings: &GuildSettings) -> Result<Arc<Book>, Error> {
+        self.open(&settings.route(guild_id))
+    }
+
+    /// サーバーの設定を保存し、以後そのサーバーではこの家計簿を使う
+    pub fn save_guild(&self, guild_id: GuildId, settings: GuildSettings, book: Arc<Book>, actor: &Actor) -> Result<(), Error> {
+        self.claim(guild_id, &settings.spreadsheet_id, actor)?;
+        let mut guilds = self.guilds.write().unwrap();
+        let mut updated = guilds.clone();
+        let old = updated.insert(guild_id.get(), settings.clone());
+        let result = save_json(&self.data_dir.join("guilds.json"), &updated);
+        self.audit.record(
+            AuditRecord::new(actor, "guild.setup", "guilds.json")
+                .range(guild_id.to_string())
+                .old(old)
+                .new_value(settings)
+                .result(&result),
+        );
+        result?;
+        *guilds = updated;
+        self.guild_books.write().unwrap().insert(guild_id.get(), book);
+        Ok(())
+    }
+
+    fn open(&self, route: &Route) -> Result<Arc<Book>, Error> {
+        let users = route.users.clone().unwrap_or_default();
+        let mut book = Book::new(route.spreadsheet_id.clone(), users, self.credentials.clone());
+        book.layout = route.layout.clone();
+        book.timezone = route.timezone()?;
+        book.audit = Some(self.audit.clone());
+        book.health = Some(self.health.clone());
+        book.events = Some(self.events.clone());
+        Ok(Arc::new(book))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CREDENTIALS: &str = r#"{"client_email":"bot@example.com","private_key":"","token_uri":""}"#;
+
+    fn dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("hiratakebot-routing-{}-{}", name, std::process::id()))
+    }
+
+    fn open(dir: PathBuf) -> Ledgers {
+        let default = Arc::new(Book::new("default-sheet".to_string(), HashMap::new(), CREDENTIALS.to_string()));
+        Ledgers::new(
+            default,
+            CREDENTIALS.to_string(),
+            BTreeMap::new(),
+            dir.clone(),
+            Arc::new(AuditLog::new(dir)),
+            Arc::new(Health::default()),
+            Arc::new(EventBus::new(None)),
+        )
+        .unwrap()
+    }
+
+    fn ledgers(name: &str) -> Ledgers {
+        let _ = std::fs::remove_dir_all(dir(name));
+        open(dir(name))
+    }
+
+    fn route(spreadsheet_id: &str, guild_id: u64) -> Route {
+        Route { spreadsheet_id: spreadsheet_id.to_string(), layout: Layout::default(), timezone: None, users: None, guild_id: Some(guild_id) }
+    }
+
+    fn actor() -> Actor {
+        Actor { user_id: Some(1), user_name: "admin".to_string(), guild_id: None, message_id: None }
+    }
+
+    #[test]
+    fn refuses_spreadsheet_bound_by_another_guild() {
+        let ledgers = ledgers("bind");
+        ledgers.bind(ChannelId::new(10), route("sheet", 1), &actor()).unwrap();
+        // 同じサーバーならほかのチャンネルにも割り当てられる
+        ledgers.bind(ChannelId::new(11), route("sheet", 1), &actor()).unwrap();
+        assert!(ledgers.bind(ChannelId::new(20), route("sheet", 2), &actor()).is_err());
+        assert!(ledgers.get(ChannelId::new(20)).is_none());
+        assert!(ledgers.check_owner(GuildId::new(2), "other-sheet").is_ok());
+    }
+
+    #[test]
+    fn keeps_owners_across_restarts() {
+        let first = ledgers("restart");
+        first.bind(ChannelId::new(10), route("sheet", 1), &actor()).unwrap();
+        first.unbind(GuildId::new(1), ChannelId::new(10), &actor()).unwrap();
+
+        let second = open(dir("restart"));
+        assert!(second.check_owner(GuildId::new(2), "sheet").is_err());
+        assert!(second.check_owner(GuildId::new(1), "sheet").is_ok());
+    }
+
+    #[test]
+    fn keeps_bindings_unchanged_when_saving_fails() {
+        let ledgers = ledgers("save-failure");
+        ledgers.bind(ChannelId::new(10), route("sheet", 1), &actor()).unwrap();
+        // 保存先をディレクトリにして書き込めなくする
+        let path = dir("save-failure").join("ledgers.json");
+        std::fs::remove_file(&path).unwrap();
+        std::fs::create_dir_all(path.join("child")).unwrap();
+
+        assert!(ledgers.bind(ChannelId::new(11), route("sheet", 1), &actor()).is_err());
+        assert!(ledgers.get(ChannelId::new(11)).is_none());
+        assert!(!ledgers.routes(GuildId::new(1)).contains_key(&11));
+        assert!(ledgers.unbind(GuildId::new(1), ChannelId::new(10), &actor()).is_err());
+        assert!(ledgers.get(ChannelId::new(10)).is_some());
+        assert!(ledgers.routes(GuildId::new(1)).contains_key(&10));
+
+        let guilds = dir("save-failure").join("guilds.json");
+        std::fs::create_dir_all(guilds.join("child")).unwrap();
+        let settings = GuildSettings {
+            expenses_channel_id: 10,
+            spreadsheet_id: "sheet".to_string(),
+            layout: Layout::default(),
+            timezone: None,
+            receipt_channel_id: None,
+        };
+        let book = ledgers.open_guild(GuildId::new(1), &settings).unwrap();
+        assert!(ledgers.save_guild(GuildId::new(1), settings, book, &actor()).is_err());
+        assert!(ledgers.guild_settings(GuildId::new(1)).is_none());
+        assert!(ledgers.for_guild(GuildId::new(1)).is_none());
+    }
+
+    #[test]
+    fn secret_spreadsheet_belongs_to_home_guild() {
+        let ledgers = ledgers("secret");
+        assert!(ledgers.check_owner(GuildId::new(2), "default-sheet").is_err());
+        ledgers.set_home_guild(GuildId::new(1));
+        assert!(ledgers.check_owner(GuildId::new(1), "default-sheet").is_ok());
+        assert!(ledgers.check_owner(GuildId::new(2), "default-sheet").is_err());
+    }
+}
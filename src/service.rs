@@ -0,0 +1,79 @@
+use crate::auth::Auth;
+use crate::http_server;
+use anyhow::Context as _;
+use poise::serenity_prelude as serenity;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::info;
+
+/// Discord のクライアントと HTTP サーバーをまとめて動かす Shuttle のサービス。
+/// どちらかが止まるか終了のシグナルを受けたら、もう一方も止める
+pub struct BotService {
+    pub client: serenity::Client,
+    /// HTTP API のハンドラーで使う家計簿や設定
+    pub state: http_server::AppState,
+    /// 待ち受けるアドレス。`None` なら Shuttle が渡すアドレス
+    pub http_addr: Option<SocketAddr>,
+    /// HTTP API の認証
+    pub auth: Arc<Auth>,
+}
+
+#[shuttle_runtime::async_trait]
+impl shuttle_runtime::Service for BotService {
+    async fn bind(mut self, addr: SocketAddr) -> Result<(), shuttle_runtime::Error> {
+        let addr = self.http_addr.unwrap_or(addr);
+        let listener = http_server::bind(addr)
+            .await
+            .with_context(|| format!("failed to bind the HTTP server to {}", addr))?;
+        info!("HTTP server listening on {}", addr);
+
+        let app = http_server::router(self.state.clone(), self.auth.clone());
+        let (stop_tx, mut stop_rx) = tokio::sync::watch::channel(());
+        let mut server = tokio::spawn(http_server::serve(listener, app, async move {
+            let _ = stop_rx.changed().await;
+        }));
+        let shard_manager = self.client.shard_manager.clone();
+
+        let outcome = tokio::select! {
+            result = self.client.start_autosharded() => result.context("the Discord client stopped"),
+            result = &mut server => {
+                shard_manager.shutdown_all().await;
+                result.context("the HTTP server panicked")?.context("the HTTP server stopped")?;
+                return Ok(());
+            }
+            _ = shutdown_signal() => {
+                info!("shutting down");
+                shard_manager.shutdown_all().await;
+                Ok(())
+            }
+        };
+
+        let _ = stop_tx.send(());
+        server.await.context("the HTTP server panicked")?.context("the HTTP server stopped")?;
+        outcome?;
+        Ok(())
+    }
+}
+
+/// Ctrl+C か SIGTERM を待つ
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
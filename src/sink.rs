@@ -0,0 +1,97 @@
+use poise::serenity_prelude::{
+    async_trait, ChannelId, CreateAllowedMentions, CreateAttachment, CreateEmbed, CreateMessage, CreateThread, Http, MessageId,
+};
+use std::sync::Arc;
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// `/send-message` で送るメッセージ
+#[derive(Debug, Clone)]
+pub struct OutgoingMessage {
+    pub channel_id: ChannelId,
+    pub content: String,
+    pub embeds: Vec<CreateEmbed>,
+    /// `None` なら本文のメンションを全て通知する
+    pub allowed_mentions: Option<CreateAllowedMentions>,
+    /// 返信する元のメッセージ（同じチャンネル）
+    pub reply_to: Option<MessageId>,
+    pub files: Vec<CreateAttachment>,
+}
+
+/// Discord への送信先。本番は `SerenitySink`、テストでは送ったものを覚えておくだけのものに差し替える
+#[async_trait]
+pub trait MessageSink: Send + Sync {
+    /// 送ったメッセージの ID を返す
+    async fn send(&self, message: OutgoingMessage) -> Result<MessageId, Error>;
+
+    /// 送ったメッセージからスレッドを作り、その ID を返す
+    async fn create_thread(&self, channel_id: ChannelId, message_id: MessageId, name: String) -> Result<ChannelId, Error>;
+}
+
+/// Discord の API で送る
+pub struct SerenitySink {
+    http: Arc<Http>,
+}
+
+impl SerenitySink {
+    pub fn new(http: Arc<Http>) -> Self {
+        Self { http }
+    }
+}
+
+#[async_trait]
+impl MessageSink for SerenitySink {
+    async fn send(&self, message: OutgoingMessage) -> Result<MessageId, Error> {
+        let mut builder = CreateMessage::new()
+            .content(message.content)
+            .embeds(message.embeds)
+            .add_files(message.files);
+        if let Some(mentions) = message.allowed_mentions {
+            builder = builder.allowed_mentions(mentions);
+        }
+        if let Some(reply_to) = message.reply_to {
+            builder = builder.reference_message((message.channel_id, reply_to));
+        }
+        let sent = message.channel_id.send_message(&self.http, builder).await?;
+        Ok(sent.id)
+    }
+
+    async fn create_thread(&self, channel_id: ChannelId, message_id: MessageId, name: String) -> Result<ChannelId, Error> {
+        let thread = channel_id
+            .create_thread_from_message(&self.http, message_id, CreateThread::new(name))
+            .await?;
+        Ok(thread.id)
+    }
+}
+
+/// 送ったものを覚えておくだけの送信先。`fail_send` や `fail_thread` を立てると Discord のエラーを真似る
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemorySink {
+    pub sent: std::sync::Mutex<Vec<OutgoingMessage>>,
+    pub threads: std::sync::Mutex<Vec<(ChannelId, MessageId, String)>>,
+    pub fail_send: bool,
+    pub fail_thread: bool,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl MessageSink for MemorySink {
+    async fn send(&self, message: OutgoingMessage) -> Result<MessageId, Error> {
+        if self.fail_send {
+            return Err("Missing Permissions".into());
+        }
+        let mut sent = self.sent.lock().unwrap();
+        sent.push(message);
+        Ok(MessageId::new(1000 + sent.len() as u64))
+    }
+
+    async fn create_thread(&self, channel_id: ChannelId, message_id: MessageId, name: String) -> Result<ChannelId, Error> {
+        if self.fail_thread {
+            return Err("Missing Permissions".into());
+        }
+        let mut threads = self.threads.lock().unwrap();
+        threads.push((channel_id, message_id, name));
+        Ok(ChannelId::new(2000 + threads.len() as u64))
+    }
+}
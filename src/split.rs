@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+/// 1人分の負担額
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Share {
+    pub user: String,
+    pub amount: i64,
+}
+
+/// 割り勘の指定（`@alice:2` なら `alice` を重み 2 で）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    pub user: String,
+    pub weight: u32,
+}
+
+/// `alice`、`alice:2`、`<@123>:2` を読み、`users` に登録されている人なら返す。登録されていない人なら `Ok(None)`
+///
+/// 登録されている人でも、重みが 1 以上の整数でなければエラーにする
+pub fn parse_member(tag: &str, users: &HashMap<u64, String>) -> Result<Option<Member>, String> {
+    let (name, weight) = match tag.split_once([':', '：']) {
+        Some((name, weight)) => (name, Some(weight)),
+        None => (tag, None),
+    };
+
+    let user = match name.strip_prefix("<@").and_then(|id| id.strip_suffix('>')) {
+        Some(id) => id.trim_start_matches('!').parse().ok().and_then(|id| users.get(&id)),
+        None => users.values().find(|u| u.to_lowercase() == name.to_lowercase()),
+    };
+    let Some(user) = user else {
+        return Ok(None);
+    };
+    let weight = match weight {
+        Some(weight) => weight
+            .parse::<u32>()
+            .ok()
+            .filter(|w| *w > 0)
+            .ok_or_else(|| format!("@{} の重みは 1 以上の整数で指定してください", tag))?,
+        None => 1,
+    };
+    Ok(Some(Member { user: user.clone(), weight }))
+}
+
+/// `total` を重みに応じて分ける。端数は切り捨てた残りを、切り捨て分の大きい人から1円ずつ配って合計を合わせる
+/// （同じなら先に並んでいる人から）。重みの合計が 0 なら分けられないのでエラーにする
+pub fn allocate(total: i64, members: &[Member]) -> Result<Vec<Share>, String> {
+    let weight_sum: i64 = members.iter().map(|m| m.weight as i64).sum();
+    if weight_sum == 0 {
+        return Err("割り勘の重みが全て 0 です".to_string());
+    }
+
+    let sign = total.signum();
+    let total = total.abs();
+    let mut shares: Vec<(i64, i64)> = members
+        .iter()
+        .map(|m| {
+            let exact = total * m.weight as i64;
+            (exact / weight_sum, exact % weight_sum)
+        })
+        .collect();
+
+    let remainder = total - shares.iter().map(|(base, _)| base).sum::<i64>();
+    let mut order: Vec<usize> = (0..shares.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(shares[i].1));
+    for &i in order.iter().take(remainder as usize) {
+        shares[i].0 += 1;
+    }
+
+    Ok(members
+        .iter()
+        .zip(shares)
+        .map(|(m, (amount, _))| Share {
+            user: m.user.clone(),
+            amount: amount * sign,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn members(weights: &[(&str, u32)]) -> Vec<Member> {
+        weights.iter().map(|(user, weight)| Member { user: user.to_string(), weight: *weight }).collect()
+    }
+
+    fn amounts(shares: &[Share]) -> Vec<i64> {
+        shares.iter().map(|s| s.amount).collect()
+    }
+
+    #[test]
+    fn splits_evenly_without_remainder() {
+        let shares = allocate(900, &members(&[("a", 1), ("b", 1), ("c", 1)])).unwrap();
+        assert_eq!(amounts(&shares), vec![300, 300, 300]);
+    }
+
+    #[test]
+    fn gives_remainder_to_first_members_on_ties() {
+        let shares = allocate(1000, &members(&[("a", 1), ("b", 1), ("c", 1)])).unwrap();
+        assert_eq!(amounts(&shares), vec![334, 333, 333]);
+    }
+
+    #[test]
+    fn gives_remainder_to_largest_fraction() {
+        // 1000 * 1/6 = 166.67、1000 * 2/6 = 333.33、1000 * 3/6 = 500
+        let shares = allocate(1000, &members(&[("a", 1), ("b", 2), ("c", 3)])).unwrap();
+        assert_eq!(amounts(&shares), vec![167, 333, 500]);
+    }
+
+    #[test]
+    fn totals_always_match() {
+        let groups = [
+            members(&[("a", 1), ("b", 1), ("c", 1)]),
+            members(&[("a", 1), ("b", 2), ("c", 3)]),
+            members(&[("a", 7), ("b", 3)]),
+            members(&[("a", 1), ("b", 1), ("c", 1), ("d", 1), ("e", 1), ("f", 1), ("g", 1)]),
+        ];
+        for group in &groups {
+            for total in [0, 1, 2, 99, 100, 101, 997, 1000, 12345, -1, -100, -997] {
+                let shares = allocate(total, group).unwrap();
+                assert_eq!(shares.iter().map(|s| s.amount).sum::<i64>(), total, "{} {:?}", total, group);
+                let max = shares.iter().map(|s| s.amount).max().unwrap();
+                let min = shares.iter().map(|s| s.amount).min().unwrap();
+                if group.iter().all(|m| m.weight == 1) {
+                    assert!(max - min <= 1, "{} {:?}", total, shares);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn splits_refunds_with_same_rounding() {
+        let shares = allocate(-1000, &members(&[("a", 1), ("b", 1), ("c", 1)])).unwrap();
+        assert_eq!(amounts(&shares), vec![-334, -333, -333]);
+    }
+
+    #[test]
+    fn rejects_zero_total_weight() {
+        assert!(allocate(1000, &members(&[("a", 0)])).is_err());
+        assert!(allocate(1000, &[]).is_err());
+    }
+
+    #[test]
+    fn parses_members_and_weights() {
+        let users = HashMap::from([(1, "Alice".to_string()), (2, "Bob".to_string())]);
+        assert_eq!(parse_member("alice", &users), Ok(Some(Member { user: "Alice".to_string(), weight: 1 })));
+        assert_eq!(parse_member("<@2>:3", &users), Ok(Some(Member { user: "Bob".to_string(), weight: 3 })));
+        assert_eq!(parse_member("Bob：2", &users), Ok(Some(Member { user: "Bob".to_string(), weight: 2 })));
+        assert_eq!(parse_member("carol", &users), Ok(None));
+        assert_eq!(parse_member("carol:2", &users), Ok(None));
+    }
+
+    #[test]
+    fn rejects_zero_or_invalid_weights() {
+        let users = HashMap::from([(1, "Alice".to_string())]);
+        assert!(parse_member("alice:0", &users).is_err());
+        assert!(parse_member("alice:-1", &users).is_err());
+        assert!(parse_member("alice:x", &users).is_err());
+    }
+}
@@ -2,6 +2,22 @@ use serde::{Deserialize, Serialize};
 use serde_json::json;
 use jsonwebtoken::{encode, EncodingKey, Header};
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use crate::audit::{Actor, AuditLog, AuditRecord};
+use crate::events::{EventBus, EventKind};
+use crate::expenses_api::Expense;
+use crate::health::Health;
+use crate::telemetry;
+use crate::ledger::{column_index, total_spending, Entry, Layout, Month};
+use chrono::{FixedOffset, NaiveDate};
+use tracing::warn;
+
+/// スプレッドシートごとの追記のロック。同じシートを開いた `Book` が複数あっても、末尾の行を読んでから書き込むまでを1つずつにする
+fn append_lock(spreadsheet_id: &str) -> Arc<tokio::sync::Mutex<()>> {
+  static LOCKS: OnceLock<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>> = OnceLock::new();
+  let mut locks = LOCKS.get_or_init(Default::default).lock().unwrap();
+  locks.entry(spreadsheet_id.to_string()).or_default().clone()
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct User(pub HashMap<u64, String>);
@@ -12,6 +28,20 @@ pub struct Book {
   pub users: User,
   credentials: Credentials,
   access_token: Option<String>,
+  #[serde(default)]
+  pub layout: Layout,
+  /// 日付を決めるタイムゾーン。`None` ならサーバーのタイムゾーン
+  #[serde(skip)]
+  pub timezone: Option<FixedOffset>,
+  /// 書き込みを記録する監査ログ
+  #[serde(skip)]
+  pub audit: Option<Arc<AuditLog>>,
+  /// Sheets API の結果を残す稼働状況
+  #[serde(skip)]
+  pub health: Option<Arc<Health>>,
+  /// 書き込みを `/events` に流す
+  #[serde(skip)]
+  pub events: Option<Arc<EventBus>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -34,10 +64,28 @@ impl Book {
   pub fn new(id: String, users:HashMap<u64, String>, credentials: String) -> Self {
     let credentials: Credentials = serde_json::from_str(credentials.as_str()).unwrap();
     let users: User = User(users);
-    Self { id, users, credentials, access_token: None }
+    Self { id, users, credentials, access_token: None, layout: Layout::default(), timezone: None, audit: None, health: None, events: None }
+  }
+
+  /// この家計簿のタイムゾーンでの今日
+  pub fn today(&self) -> NaiveDate {
+    match self.timezone {
+      Some(offset) => chrono::Utc::now().with_timezone(&offset).date_naive(),
+      None => chrono::Local::now().date_naive(),
+    }
+  }
+
+  /// スプレッドシートの ID
+  pub fn id(&self) -> &str {
+    &self.id
+  }
+
+  /// スプレッドシートを共有する相手（サービスアカウント）のメールアドレス
+  pub fn service_account(&self) -> &str {
+    &self.credentials.client_email
   }
 
-  fn create_jwt(&self) -> Result<String, Box<dyn std::error::Error>> {
+  fn create_jwt(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
     let now = chrono::Utc::now();
     let iat = now.timestamp();
     let exp = (now + chrono::Duration::hours(1)).timestamp();
@@ -57,24 +105,53 @@ impl Book {
   }
 
   // アクセストークンの取得
-  pub async fn get_access_token(&self) -> Result<String, Box<dyn std::error::Error>> {
+  pub async fn get_access_token(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
     let client = reqwest::Client::new();
-    let token_response = client
-        .post(&self.credentials.token_uri)
-        .json(&json!({
-            "grant_type": "urn:ietf:params:oauth:grant-type:jwt-bearer",
-            "assertion": self.create_jwt()?,
-        }))
-        .send()
-        .await?
-        .json::<serde_json::Value>()
-        .await?;
+    let result: Result<(String, i64), Box<dyn std::error::Error + Send + Sync>> = async {
+      let token_response = client
+          .post(&self.credentials.token_uri)
+          .json(&json!({
+              "grant_type": "urn:ietf:params:oauth:grant-type:jwt-bearer",
+              "assertion": self.create_jwt()?,
+          }))
+          .send()
+          .await?
+          .json::<serde_json::Value>()
+          .await?;
 
-    let access_token = token_response["access_token"].as_str().unwrap();
-    Ok(access_token.to_string())
+      match token_response["access_token"].as_str() {
+        Some(access_token) => Ok((access_token.to_string(), token_response["expires_in"].as_i64().unwrap_or(3600))),
+        None => Err(format!("アクセストークンを取得できませんでした: {}", token_response).into()),
+      }
+    }
+    .await;
+
+    telemetry::token_refreshed(result.is_ok());
+    if let Some(health) = &self.health {
+      match &result {
+        Ok((_, expires_in)) => health.token_refreshed(chrono::Utc::now() + chrono::Duration::seconds(*expires_in)),
+        Err(e) => health.token_failed(e.to_string()),
+      }
+    }
+    result.map(|(access_token, _)| access_token)
+  }
+
+  // Sheets API にリクエストを送り、結果を稼働状況と集計に残す
+  async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, Box<dyn std::error::Error + Send + Sync>> {
+    let started = std::time::Instant::now();
+    let result = request.send().await;
+    telemetry::sheets_request(result.as_ref().ok().map(|r| r.status().as_u16()), started.elapsed());
+    if let Some(health) = &self.health {
+      match &result {
+        Ok(response) if response.status().is_success() => health.sheets_succeeded(),
+        Ok(response) => health.sheets_failed(format!("HTTP {}", response.status())),
+        Err(e) => health.sheets_failed(e.to_string()),
+      }
+    }
+    Ok(result?)
   }
 
-  pub async fn get_last_row(&self, range: &str) -> Result<i64, Box<dyn std::error::Error>> {
+  pub async fn get_last_row(&self, range: &str) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
     let access_token = self.get_access_token().await?;
     let client = reqwest::Client::new();
     let url = format!(
@@ -82,11 +159,10 @@ impl Book {
         self.id, range
     );
 
-    let response = client
+    let request = client
         .get(&url)
-        .bearer_auth(access_token)
-        .send()
-        .await?;
+        .bearer_auth(access_token);
+    let response = self.send(request).await?;
 
     let result = response.json::<serde_json::Value>().await?;
     let num_rows = result["values"].as_array().map_or(0, |v| v.len()) as i64;
@@ -95,7 +171,7 @@ impl Book {
   }
 
   // テキストの書き込み
-  pub async fn write_text(&self, range: &str, values: Vec<Vec<serde_json::Value>>) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
+  pub async fn write_text(&self, range: &str, values: Vec<Vec<serde_json::Value>>) -> Result<reqwest::Response, Box<dyn std::error::Error + Send + Sync>> {
     let value_input_option = "USER_ENTERED";
     let access_token = self.get_access_token().await?;
     let client = reqwest::Client::new();
@@ -104,16 +180,273 @@ impl Book {
         self.id, range
     );
 
-    let response = client
+    let request = client
         .put(&url)
         .bearer_auth(access_token)
         .json(&json!({
             "values": values
         }))
-        .query(&[("valueInputOption", value_input_option)])
-        .send()
-        .await?;
+        .query(&[("valueInputOption", value_input_option)]);
+    let response = self.send(request).await?;
 
     Ok(response)
   }
+
+  // スプレッドシートの名前。開けるかどうかの確認にも使う
+  pub async fn title(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let access_token = self.get_access_token().await?;
+    let client = reqwest::Client::new();
+    let url = format!("https://sheets.googleapis.com/v4/spreadsheets/{}", self.id);
+
+    let request = client
+        .get(&url)
+        .bearer_auth(access_token)
+        .query(&[("fields", "properties.title")]);
+    let response = self.send(request).await?;
+
+    let result = response.json::<serde_json::Value>().await?;
+    if let Some(message) = result["error"]["message"].as_str() {
+        return Err(message.into());
+    }
+    Ok(result["properties"]["title"].as_str().unwrap_or_default().to_string())
+  }
+
+  // シートの名前の一覧
+  pub async fn sheet_names(&self) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let access_token = self.get_access_token().await?;
+    let client = reqwest::Client::new();
+    let url = format!("https://sheets.googleapis.com/v4/spreadsheets/{}", self.id);
+
+    let request = client
+        .get(&url)
+        .bearer_auth(access_token)
+        .query(&[("fields", "sheets.properties.title")]);
+    let response = self.send(request).await?;
+
+    let result = response.json::<serde_json::Value>().await?;
+    if let Some(message) = result["error"]["message"].as_str() {
+        return Err(message.into());
+    }
+    let names = result["sheets"]
+        .as_array()
+        .map(|sheets| sheets.iter().filter_map(|s| s["properties"]["title"].as_str().map(str::to_string)).collect())
+        .unwrap_or_default();
+    Ok(names)
+  }
+
+  // 値の読み込み（表示形式のまま）
+  pub async fn read_values(&self, range: &str) -> Result<Vec<Vec<serde_json::Value>>, Box<dyn std::error::Error + Send + Sync>> {
+    let access_token = self.get_access_token().await?;
+    let client = reqwest::Client::new();
+    let url = format!(
+        "https://sheets.googleapis.com/v4/spreadsheets/{}/values/{}",
+        self.id, range
+    );
+
+    let request = client
+        .get(&url)
+        .bearer_auth(access_token);
+    let response = self.send(request).await?;
+
+    let result = response.json::<serde_json::Value>().await?;
+    if let Some(message) = result["error"]["message"].as_str() {
+        return Err(message.into());
+    }
+    let rows = result["values"]
+        .as_array()
+        .map(|rows| {
+            rows.iter()
+                .map(|row| row.as_array().cloned().unwrap_or_default())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(rows)
+  }
+
+  // 月の明細を読み込む
+  // 月の明細を読み込む。シートのない月は明細がないものとする。`sheets` は `sheet_names` で取得したもの
+  pub async fn read_month_if_exists(&self, month: Month, sheets: &[String]) -> Result<Vec<Entry>, Box<dyn std::error::Error + Send + Sync>> {
+    if !sheets.contains(&self.layout.sheet_name(month)) {
+      return Ok(Vec::new());
+    }
+    self.read_month(month).await
+  }
+
+  pub async fn read_month(&self, month: Month) -> Result<Vec<Entry>, Box<dyn std::error::Error + Send + Sync>> {
+    let range = self.layout.entries_range(month);
+    let rows = self.read_values(&range).await?;
+    let entries = rows
+        .iter()
+        .enumerate()
+        .filter_map(|(i, row)| self.layout.parse_row(self.layout.first_row + i as i64, row, month))
+        .collect();
+
+    Ok(entries)
+  }
+
+  // 書いても読み戻せない明細を断る
+  fn check_columns(&self, entry: &Entry) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match self.layout.missing_column(entry) {
+      Some(column) => Err(format!("この家計簿には「{}」の列がないため記録できません。レイアウトで列を指定してください", column).into()),
+      None => Ok(()),
+    }
+  }
+
+  fn audit(&self, record: AuditRecord) {
+    if let Some(audit) = &self.audit {
+      audit.record(record);
+    }
+  }
+
+  fn publish(&self, actor: &Actor, kind: EventKind) {
+    if let Some(events) = &self.events {
+      events.publish(&self.id, actor, kind);
+    }
+  }
+
+  // 書き込みで月の支出が予算を超えたら知らせる。`added` は今回増えた支出
+  async fn check_budget(&self, month: Month, added: i64, actor: &Actor) {
+    let Some(budget) = self.events.as_ref().and_then(|events| events.monthly_budget) else {
+      return;
+    };
+    if added <= 0 {
+      return;
+    }
+    match self.read_month(month).await {
+      Ok(entries) => {
+        let total = total_spending(&entries);
+        if total > budget && total - added <= budget {
+          self.publish(actor, EventKind::BudgetExceeded { month: month.to_string(), total, budget });
+        }
+      }
+      Err(e) => warn!("failed to check the budget of {}: {:?}", month, e),
+    }
+  }
+
+  // 明細を各月のシートの末尾に追記する。行番号を埋めた明細を返す
+  pub async fn append_entries(&self, entries: &[Entry], actor: &Actor) -> Result<Vec<Entry>, Box<dyn std::error::Error + Send + Sync>> {
+    for entry in entries {
+      self.check_columns(entry)?;
+    }
+    let mut by_month: Vec<(Month, Vec<Entry>)> = Vec::new();
+    for entry in entries {
+      let month = Month::of(entry.date.unwrap_or_else(|| self.today()));
+      match by_month.iter_mut().find(|(m, _)| *m == month) {
+        Some((_, group)) => group.push(entry.clone()),
+        None => by_month.push((month, vec![entry.clone()])),
+      }
+    }
+
+    let mut written = Vec::new();
+    for (month, mut group) in by_month {
+      let sheet = self.layout.sheet_name(month);
+      let first_row = self.layout.first_row;
+      let range = format!("{}!A{}:C", sheet, first_row);
+      // ほかの書き込みと同じ行に書かないよう、書き終えるまでロックを持つ
+      let lock = append_lock(&self.id);
+      let guard = lock.lock().await;
+      let row = match self.get_last_row(&range).await {
+        Ok(last_row) => last_row + first_row,
+        Err(e) => {
+          self.audit(AuditRecord::new(actor, "append", &self.id).range(&range).new_value(&group).result::<(), _>(&Err(&e)));
+          return Err(e);
+        }
+      };
+
+      for (i, entry) in group.iter_mut().enumerate() {
+        entry.row = row + i as i64;
+      }
+      let values: Vec<Vec<serde_json::Value>> = group.iter().map(|entry| self.layout.to_row(entry)).collect();
+      let range = format!("{}!A{}", sheet, row);
+      let result = match self.write_text(&range, values.clone()).await {
+        Ok(response) => response.error_for_status().map(|_| ()).map_err(Into::into),
+        Err(e) => Err(e),
+      };
+      let audited = format!("{}!A{}:{}{}", sheet, row, self.layout.last_column(), row + group.len() as i64 - 1);
+      drop(guard);
+      self.audit(AuditRecord::new(actor, "append", &self.id).range(audited).new_value(&values).result(&result));
+      result?;
+      let expenses = group.iter().map(|entry| Expense::in_month(month, entry.clone())).collect();
+      self.publish(actor, EventKind::ExpenseCreated { expenses });
+      self.check_budget(month, total_spending(&group), actor).await;
+      written.extend(group);
+    }
+
+    Ok(written)
+  }
+
+  // 1行分の明細を読み込む。明細のない行なら `None`
+  pub async fn read_entry(&self, month: Month, row: i64) -> Result<Option<Entry>, Box<dyn std::error::Error + Send + Sync>> {
+    if row < self.layout.first_row {
+      return Ok(None);
+    }
+    let range = format!("{}!A{}:{}{}", self.layout.sheet_name(month), row, self.layout.last_column(), row);
+    let rows = self.read_values(&range).await?;
+    Ok(rows.first().and_then(|values| self.layout.parse_row(row, values, month)))
+  }
+
+  // 明細の行を書き換える。空の項目はセルを空にする
+  pub async fn update_entry(&self, month: Month, old: &Entry, entry: &Entry, actor: &Actor) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    self.check_columns(entry)?;
+    let mut values = self.layout.to_row(entry);
+    for (_, column) in self.layout.columns() {
+      let cell = &mut values[column_index(column)];
+      if cell.is_null() {
+        *cell = serde_json::Value::String(String::new());
+      }
+    }
+    self.overwrite_row(month, entry.row, values, "update", old, actor).await?;
+    self.publish(actor, EventKind::ExpenseUpdated {
+      old: Box::new(Expense::in_month(month, old.clone())),
+      expense: Box::new(Expense::in_month(month, entry.clone())),
+    });
+    let added = total_spending(std::slice::from_ref(entry)) - total_spending(std::slice::from_ref(old));
+    self.check_budget(month, added, actor).await;
+    Ok(())
+  }
+
+  // 明細の行を空にする。後ろの行の番号が変わらないよう、行そのものは消さない
+  pub async fn delete_entry(&self, month: Month, old: &Entry, actor: &Actor) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut values = vec![serde_json::Value::Null; column_index(&self.layout.last_column()) + 1];
+    for (_, column) in self.layout.columns() {
+      values[column_index(column)] = serde_json::Value::String(String::new());
+    }
+    self.overwrite_row(month, old.row, values, "delete", old, actor).await?;
+    self.publish(actor, EventKind::ExpenseDeleted { expense: Box::new(Expense::in_month(month, old.clone())) });
+    Ok(())
+  }
+
+  async fn overwrite_row(
+    &self,
+    month: Month,
+    row: i64,
+    values: Vec<serde_json::Value>,
+    action: &str,
+    old: &Entry,
+    actor: &Actor,
+  ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let sheet = self.layout.sheet_name(month);
+    let range = format!("{}!A{}", sheet, row);
+    let result = match self.write_text(&range, vec![values.clone()]).await {
+      Ok(response) => response.error_for_status().map(|_| ()).map_err(Into::into),
+      Err(e) => Err(e),
+    };
+    let audited = format!("{}!A{}:{}{}", sheet, row, self.layout.last_column(), row);
+    self.audit(AuditRecord::new(actor, action, &self.id).range(audited).old(self.layout.to_row(old)).new_value(&values).result(&result));
+    result
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn append_lock_is_shared_per_spreadsheet() {
+    let lock = append_lock("append-lock-test-a");
+    let _guard = lock.lock().await;
+    assert!(append_lock("append-lock-test-a").try_lock().is_err());
+    assert!(append_lock("append-lock-test-b").try_lock().is_ok());
+  }
 }
@@ -0,0 +1,271 @@
+use crate::ledger::{parse_amount, Entry};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// カード会社・銀行ごとの明細 CSV の読み方
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatementProfile {
+    pub name: String,
+    /// `shift_jis` または `utf-8`
+    #[serde(default = "default_encoding")]
+    pub encoding: String,
+    /// 先頭の読み飛ばす行数（見出しやカード番号の行）
+    #[serde(default)]
+    pub skip_rows: usize,
+    /// 各項目の列番号（0 始まり）
+    pub date_column: usize,
+    pub description_column: usize,
+    pub amount_column: usize,
+    /// 日付の書式。省略時は一般的な書式と和暦を順に試す
+    #[serde(default)]
+    pub date_format: Option<String>,
+    /// 利用日と記録日のずれをどこまで許すか（日）
+    #[serde(default)]
+    pub date_tolerance_days: i64,
+    /// この明細に対応する支払い方法。照合の対象を絞り、追加する行にも書き込む
+    #[serde(default)]
+    pub payment_method: Option<String>,
+}
+
+fn default_encoding() -> String {
+    "utf-8".to_string()
+}
+
+/// 明細 CSV の1行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatementLine {
+    pub date: NaiveDate,
+    pub description: String,
+    pub amount: i64,
+}
+
+/// 照合結果
+#[derive(Debug, Default)]
+pub struct Reconciliation {
+    pub matched: Vec<(StatementLine, Entry)>,
+    /// 明細にあって家計簿にない行
+    pub missing_in_ledger: Vec<StatementLine>,
+    /// 家計簿にあって明細にない行
+    pub missing_in_statement: Vec<Entry>,
+}
+
+impl StatementProfile {
+    /// CSV を読み、日付と金額を解釈できた行だけを返す（合計行などは読み飛ばす）
+    pub fn parse(&self, bytes: &[u8]) -> Result<Vec<StatementLine>, Error> {
+        let text = decode(bytes, &self.encoding)?;
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(text.as_bytes());
+
+        let mut lines = Vec::new();
+        for record in reader.records().skip(self.skip_rows) {
+            let record = record?;
+            let cell = |i: usize| record.get(i).unwrap_or("").trim();
+            let date = match self.parse_date(cell(self.date_column)) {
+                Some(date) => date,
+                None => continue,
+            };
+            let amount = match parse_amount(cell(self.amount_column)) {
+                Some(amount) => amount,
+                None => continue,
+            };
+            lines.push(StatementLine {
+                date,
+                description: cell(self.description_column).to_string(),
+                amount,
+            });
+        }
+
+        Ok(lines)
+    }
+
+    fn parse_date(&self, s: &str) -> Option<NaiveDate> {
+        match &self.date_format {
+            Some(format) => NaiveDate::parse_from_str(s, format).ok(),
+            None => parse_japanese_date(s),
+        }
+    }
+}
+
+/// 明細の文字コードを UTF-8 に変換する
+pub fn decode(bytes: &[u8], encoding: &str) -> Result<String, Error> {
+    let encoding = encoding_rs::Encoding::for_label(encoding.as_bytes())
+        .ok_or_else(|| format!("未対応の文字コードです: {}", encoding))?;
+    let (text, _, had_errors) = encoding.decode(bytes);
+    if had_errors {
+        return Err(format!("{} として読めない文字があります", encoding.name()).into());
+    }
+    Ok(text.into_owned())
+}
+
+/// `2026/09/03`、`2026年9月3日`、`20260903`、和暦（`R8.9.3`、`令和8年9月3日`）を受け付ける
+pub fn parse_japanese_date(s: &str) -> Option<NaiveDate> {
+    let s = s.trim();
+    for format in ["%Y/%m/%d", "%Y-%m-%d", "%Y.%m.%d", "%Y年%m月%d日", "%Y%m%d"] {
+        if let Ok(date) = NaiveDate::parse_from_str(s, format) {
+            return Some(date);
+        }
+    }
+
+    // 和暦は元号の元年の前年を足して西暦にする
+    let eras = [("令和", 2018), ("R", 2018), ("平成", 1988), ("H", 1988)];
+    let (offset, rest) = eras
+        .iter()
+        .find_map(|(prefix, offset)| s.strip_prefix(prefix).map(|rest| (*offset, rest)))?;
+    let numbers: Vec<u32> = rest
+        .split(|c: char| !c.is_ascii_digit() && c != '元')
+        .filter(|part| !part.is_empty())
+        .map(|part| if part == "元" { Some(1) } else { part.parse().ok() })
+        .collect::<Option<_>>()?;
+    match numbers[..] {
+        [year, month, day] => NaiveDate::from_ymd_opt(offset + year as i32, month, day),
+        _ => None,
+    }
+}
+
+/// 明細と家計簿を日付と金額で1対1に突き合わせる。日付のずれが小さいものを優先する
+pub fn reconcile(lines: Vec<StatementLine>, entries: Vec<Entry>, tolerance_days: i64) -> Reconciliation {
+    let mut remaining: Vec<Option<Entry>> = entries.into_iter().map(Some).collect();
+    let mut result = Reconciliation::default();
+
+    for line in lines {
+        let best = remaining
+            .iter()
+            .enumerate()
+            .filter_map(|(i, entry)| {
+                let entry = entry.as_ref()?;
+                let date = entry.date?;
+                let gap = (date - line.date).num_days().abs();
+                (entry.amount == line.amount && gap <= tolerance_days).then_some((gap, i))
+            })
+            .min();
+
+        match best {
+            Some((_, i)) => {
+                let entry = remaining[i].take().unwrap();
+                result.matched.push((line, entry));
+            }
+            None => result.missing_in_ledger.push(line),
+        }
+    }
+
+    result.missing_in_statement = remaining.into_iter().flatten().collect();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::Kind;
+
+    const CARD_SAMPLE: &[u8] = include_bytes!("../tests/fixtures/card_sjis.csv");
+
+    fn profile() -> StatementProfile {
+        StatementProfile {
+            name: "楽天カード".to_string(),
+            encoding: "shift_jis".to_string(),
+            skip_rows: 3,
+            date_column: 0,
+            description_column: 1,
+            amount_column: 6,
+            date_format: None,
+            date_tolerance_days: 2,
+            payment_method: Some("楽天カード".to_string()),
+        }
+    }
+
+    fn line(day: u32, description: &str, amount: i64) -> StatementLine {
+        StatementLine {
+            date: NaiveDate::from_ymd_opt(2026, 9, day).unwrap(),
+            description: description.to_string(),
+            amount,
+        }
+    }
+
+    fn entry(day: u32, item: &str, amount: i64) -> Entry {
+        Entry {
+            row: day as i64 + 1,
+            date: NaiveDate::from_ymd_opt(2026, 9, day),
+            item: item.to_string(),
+            amount,
+            category: String::new(),
+            user: "太郎".to_string(),
+            link: String::new(),
+            kind: Kind::Expense,
+            payment: String::new(),
+            payer: String::new(),
+            receipt: String::new(),
+            patient: String::new(),
+            provider: String::new(),
+        }
+    }
+
+    #[test]
+    fn parses_shift_jis_card_statement() {
+        let lines = profile().parse(CARD_SAMPLE).unwrap();
+        // 見出しと合計の行は読み飛ばす
+        assert_eq!(
+            lines,
+            vec![
+                line(1, "ライフ　中野店", 2380),
+                line(3, "スターバックス", 550),
+                line(5, "ＡＭＡＺＯＮ．ＣＯ．ＪＰ", 12800),
+                line(7, "ＡＭＡＺＯＮ．ＣＯ．ＪＰ　返品", -1980),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_encoding() {
+        let profile = StatementProfile { encoding: "utf-8".to_string(), ..profile() };
+        assert!(profile.parse(CARD_SAMPLE).is_err());
+        assert!(decode(b"abc", "ebcdic-jp").is_err());
+    }
+
+    #[test]
+    fn uses_date_format_when_given() {
+        let profile = StatementProfile {
+            encoding: "utf-8".to_string(),
+            skip_rows: 0,
+            date_format: Some("%d/%m/%Y".to_string()),
+            ..profile()
+        };
+        let lines = profile.parse("03/09/2026,店,,,,,980\n2026/09/04,店,,,,,120\n".as_bytes()).unwrap();
+        assert_eq!(lines, vec![line(3, "店", 980)]);
+    }
+
+    #[test]
+    fn parses_japanese_dates() {
+        let date = NaiveDate::from_ymd_opt(2026, 9, 3);
+        for s in ["2026/09/03", "2026-9-3", "2026年9月3日", "20260903", "R8.9.3", "令和8年9月3日", " R08/09/03 "] {
+            assert_eq!(parse_japanese_date(s), date, "{}", s);
+        }
+        assert_eq!(parse_japanese_date("令和元年5月1日"), NaiveDate::from_ymd_opt(2019, 5, 1));
+        assert_eq!(parse_japanese_date("H31.4.30"), NaiveDate::from_ymd_opt(2019, 4, 30));
+        assert_eq!(parse_japanese_date("R8.9"), None);
+        assert_eq!(parse_japanese_date("合計"), None);
+    }
+
+    #[test]
+    fn reconciles_by_amount_and_nearest_date() {
+        let lines = profile().parse(CARD_SAMPLE).unwrap();
+        let entries = vec![
+            entry(2, "スーパー", 2380),
+            // 同じ金額なら日付の近いほうと組む
+            entry(1, "コーヒー", 550),
+            entry(4, "コーヒー", 550),
+            // 許容日数を超えてずれている
+            entry(10, "通販", 12800),
+        ];
+        let result = reconcile(lines, entries, profile().date_tolerance_days);
+        let matched: Vec<(&str, i64)> = result.matched.iter().map(|(l, e)| (l.description.as_str(), e.row)).collect();
+        assert_eq!(matched, vec![("ライフ　中野店", 3), ("スターバックス", 5)]);
+        let missing: Vec<i64> = result.missing_in_ledger.iter().map(|l| l.amount).collect();
+        assert_eq!(missing, vec![12800, -1980]);
+        let extra: Vec<i64> = result.missing_in_statement.iter().map(|e| e.row).collect();
+        assert_eq!(extra, vec![2, 11]);
+    }
+}
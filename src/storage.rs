@@ -0,0 +1,76 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// JSON ファイルを読む。まだなければ既定値を返す
+pub fn load_json<T: DeserializeOwned + Default>(path: &Path) -> Result<T, Error> {
+    match std::fs::read(path) {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// JSON ファイルに書く。ディレクトリがなければ作る
+///
+/// 途中で止まっても元のファイルが壊れないよう、隣の一時ファイルに書いてから置き換える
+pub fn save_json<T: Serialize>(path: &Path, value: &T) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    let result = write_synced(&temp, &serde_json::to_vec_pretty(value)?).and_then(|_| std::fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    Ok(result?)
+}
+
+fn write_synced(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let mut file = std::fs::File::create(path)?;
+    file.write_all(bytes)?;
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hiratakebot-storage-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn saves_and_loads_without_leaving_temp_files() {
+        let dir = dir("roundtrip");
+        let path = dir.join("values.json");
+        let missing: BTreeMap<String, u64> = load_json(&path).unwrap();
+        assert!(missing.is_empty());
+
+        let values = BTreeMap::from([("a".to_string(), 1u64)]);
+        save_json(&path, &values).unwrap();
+        save_json(&path, &values).unwrap();
+        let loaded: BTreeMap<String, u64> = load_json(&path).unwrap();
+        assert_eq!(loaded, values);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn keeps_the_old_file_when_replacing_fails() {
+        let dir = dir("failure");
+        // 置き換え先がディレクトリだと rename に失敗する
+        let path = dir.join("values.json");
+        std::fs::create_dir_all(path.join("child")).unwrap();
+        assert!(save_json(&path, &BTreeMap::from([("a".to_string(), 1u64)])).is_err());
+        assert!(path.join("child").is_dir());
+        assert!(!dir.join("values.json.tmp").exists());
+    }
+}
@@ -0,0 +1,152 @@
+use crate::ledger::{total_spending, Entry, Kind, Month};
+use std::collections::HashMap;
+
+/// 1か月分の集計。`/chart` とダッシュボードで同じ計算を使う
+#[derive(Debug, Clone)]
+pub struct MonthSummary {
+    pub month: Month,
+    /// 支出の合計。返金は差し引く
+    pub spending: i64,
+    pub income: i64,
+    pub budget: Option<i64>,
+    /// 分類ごとの支出。多い順
+    pub categories: Vec<(String, i64)>,
+    /// 割り勘の貸し借り。正なら受け取る、負なら支払う
+    pub balances: Vec<(String, i64)>,
+}
+
+impl MonthSummary {
+    pub fn new(month: Month, entries: &[Entry], budget: Option<i64>) -> Self {
+        Self {
+            month,
+            spending: total_spending(entries),
+            income: entries.iter().filter(|e| e.kind == Kind::Income).map(|e| e.amount).sum(),
+            budget,
+            categories: category_totals(entries),
+            balances: balances(entries),
+        }
+    }
+
+    /// 予算のうち使った割合（%）
+    pub fn budget_used(&self) -> Option<i64> {
+        self.budget.filter(|budget| *budget > 0).map(|budget| self.spending * 100 / budget)
+    }
+}
+
+/// 分類ごとの支出。返金は同じ分類から差し引き、合計が正のものだけを多い順に返す
+pub fn category_totals(entries: &[Entry]) -> Vec<(String, i64)> {
+    let mut totals: HashMap<&str, i64> = HashMap::new();
+    for entry in entries.iter().filter(|e| e.is_expense()) {
+        *totals.entry(entry.category_or_item()).or_default() += entry.amount;
+    }
+    let mut totals: Vec<(String, i64)> = totals
+        .into_iter()
+        .filter(|(_, amount)| *amount > 0)
+        .map(|(category, amount)| (category.to_string(), amount))
+        .collect();
+    totals.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    totals
+}
+
+/// 立て替えた人は負担する人の分を受け取る。貸し借りのない人は含めない
+pub fn balances(entries: &[Entry]) -> Vec<(String, i64)> {
+    let mut balances: HashMap<&str, i64> = HashMap::new();
+    for entry in entries.iter().filter(|e| e.is_expense() && e.payer() != e.user) {
+        *balances.entry(entry.payer()).or_default() += entry.amount;
+        *balances.entry(&entry.user).or_default() -= entry.amount;
+    }
+    let mut balances: Vec<(String, i64)> = balances
+        .into_iter()
+        .filter(|(_, amount)| *amount != 0)
+        .map(|(user, amount)| (user.to_string(), amount))
+        .collect();
+    balances.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    balances
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(kind: Kind, category: &str, amount: i64, user: &str, payer: &str) -> Entry {
+        Entry {
+            row: 0,
+            date: None,
+            item: "品目".to_string(),
+            amount,
+            category: category.to_string(),
+            user: user.to_string(),
+            link: String::new(),
+            kind,
+            payment: String::new(),
+            payer: payer.to_string(),
+            receipt: String::new(),
+            patient: String::new(),
+            provider: String::new(),
+        }
+    }
+
+    fn pairs(values: &[(&str, i64)]) -> Vec<(String, i64)> {
+        values.iter().map(|(name, amount)| (name.to_string(), *amount)).collect()
+    }
+
+    #[test]
+    fn payer_receives_and_recorder_pays() {
+        let entries = vec![
+            // 花子が立て替えた太郎の分
+            entry(Kind::Expense, "食費", 3000, "太郎", "花子"),
+            // 自分で払った分は貸し借りにならない
+            entry(Kind::Expense, "食費", 1000, "太郎", ""),
+            entry(Kind::Expense, "食費", 500, "太郎", "太郎"),
+            // 収入や振替は割り勘にしない
+            entry(Kind::Income, "給与", 280000, "太郎", "花子"),
+            entry(Kind::Transfer, "", 10000, "太郎", "花子"),
+        ];
+        assert_eq!(balances(&entries), pairs(&[("花子", 3000), ("太郎", -3000)]));
+    }
+
+    #[test]
+    fn refunds_reduce_balances() {
+        let entries = vec![
+            entry(Kind::Expense, "日用品", 2000, "太郎", "花子"),
+            // 立て替えた買い物の返金は立て替えた人に戻る
+            entry(Kind::Expense, "日用品", -500, "太郎", "花子"),
+            entry(Kind::Expense, "外食", 1200, "花子", "太郎"),
+            // 貸し借りが打ち消し合った人は含めない
+            entry(Kind::Expense, "外食", 800, "次郎", "三郎"),
+            entry(Kind::Expense, "外食", -800, "次郎", "三郎"),
+        ];
+        assert_eq!(balances(&entries), pairs(&[("花子", 300), ("太郎", -300)]));
+    }
+
+    #[test]
+    fn summarizes_month() {
+        let month = Month::new(2026, 9).unwrap();
+        let entries = vec![
+            entry(Kind::Expense, "食費", 3000, "太郎", "花子"),
+            entry(Kind::Expense, "食費", 2000, "太郎", ""),
+            entry(Kind::Expense, "日用品", 1500, "花子", ""),
+            // 返金は同じ分類の支出から差し引く
+            entry(Kind::Expense, "日用品", -1500, "花子", ""),
+            entry(Kind::Expense, "", 700, "花子", ""),
+            entry(Kind::Income, "給与", 280000, "太郎", ""),
+            entry(Kind::Transfer, "", 10000, "太郎", ""),
+        ];
+        let summary = MonthSummary::new(month, &entries, Some(10000));
+        assert_eq!(summary.month, month);
+        assert_eq!(summary.spending, 5700);
+        assert_eq!(summary.income, 280000);
+        assert_eq!(summary.budget_used(), Some(57));
+        // 分類のない行は品目で分ける。合計が 0 の分類は出さない
+        assert_eq!(summary.categories, pairs(&[("食費", 5000), ("品目", 700)]));
+        assert_eq!(summary.balances, pairs(&[("花子", 3000), ("太郎", -3000)]));
+    }
+
+    #[test]
+    fn budget_used_needs_positive_budget() {
+        let month = Month::new(2026, 9).unwrap();
+        assert_eq!(MonthSummary::new(month, &[], None).budget_used(), None);
+        assert_eq!(MonthSummary::new(month, &[], Some(0)).budget_used(), None);
+        assert_eq!(MonthSummary::new(month, &[], Some(10000)).budget_used(), Some(0));
+    }
+}
@@ -0,0 +1,72 @@
+use axum::extract::{MatchedPath, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use metrics::{counter, describe_counter, describe_histogram, histogram, Unit};
+use metrics_exporter_prometheus::{BuildError, Matcher, PrometheusBuilder, PrometheusHandle};
+use std::time::{Duration, Instant};
+
+/// 所要時間のヒストグラムの区切り（秒）
+const DURATION_BUCKETS: &[f64] = &[0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// Prometheus 形式で集計を始める。`/metrics` ではこのハンドルで書き出す
+pub fn install() -> Result<PrometheusHandle, BuildError> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), DURATION_BUCKETS)?
+        .install_recorder()?;
+
+    describe_counter!("discord_messages_total", "Discord の発言を処理した数。outcome は recorded、rejected、ignored");
+    describe_counter!("expenses_parsed_total", "記録用の文として読めた数");
+    describe_counter!("expenses_rejected_total", "記録しなかった数。reason は理由");
+    describe_counter!("sheets_requests_total", "Google Sheets API を呼んだ数。status は HTTP のステータスか error");
+    describe_histogram!("sheets_request_duration_seconds", Unit::Seconds, "Google Sheets API の応答までの時間");
+    describe_counter!("sheets_token_refreshes_total", "アクセストークンを取得した数。result は ok か error");
+    describe_counter!("http_requests_total", "HTTP API のリクエスト数");
+    describe_histogram!("http_request_duration_seconds", Unit::Seconds, "HTTP API の応答までの時間");
+    describe_counter!("discord_gateway_reconnects_total", "Discord のゲートウェイにつなぎ直した数");
+    Ok(handle)
+}
+
+/// `outcome`: 記録した（recorded）、断った（rejected）、記録用のチャンネルではない（ignored）
+pub fn message_handled(outcome: &'static str) {
+    counter!("discord_messages_total", "outcome" => outcome).increment(1);
+}
+
+pub fn expense_parsed() {
+    counter!("expenses_parsed_total").increment(1);
+}
+
+/// `reason`: parse（金額などが読めない）、unknown_tag（知らない @）、permission（権限がない）、sheets（書き込みに失敗）
+pub fn expense_rejected(reason: &'static str) {
+    counter!("expenses_rejected_total", "reason" => reason).increment(1);
+}
+
+/// `status` は HTTP のステータス。送れなかったときは `None`
+pub fn sheets_request(status: Option<u16>, elapsed: Duration) {
+    let status = status.map_or_else(|| "error".to_string(), |s| s.to_string());
+    counter!("sheets_requests_total", "status" => status).increment(1);
+    histogram!("sheets_request_duration_seconds").record(elapsed.as_secs_f64());
+}
+
+pub fn token_refreshed(ok: bool) {
+    counter!("sheets_token_refreshes_total", "result" => if ok { "ok" } else { "error" }).increment(1);
+}
+
+pub fn gateway_reconnected(shard_id: u32) {
+    counter!("discord_gateway_reconnects_total", "shard" => shard_id.to_string()).increment(1);
+}
+
+/// HTTP API のリクエストを数える。パスはルートの形（`/expenses/{id}`）で数え、値ごとには分けない
+pub async fn track_http(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| "unmatched".to_string(), |p| p.as_str().to_string());
+    let started = Instant::now();
+    let response = next.run(request).await;
+
+    let status = response.status().as_u16().to_string();
+    counter!("http_requests_total", "method" => method.clone(), "path" => path.clone(), "status" => status).increment(1);
+    histogram!("http_request_duration_seconds", "method" => method, "path" => path).record(started.elapsed().as_secs_f64());
+    response
+}
@@ -0,0 +1,245 @@
+use crate::auth::Principal;
+use crate::http_server::{ApiError, AppState};
+use crate::ledger::{self, format_yen, Month};
+use crate::pending::PendingExpense;
+use axum::extract::{Extension, Json, Path, State};
+use axum::http::StatusCode;
+use axum::routing::post;
+use axum::Router;
+use chrono::{DateTime, NaiveDate, Utc};
+use poise::serenity_prelude::{ChannelId, CreateMessage};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use tracing::error;
+
+/// 決済の通知を送ってくるところ（カード会社、PayPay など）ごとの読み取り方
+///
+/// セレクターは `$.data.amount` や `$.items[0].name` のように書く
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookSource {
+    /// 確認を求めるチャンネル。このチャンネルの家計簿に記録する
+    pub channel_id: u64,
+    pub amount: String,
+    /// 店名。品目として記録する
+    pub merchant: String,
+    /// 省略すると確認した日
+    #[serde(default)]
+    pub date: Option<String>,
+    /// 支払い方法として記録する名前（`PayPay` など）
+    #[serde(default)]
+    pub payment: Option<String>,
+    #[serde(default)]
+    pub category: Option<String>,
+    /// セレクターと値の組。全て一致した通知だけを記録する（`{"$.type": "payment"}` など）
+    #[serde(default, rename = "match")]
+    pub conditions: BTreeMap<String, String>,
+}
+
+impl WebhookSource {
+    fn matches(&self, payload: &Value) -> bool {
+        self.conditions
+            .iter()
+            .all(|(selector, expected)| select(payload, selector).is_some_and(|v| ledger::cell_to_string(v) == *expected))
+    }
+
+    /// 通知から明細の元になる値を取り出す
+    fn extract(&self, payload: &Value) -> Result<(Option<NaiveDate>, String, i64), String> {
+        let text = |selector: &str| {
+            select(payload, selector)
+                .map(ledger::cell_to_string)
+                .filter(|s| !s.is_empty())
+                .ok_or_else(|| format!("{} が見つかりません", selector))
+        };
+
+        let amount = text(&self.amount)?;
+        let amount = ledger::parse_amount(&amount).ok_or_else(|| format!("{} の「{}」は金額として読めません", self.amount, amount))?;
+        let merchant = text(&self.merchant)?;
+        let date = match &self.date {
+            Some(selector) => {
+                let date = text(selector)?;
+                Some(parse_date(&date).ok_or_else(|| format!("{} の「{}」は日付として読めません", selector, date))?)
+            }
+            None => None,
+        };
+        Ok((date, merchant, amount))
+    }
+}
+
+/// `$.a.b[0].c` の形のセレクターで値を取り出す。先頭の `$` は省略できる
+pub fn select<'a>(value: &'a Value, selector: &str) -> Option<&'a Value> {
+    let mut current = value;
+    for segment in selector.trim().trim_start_matches('$').split('.').filter(|s| !s.is_empty()) {
+        let (key, indexes) = segment.split_at(segment.find('[').unwrap_or(segment.len()));
+        if !key.is_empty() {
+            current = current.get(key)?;
+        }
+        for index in indexes.split('[').filter(|s| !s.is_empty()) {
+            let index = index.strip_suffix(']')?;
+            current = match index.parse::<usize>() {
+                Ok(i) => current.get(i)?,
+                Err(_) => current.get(index.trim_matches(['\'', '"']))?,
+            };
+        }
+    }
+    Some(current)
+}
+
+/// ISO 8601 の日時か、シートと同じ表記の日付を読む
+fn parse_date(s: &str) -> Option<NaiveDate> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Some(time.date_naive());
+    }
+    ledger::parse_date(s, Month::of(Utc::now().date_naive()))
+}
+
+#[derive(Serialize)]
+struct Received {
+    /// `pending` なら確認待ち、`ignored` なら条件に合わず記録しない
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+}
+
+async fn receive(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Path(source_name): Path<String>,
+    Json(payload): Json<Value>,
+) -> Result<(StatusCode, Json<Received>), ApiError> {
+    let source = state
+        .config
+        .webhook_sources
+        .get(&source_name)
+        .ok_or_else(|| ApiError::not_found(format!("{} の読み取り方が WEBHOOK_SOURCES にありません", source_name)))?;
+    let channel_id = ChannelId::new(source.channel_id);
+    if !principal.scopes.allows_channel(channel_id.get()) {
+        return Err(ApiError::forbidden(format!("このキーでは <#{}> に記録できません", channel_id)));
+    }
+    if !source.matches(&payload) {
+        return Ok((StatusCode::OK, Json(Received { status: "ignored", id: None })));
+    }
+    let (date, merchant, amount) = source
+        .extract(&payload)
+        .map_err(|message| ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "unprocessable", message))?;
+
+    let expense = PendingExpense {
+        id: 0,
+        source: source_name.clone(),
+        channel_id: channel_id.get(),
+        date,
+        merchant,
+        amount,
+        payment: source.payment.clone().unwrap_or_default(),
+        category: source.category.clone().unwrap_or_default(),
+        received_at: Utc::now(),
+        received_by: principal.name.clone(),
+    };
+    let actor = principal.actor(None);
+    let id = state.pending.add(expense.clone(), &actor).map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "internal", e.to_string()))?;
+
+    let date = expense.date.map(|d| d.format("%Y/%m/%d ").to_string()).unwrap_or_default();
+    let content = format!(
+        "{} の通知: {}{} {}\n記録するなら「記録する」を押してください",
+        source_name,
+        date,
+        expense.merchant,
+        format_yen(expense.amount)
+    );
+    let prompt = CreateMessage::new().content(content).components(PendingExpense::buttons(id));
+    if let Err(e) = channel_id.send_message(&state.http, prompt).await {
+        error!("Error asking to confirm a webhook: {:?}", e);
+        let _ = state.pending.take(id, "pending.cancel", &actor);
+        return Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "discord_error", format!("確認を求められませんでした: {}", e)));
+    }
+    Ok((StatusCode::ACCEPTED, Json(Received { status: "pending", id: Some(id) })))
+}
+
+/// `/webhooks` 以下のルート。認証は呼び出し側で付ける
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/webhooks/{source}", post(receive))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn payload() -> Value {
+        json!({
+            "type": "payment",
+            "data": {
+                "amount": "¥1,200",
+                "merchant": { "name": "コンビニ" },
+                "paid_at": "2026-09-01T12:34:56+09:00",
+                "items": [{ "name": "おにぎり" }, { "name": "お茶" }],
+                "meta": { "store-id": 42 }
+            }
+        })
+    }
+
+    fn source() -> WebhookSource {
+        serde_json::from_value(json!({
+            "channel_id": 1,
+            "amount": "$.data.amount",
+            "merchant": "$.data.merchant.name",
+            "date": "$.data.paid_at",
+            "match": { "$.type": "payment" }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn selects_nested_keys_and_indexes() {
+        let payload = payload();
+        assert_eq!(select(&payload, "$.data.merchant.name"), Some(&json!("コンビニ")));
+        assert_eq!(select(&payload, "data.merchant.name"), Some(&json!("コンビニ")));
+        assert_eq!(select(&payload, "$.data.items[1].name"), Some(&json!("お茶")));
+        assert_eq!(select(&payload, "$.data.meta['store-id']"), Some(&json!(42)));
+        assert_eq!(select(&payload, "$.data['items'][0]['name']"), Some(&json!("おにぎり")));
+        assert_eq!(select(&payload, "$"), Some(&payload));
+    }
+
+    #[test]
+    fn missing_paths_select_nothing() {
+        let payload = payload();
+        assert_eq!(select(&payload, "$.data.total"), None);
+        assert_eq!(select(&payload, "$.data.items[5].name"), None);
+        assert_eq!(select(&payload, "$.data.items[0"), None);
+        assert_eq!(select(&payload, "$.type.name"), None);
+    }
+
+    #[test]
+    fn matches_only_when_every_condition_holds() {
+        let source = source();
+        assert!(source.matches(&payload()));
+        assert!(!source.matches(&json!({ "type": "refund", "data": {} })));
+        assert!(!source.matches(&json!({ "data": {} })));
+        let numbers = WebhookSource { conditions: BTreeMap::from([("$.data.meta['store-id']".to_string(), "42".to_string())]), ..source };
+        assert!(numbers.matches(&payload()));
+    }
+
+    #[test]
+    fn extracts_amount_merchant_and_date() {
+        let (date, merchant, amount) = source().extract(&payload()).unwrap();
+        assert_eq!(date, NaiveDate::from_ymd_opt(2026, 9, 1));
+        assert_eq!((merchant.as_str(), amount), ("コンビニ", 1200));
+        let undated = WebhookSource { date: None, ..source() };
+        assert_eq!(undated.extract(&payload()).unwrap().0, None);
+    }
+
+    #[test]
+    fn reports_unreadable_values() {
+        let mut payload = payload();
+        payload["data"]["amount"] = json!("無料");
+        assert!(source().extract(&payload).unwrap_err().contains("金額として読めません"));
+
+        let mut payload = self::payload();
+        payload["data"]["paid_at"] = json!("先週");
+        assert!(source().extract(&payload).unwrap_err().contains("日付として読めません"));
+
+        let mut payload = self::payload();
+        payload["data"]["merchant"]["name"] = json!("");
+        assert!(source().extract(&payload).unwrap_err().contains("見つかりません"));
+    }
+}